@@ -0,0 +1,63 @@
+//! Compares PTY read throughput for different reader buffer sizes, so the
+//! `create_pty` default can be revisited. Run with `cargo bench --bench read_buffer`.
+
+#[cfg(unix)]
+fn run(buffer_size: usize, payload: usize) {
+    use portable_pty::{native_pty_system, CommandBuilder, PtySize};
+    use std::io::Read;
+    use std::time::Instant;
+
+    let pair = native_pty_system()
+        .openpty(PtySize {
+            rows: 24,
+            cols: 80,
+            pixel_width: 0,
+            pixel_height: 0,
+        })
+        .expect("openpty failed");
+
+    let mut cmd = CommandBuilder::new("head");
+    cmd.args(["-c", &payload.to_string(), "/dev/zero"]);
+    let mut child = pair.slave.spawn_command(cmd).expect("spawn failed");
+    drop(pair.slave);
+
+    let mut reader = pair.master.try_clone_reader().expect("reader failed");
+    let mut buf = vec![0u8; buffer_size];
+    let mut total = 0usize;
+    let mut reads = 0usize;
+
+    let start = Instant::now();
+    loop {
+        match reader.read(&mut buf) {
+            Ok(0) | Err(_) => break,
+            Ok(n) => {
+                total += n;
+                reads += 1;
+            }
+        }
+    }
+    let elapsed = start.elapsed();
+    let _ = child.wait();
+
+    let mb = total as f64 / (1024.0 * 1024.0);
+    println!(
+        "{:>6} KB buffer: {:>8.1} MB/s, {:>7} reads, {:>6} avg bytes/read",
+        buffer_size / 1024,
+        mb / elapsed.as_secs_f64(),
+        reads,
+        total / reads.max(1),
+    );
+}
+
+#[cfg(unix)]
+fn main() {
+    const PAYLOAD: usize = 64 * 1024 * 1024;
+    for size in [4 * 1024, 64 * 1024] {
+        run(size, PAYLOAD);
+    }
+}
+
+#[cfg(not(unix))]
+fn main() {
+    println!("read_buffer benchmark requires a unix PTY");
+}
@@ -0,0 +1,31 @@
+# nanoprompt shell integration for bash: reports the working directory
+# (OSC 7) and prompt/command boundaries (OSC 133).
+if [[ -n "$NANOPROMPT_SESSION" && -z "$__nanoprompt_integrated" ]]; then
+  __nanoprompt_integrated=1
+  __nanoprompt_at_prompt=
+  __nanoprompt_running=
+
+  __nanoprompt_prompt() {
+    if [[ -n "$__nanoprompt_running" ]]; then
+      printf '\e]133;D;%s\a' "$__nanoprompt_status"
+      __nanoprompt_running=
+    fi
+    printf '\e]7;file://%s%s\a' "$HOSTNAME" "$PWD"
+    printf '\e]133;A\a'
+    __nanoprompt_at_prompt=1
+  }
+
+  # The DEBUG trap fires before every simple command; only the first one
+  # after the prompt is the user's command line. PROMPT_COMMAND starts by
+  # clearing the flag so an empty line doesn't count as a command.
+  __nanoprompt_preexec() {
+    [[ -z "$__nanoprompt_at_prompt" || -n "$COMP_LINE" ]] && return
+    [[ "$BASH_COMMAND" == __nanoprompt_status=* ]] && return
+    __nanoprompt_at_prompt=
+    __nanoprompt_running=1
+    printf '\e]133;C\a'
+  }
+
+  trap '__nanoprompt_preexec' DEBUG
+  PROMPT_COMMAND="__nanoprompt_status=\$? __nanoprompt_at_prompt=${PROMPT_COMMAND:+;$PROMPT_COMMAND};__nanoprompt_prompt"
+fi
@@ -0,0 +1,18 @@
+# nanoprompt shell integration for fish: reports the working directory
+# (OSC 7) and prompt/command boundaries (OSC 133).
+if set -q NANOPROMPT_SESSION; and not set -q __nanoprompt_integrated
+    set -g __nanoprompt_integrated 1
+
+    function __nanoprompt_preexec --on-event fish_preexec
+        printf '\e]133;C\a'
+    end
+
+    function __nanoprompt_postexec --on-event fish_postexec
+        printf '\e]133;D;%s\a' $status
+    end
+
+    function __nanoprompt_prompt --on-event fish_prompt
+        printf '\e]7;file://%s%s\a' (hostname) $PWD
+        printf '\e]133;A\a'
+    end
+end
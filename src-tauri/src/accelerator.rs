@@ -0,0 +1,115 @@
+//! Menu accelerators, and just enough parsing to compare two of them.
+
+/// Our own menu items by id. `run` builds the menu from these.
+pub const MENU: [(&str, &str); 5] = [
+    ("settings", "CmdOrCtrl+,"),
+    ("quit", "CmdOrCtrl+Q"),
+    ("new_tab", "CmdOrCtrl+T"),
+    ("close_tab", "CmdOrCtrl+W"),
+    ("close_window", "CmdOrCtrl+Shift+W"),
+];
+
+/// What the OS binds the predefined items (undo, copy, ...) to. Hide and
+/// hide others only have shortcuts on macOS.
+const PREDEFINED: &[(&str, &str)] = &[
+    ("undo", "CmdOrCtrl+Z"),
+    ("redo", "CmdOrCtrl+Shift+Z"),
+    ("cut", "CmdOrCtrl+X"),
+    ("copy", "CmdOrCtrl+C"),
+    ("paste", "CmdOrCtrl+V"),
+    ("select_all", "CmdOrCtrl+A"),
+    ("minimize", "CmdOrCtrl+M"),
+    #[cfg(target_os = "macos")]
+    ("hide", "Cmd+H"),
+    #[cfg(target_os = "macos")]
+    ("hide_others", "Cmd+Alt+H"),
+    ("maximize", ""),
+    ("show_all", ""),
+];
+
+/// Accelerator for menu item `id`.
+pub fn for_item(id: &str) -> &'static str {
+    MENU.iter()
+        .find(|(item, _)| *item == id)
+        .map(|(_, accelerator)| *accelerator)
+        .unwrap_or_default()
+}
+
+const SHIFT: u8 = 1;
+const CTRL: u8 = 2;
+const ALT: u8 = 4;
+const SUPER: u8 = 8;
+
+/// Modifier bits and upper-cased key, with `CmdOrCtrl` resolved for this OS.
+#[derive(PartialEq)]
+struct Parsed {
+    modifiers: u8,
+    key: String,
+}
+
+fn parse(accelerator: &str) -> Result<Parsed, String> {
+    let invalid = || format!("Invalid accelerator {:?}", accelerator);
+    let mut parts: Vec<&str> = accelerator.split('+').map(str::trim).collect();
+    // "Ctrl++" binds the plus key
+    if accelerator.ends_with("++") {
+        parts.truncate(parts.len() - 2);
+        parts.push("+");
+    }
+    let (key, modifiers) = parts.split_last().ok_or_else(invalid)?;
+    if key.is_empty() {
+        return Err(invalid());
+    }
+    let mut bits = 0;
+    for modifier in modifiers {
+        bits |= match modifier.to_ascii_lowercase().as_str() {
+            "shift" => SHIFT,
+            "ctrl" | "control" => CTRL,
+            "alt" | "option" => ALT,
+            "cmd" | "command" | "super" | "meta" => SUPER,
+            "cmdorctrl" | "commandorcontrol" => {
+                if cfg!(target_os = "macos") {
+                    SUPER
+                } else {
+                    CTRL
+                }
+            }
+            _ => return Err(invalid()),
+        };
+    }
+    Ok(Parsed {
+        modifiers: bits,
+        key: key.to_ascii_uppercase(),
+    })
+}
+
+/// Ids of menu items (ours and the predefined ones) already bound to
+/// `accelerator`. Errors if it doesn't parse.
+pub fn conflicts(accelerator: &str) -> Result<Vec<String>, String> {
+    let wanted = parse(accelerator)?;
+    Ok(MENU
+        .iter()
+        .chain(PREDEFINED.iter())
+        .filter(|(_, bound)| !bound.is_empty() && parse(bound).is_ok_and(|b| b == wanted))
+        .map(|(id, _)| id.to_string())
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_collisions() {
+        assert_eq!(conflicts("CmdOrCtrl+C").unwrap(), ["copy"]);
+        assert_eq!(conflicts("cmdorctrl + shift + w").unwrap(), ["close_window"]);
+        assert!(conflicts("CmdOrCtrl+Shift+K").unwrap().is_empty());
+        assert_eq!(conflicts("Cmd+H").unwrap().len(), usize::from(cfg!(target_os = "macos")));
+    }
+
+    #[test]
+    fn rejects_unparseable_accelerators() {
+        assert!(conflicts("Hyper+K").is_err());
+        assert!(conflicts("Ctrl+").is_err());
+        assert!(conflicts("").is_err());
+    }
+}
@@ -0,0 +1,114 @@
+//! Command output blocks from OSC 133 shell integration marks: a command's
+//! output runs from `C` (command started) to `D` (finished, with its exit
+//! status). Blocks are kept as offsets into the session's whole output
+//! stream and mapped onto the scrollback buffer when listed.
+
+use crate::parser::Sequence;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::ops::Range;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+/// Blocks kept per session; the oldest are dropped first
+const MAX_BLOCKS: usize = 1000;
+
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CommandBlock {
+    pub start: u64,
+    /// `None` while the command is still running
+    pub end: Option<u64>,
+    /// `None` until it finishes, or if the shell didn't report one
+    pub exit_code: Option<i32>,
+}
+
+#[derive(Default)]
+pub struct CommandBlocks {
+    blocks: Mutex<VecDeque<CommandBlock>>,
+    /// Stream offset of the end of the scrollback buffer, kept by the
+    /// reader under the scrollback lock
+    pub scrollback_end: AtomicU64,
+}
+
+impl CommandBlocks {
+    /// Update from `seq`, found at `span` in the stream.
+    pub fn apply(&self, seq: &Sequence, span: Range<u64>) {
+        let Sequence::Osc(payload) = *seq else { return };
+        let Some(mark) = payload.strip_prefix(b"133;") else { return };
+        let Ok(mut blocks) = self.blocks.lock() else { return };
+        match mark.first() {
+            Some(b'C') => {
+                // A command that never reported D ends where the next starts
+                if let Some(open) = blocks.back_mut().filter(|b| b.end.is_none()) {
+                    open.end = Some(span.start);
+                }
+                if blocks.len() == MAX_BLOCKS {
+                    blocks.pop_front();
+                }
+                blocks.push_back(CommandBlock {
+                    start: span.end,
+                    end: None,
+                    exit_code: None,
+                });
+            }
+            Some(b'D') => {
+                if let Some(open) = blocks.back_mut().filter(|b| b.end.is_none()) {
+                    open.end = Some(span.start);
+                    // `D;<status>`, possibly followed by more `;` fields
+                    open.exit_code = mark
+                        .get(2..)
+                        .and_then(|rest| std::str::from_utf8(rest).ok())
+                        .and_then(|rest| rest.split(';').next()?.parse().ok());
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Blocks still (at least partly) in a scrollback buffer of `len` bytes,
+    /// with offsets into it. A block that started before the buffer does
+    /// starts at 0.
+    pub fn list(&self, len: usize) -> Vec<CommandBlock> {
+        let base = self.scrollback_end.load(Ordering::Relaxed).saturating_sub(len as u64);
+        let Ok(blocks) = self.blocks.lock() else { return Vec::new() };
+        blocks
+            .iter()
+            .filter(|b| b.end.is_none_or(|end| end > base))
+            .map(|b| CommandBlock {
+                start: b.start.saturating_sub(base),
+                end: b.end.map(|end| end - base),
+                exit_code: b.exit_code,
+            })
+            .collect()
+    }
+
+    pub fn clear(&self) {
+        if let Ok(mut blocks) = self.blocks.lock() {
+            blocks.clear();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+
+    #[test]
+    fn osc133_cycles_become_blocks() {
+        let stream: &[u8] = b"\x1b]133;A\x07$ ls\n\x1b]133;C\x07one\n\x1b]133;D;0\x07\
+                              \x1b]133;A\x07$ false\n\x1b]133;C\x07two\n\x1b]133;D;1\x07";
+        let (mut parser, blocks) = (Parser::default(), CommandBlocks::default());
+        parser.feed(stream, |seq, span| blocks.apply(&seq, span));
+        blocks.scrollback_end.store(parser.offset(), Ordering::Relaxed);
+
+        let list = blocks.list(stream.len());
+        assert_eq!(list.len(), 2);
+        let output = |b: &CommandBlock| &stream[b.start as usize..b.end.unwrap() as usize];
+        assert_eq!(output(&list[0]), b"one\n");
+        assert_eq!(list[0].exit_code, Some(0));
+        assert_eq!(output(&list[1]), b"two\n");
+        assert_eq!(list[1].exit_code, Some(1));
+    }
+}
@@ -0,0 +1,453 @@
+//! Backend settings persisted as `config.json` in the app config dir.
+//!
+//! The frontend keeps its own display settings in localStorage; this file
+//! holds what the backend needs to know when spawning sessions.
+
+use crate::theme::Theme;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tauri::{AppHandle, Manager};
+
+/// Serializes read-modify-write cycles across concurrent commands.
+static WRITE_LOCK: Mutex<()> = Mutex::new(());
+
+/// A named spawn configuration.
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct Profile {
+    pub name: String,
+    /// Program to run. `None` uses the user's default shell.
+    pub program: Option<String>,
+    pub args: Vec<String>,
+    pub cwd: Option<String>,
+    pub env: HashMap<String, String>,
+    /// Start the program as a login shell
+    pub login: bool,
+    /// Overrides TERM (default `xterm-256color`)
+    pub term: Option<String>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct Config {
+    pub profiles: Vec<Profile>,
+    /// Native menu bar shown on Linux/Windows (macOS always shows it)
+    pub menu_visible: bool,
+    /// Profile new tabs use when their window has none of its own
+    pub default_profile: Option<String>,
+    /// Profile name per window label
+    pub window_profiles: HashMap<String, String>,
+    /// Where sessions without their own cwd start (`~` allowed)
+    pub default_cwd: Option<String>,
+    /// Environment for every new session. Precedence, highest first:
+    /// session env > profile env > this > what the app inherited.
+    pub global_env: HashMap<String, String>,
+    /// Most sessions running at once; more fail to spawn (0 = unlimited)
+    pub max_sessions: usize,
+    /// Size at which a `NANOPROMPT_PTY_LOG` file rotates (0 = never)
+    pub pty_log_max_bytes: u64,
+    /// Case-insensitive substrings marking a password prompt, for sessions
+    /// spawned with `detect_password_prompts`
+    pub password_prompts: Vec<String>,
+    /// User themes; a name matching a built-in replaces it
+    pub themes: Vec<Theme>,
+    pub active_theme: Option<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            profiles: Vec::new(),
+            menu_visible: true,
+            default_profile: None,
+            window_profiles: HashMap::new(),
+            default_cwd: None,
+            global_env: HashMap::new(),
+            max_sessions: 128,
+            pty_log_max_bytes: 16 * 1024 * 1024,
+            password_prompts: crate::password::default_patterns(),
+            themes: Vec::new(),
+            active_theme: None,
+        }
+    }
+}
+
+impl Config {
+    /// The profile a new tab in window `label` should use: the window's own,
+    /// then the global default. Names that no longer exist are skipped.
+    pub fn profile_for_window(&self, label: &str) -> Option<&Profile> {
+        let find = |name: &String| self.profiles.iter().find(|p| &p.name == name);
+        self.window_profiles
+            .get(label)
+            .and_then(find)
+            .or_else(|| self.default_profile.as_ref().and_then(find))
+    }
+}
+
+impl Config {
+    /// Fold `other` in: profiles and themes by name and window profiles by
+    /// label, with `other` winning; its other settings replace ours where set.
+    pub fn merge(&mut self, other: Config) {
+        for profile in other.profiles {
+            match self.profiles.iter_mut().find(|p| p.name == profile.name) {
+                Some(existing) => *existing = profile,
+                None => self.profiles.push(profile),
+            }
+        }
+        for theme in other.themes {
+            match self.themes.iter_mut().find(|t| t.name == theme.name) {
+                Some(existing) => *existing = theme,
+                None => self.themes.push(theme),
+            }
+        }
+        self.window_profiles.extend(other.window_profiles);
+        self.global_env.extend(other.global_env);
+        self.menu_visible = other.menu_visible;
+        self.max_sessions = other.max_sessions;
+        self.pty_log_max_bytes = other.pty_log_max_bytes;
+        self.password_prompts = other.password_prompts;
+        self.default_profile = other.default_profile.or(self.default_profile.take());
+        self.default_cwd = other.default_cwd.or(self.default_cwd.take());
+        self.active_theme = other.active_theme.or(self.active_theme.take());
+    }
+
+    /// Settings that parse but don't make sense: blank or duplicate profile
+    /// names, references to profiles or themes that don't exist, bad colors.
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        let mut names = std::collections::HashSet::new();
+        for profile in &self.profiles {
+            if profile.name.trim().is_empty() {
+                problems.push("A profile has no name".to_string());
+            } else if !names.insert(profile.name.as_str()) {
+                problems.push(format!("Profile {:?} is defined more than once", profile.name));
+            }
+        }
+        let mut referenced: Vec<(String, &String)> = self
+            .window_profiles
+            .iter()
+            .map(|(label, name)| (format!("windowProfiles.{}", label), name))
+            .collect();
+        referenced.sort();
+        if let Some(name) = &self.default_profile {
+            referenced.insert(0, ("defaultProfile".to_string(), name));
+        }
+        for (field, name) in referenced {
+            if !names.contains(name.as_str()) {
+                problems.push(format!("{} refers to missing profile {:?}", field, name));
+            }
+        }
+        for theme in &self.themes {
+            if let Err(e) = theme.validate() {
+                problems.push(format!("Theme {:?}: {}", theme.name, e));
+            }
+        }
+        if let Some(name) = &self.active_theme
+            && !self.all_themes().iter().any(|t| &t.name == name)
+        {
+            problems.push(format!("activeTheme refers to missing theme {:?}", name));
+        }
+        problems
+    }
+
+    /// Insert `profile`, replacing any with the same name.
+    pub fn put_profile(&mut self, profile: Profile) {
+        match self.profiles.iter_mut().find(|p| p.name == profile.name) {
+            Some(existing) => *existing = profile,
+            None => self.profiles.push(profile),
+        }
+    }
+
+    /// Insert `theme`, replacing any user theme with the same name.
+    pub fn put_theme(&mut self, theme: Theme) {
+        match self.themes.iter_mut().find(|t| t.name == theme.name) {
+            Some(existing) => *existing = theme,
+            None => self.themes.push(theme),
+        }
+    }
+
+    /// Built-in themes overlaid with the user's, built-ins first.
+    pub fn all_themes(&self) -> Vec<Theme> {
+        let mut themes = crate::theme::builtin();
+        for theme in &self.themes {
+            match themes.iter_mut().find(|t| t.name == theme.name) {
+                Some(existing) => *existing = theme.clone(),
+                None => themes.push(theme.clone()),
+            }
+        }
+        themes
+    }
+}
+
+/// Check config JSON without applying it, collecting every problem found:
+/// unknown fields (which serde would silently drop), type errors, and what
+/// `Config::problems` reports.
+pub fn validate(json: &str) -> Result<(), Vec<String>> {
+    let value: serde_json::Value = serde_json::from_str(json).map_err(|e| vec![e.to_string()])?;
+    let mut problems = Vec::new();
+    let Some(fields) = value.as_object() else {
+        return Err(vec!["Config must be a JSON object".to_string()]);
+    };
+    unknown_fields(fields, &Config::default(), "", &mut problems);
+    if let Some(profiles) = fields.get("profiles").and_then(|p| p.as_array()) {
+        for (i, profile) in profiles.iter().enumerate() {
+            if let Some(profile) = profile.as_object() {
+                unknown_fields(profile, &Profile::default(), &format!("profiles[{}].", i), &mut problems);
+            }
+        }
+    }
+    match serde_json::from_value::<Config>(value) {
+        Ok(config) => problems.extend(config.problems()),
+        Err(e) => problems.push(e.to_string()),
+    }
+    if problems.is_empty() { Ok(()) } else { Err(problems) }
+}
+
+/// Report keys of `fields` that `known` doesn't serialize.
+fn unknown_fields(
+    fields: &serde_json::Map<String, serde_json::Value>,
+    known: &impl Serialize,
+    prefix: &str,
+    problems: &mut Vec<String>,
+) {
+    let Ok(serde_json::Value::Object(known)) = serde_json::to_value(known) else { return };
+    for key in fields.keys().filter(|k| !known.contains_key(*k)) {
+        problems.push(format!("Unknown field {}{}", prefix, key));
+    }
+}
+
+/// Bumped whenever a bundle written by this version can't be read by an
+/// older one.
+pub const BUNDLE_VERSION: u32 = 1;
+
+/// Everything needed to move settings to another machine, as one file.
+#[derive(Serialize, Deserialize)]
+pub struct Bundle {
+    pub version: u32,
+    pub config: Config,
+    /// The frontend's own settings (localStorage), passed through untouched
+    #[serde(default)]
+    pub frontend: Option<serde_json::Value>,
+}
+
+impl Bundle {
+    pub fn new(config: Config, frontend: Option<serde_json::Value>) -> Self {
+        Bundle {
+            version: BUNDLE_VERSION,
+            config,
+            frontend,
+        }
+    }
+
+    pub fn write(&self, path: &Path) -> Result<(), String> {
+        let json = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        std::fs::write(path, json).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    /// Read a bundle from `write`, checking its version before anything else
+    /// so a newer format gets a clear error rather than a parse failure.
+    pub fn read(path: &Path) -> Result<Bundle, String> {
+        let raw = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let header: serde_json::Value = serde_json::from_str(&raw).map_err(|e| format!("{}: {}", path.display(), e))?;
+        match header.get("version").and_then(|v| v.as_u64()) {
+            Some(v) if v == BUNDLE_VERSION as u64 => {}
+            Some(v) => {
+                return Err(format!(
+                    "Settings bundle version {} is not supported (expected {})",
+                    v, BUNDLE_VERSION
+                ))
+            }
+            None => return Err(format!("{} is not a nanoprompt settings bundle", path.display())),
+        }
+        serde_json::from_value(header).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+}
+
+pub fn path(app: &AppHandle) -> Result<PathBuf, String> {
+    app.path()
+        .app_config_dir()
+        .map(|dir| dir.join("config.json"))
+        .map_err(|e| e.to_string())
+}
+
+/// A missing file is the default config; a malformed one is an error so we
+/// never overwrite something the user is hand-editing.
+pub fn read(app: &AppHandle) -> Result<Config, String> {
+    read_from(&path(app)?)
+}
+
+/// `read` for the config file at `path`.
+pub fn read_from(path: &Path) -> Result<Config, String> {
+    match std::fs::read_to_string(path) {
+        Ok(raw) => serde_json::from_str(&raw).map_err(|e| format!("{}: {}", path.display(), e)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Config::default()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Like `read`, but falls back to defaults on any error.
+pub fn load(app: &AppHandle) -> Config {
+    read(app).unwrap_or_default()
+}
+
+pub fn save(app: &AppHandle, config: &Config) -> Result<(), String> {
+    save_to(&path(app)?, config)
+}
+
+pub fn save_to(path: &Path, config: &Config) -> Result<(), String> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    }
+    let json = serde_json::to_string_pretty(config).map_err(|e| e.to_string())?;
+    std::fs::write(path, json).map_err(|e| e.to_string())
+}
+
+/// Apply `f` to the stored config and write it back.
+pub fn update<R>(app: &AppHandle, f: impl FnOnce(&mut Config) -> R) -> Result<R, String> {
+    update_at(&path(app)?, f)
+}
+
+/// `update` for the config file at `path`.
+pub fn update_at<R>(path: &Path, f: impl FnOnce(&mut Config) -> R) -> Result<R, String> {
+    let _guard = WRITE_LOCK.lock().map_err(|e| e.to_string())?;
+    let mut config = read_from(path)?;
+    let result = f(&mut config);
+    save_to(path, &config)?;
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validate_reports_every_problem() {
+        let json = r#"{ "profiles": [{ "name": "" }], "defaultProfile": "missing", "colour": 1 }"#;
+        let problems = validate(json).unwrap_err();
+        assert!(problems.contains(&"Unknown field colour".to_string()), "{:?}", problems);
+        assert!(problems.contains(&"A profile has no name".to_string()), "{:?}", problems);
+        assert!(
+            problems.contains(&r#"defaultProfile refers to missing profile "missing""#.to_string()),
+            "{:?}",
+            problems
+        );
+    }
+
+    #[test]
+    fn validate_accepts_defaults() {
+        let json = serde_json::to_string(&Config::default()).unwrap();
+        assert!(validate(&json).is_ok());
+    }
+
+    #[test]
+    fn bundle_round_trips() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("settings.json");
+        let mut config = Config::default();
+        config.profiles.push(Profile {
+            name: "work".into(),
+            program: Some("zsh".into()),
+            ..Default::default()
+        });
+        config.default_profile = Some("work".into());
+        let frontend = serde_json::json!({ "fontSize": 13 });
+        Bundle::new(config, Some(frontend.clone())).write(&path).unwrap();
+
+        let bundle = Bundle::read(&path).unwrap();
+        assert_eq!(bundle.config.profiles.len(), 1);
+        assert_eq!(bundle.config.profiles[0].program.as_deref(), Some("zsh"));
+        assert_eq!(bundle.config.default_profile.as_deref(), Some("work"));
+        assert_eq!(bundle.frontend, Some(frontend));
+
+        let mut merged = Config::default();
+        merged.profiles.push(Profile { name: "home".into(), ..Default::default() });
+        merged.merge(bundle.config);
+        let names: Vec<&str> = merged.profiles.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, ["home", "work"]);
+    }
+
+    #[test]
+    fn bundle_rejects_other_versions() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("settings.json");
+        std::fs::write(&path, r#"{ "version": 99, "config": {} }"#).unwrap();
+        let err = Bundle::read(&path).err().unwrap();
+        assert!(err.contains("version 99 is not supported"), "{}", err);
+    }
+
+    #[test]
+    fn profiles_round_trip_through_the_config_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config").join("config.json");
+        let profile = Profile {
+            name: "build".into(),
+            program: Some("/bin/zsh".into()),
+            args: vec!["-i".into()],
+            cwd: Some("~/src".into()),
+            env: HashMap::from([("EDITOR".into(), "vim".into())]),
+            login: true,
+            term: Some("xterm-kitty".into()),
+        };
+        update_at(&path, |config| config.put_profile(profile.clone())).unwrap();
+
+        let saved = read_from(&path).unwrap();
+        assert_eq!(saved.profiles.len(), 1);
+        let loaded = &saved.profiles[0];
+        assert_eq!(loaded.name, "build");
+        assert_eq!(loaded.program, profile.program);
+        assert_eq!(loaded.args, profile.args);
+        assert_eq!(loaded.cwd, profile.cwd);
+        assert_eq!(loaded.env, profile.env);
+        assert!(loaded.login);
+        assert_eq!(loaded.term, profile.term);
+
+        // Saving under the same name replaces it
+        let replaced = Profile { program: Some("/bin/bash".into()), ..profile };
+        update_at(&path, |config| config.put_profile(replaced)).unwrap();
+        let saved = read_from(&path).unwrap();
+        assert_eq!(saved.profiles.len(), 1);
+        assert_eq!(saved.profiles[0].program.as_deref(), Some("/bin/bash"));
+    }
+
+    #[test]
+    fn themes_round_trip_through_the_config_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.json");
+        let builtin = crate::theme::builtin();
+        let custom = Theme {
+            name: "Custom".into(),
+            foreground: "#eeeeee".into(),
+            ..builtin[0].clone()
+        };
+        update_at(&path, |config| {
+            config.put_theme(custom.clone());
+            config.active_theme = Some("Custom".into());
+        })
+        .unwrap();
+
+        let saved = read_from(&path).unwrap();
+        assert_eq!(saved.active_theme.as_deref(), Some("Custom"));
+        let themes = saved.all_themes();
+        assert_eq!(themes.len(), builtin.len() + 1);
+        let loaded = themes.iter().find(|t| t.name == "Custom").unwrap();
+        assert_eq!(loaded.foreground, "#eeeeee");
+        assert_eq!(loaded.ansi, custom.ansi);
+
+        // A user theme named after a built-in replaces it
+        let dark = Theme { cursor: "#ff0000".into(), ..builtin[0].clone() };
+        update_at(&path, |config| config.put_theme(dark)).unwrap();
+        let themes = read_from(&path).unwrap().all_themes();
+        assert_eq!(themes.len(), builtin.len() + 1);
+        assert_eq!(themes[0].cursor, "#ff0000");
+    }
+
+    #[test]
+    fn default_cwd_round_trips() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.json");
+        update_at(&path, |config| config.default_cwd = Some("~/projects".into())).unwrap();
+        assert_eq!(read_from(&path).unwrap().default_cwd.as_deref(), Some("~/projects"));
+    }
+}
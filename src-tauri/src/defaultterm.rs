@@ -0,0 +1,157 @@
+//! Registering the app as the system's default terminal.
+//!
+//! - macOS: becomes the shell-role handler for `public.unix-executable` and
+//!   `com.apple.terminal.shell-script`, which is what Finder and "open" use
+//!   to run scripts. No special permissions, but Launch Services only knows
+//!   an app that is installed (e.g. in /Applications), not a dev build.
+//! - Linux: puts our desktop entry first in `$XDG_CONFIG_HOME/xdg-terminals.list`,
+//!   the per-user list read by `xdg-terminal-exec` (used by newer desktops
+//!   and launchers). The Debian `x-terminal-emulator` alternative is
+//!   system-wide and needs root, so we don't touch it; neither do we change
+//!   GNOME's or KDE's own settings.
+//! - Elsewhere: unsupported.
+
+/// Desktop entry id the Linux bundles install.
+#[cfg(target_os = "linux")]
+const DESKTOP_ID: &str = "nanoprompt.desktop";
+
+#[cfg(target_os = "linux")]
+fn terminals_list() -> Result<std::path::PathBuf, String> {
+    let config = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(std::path::PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| std::path::Path::new(&home).join(".config")))
+        .ok_or("Neither XDG_CONFIG_HOME nor HOME is set")?;
+    Ok(config.join("xdg-terminals.list"))
+}
+
+/// Entries of xdg-terminals.list, skipping comments and blank lines.
+#[cfg(target_os = "linux")]
+fn entries(raw: &str) -> impl Iterator<Item = &str> {
+    raw.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+}
+
+#[cfg(target_os = "linux")]
+pub fn is_default(_identifier: &str) -> bool {
+    let Ok(path) = terminals_list() else { return false };
+    let raw = std::fs::read_to_string(path).unwrap_or_default();
+    entries(&raw).next() == Some(DESKTOP_ID)
+}
+
+#[cfg(target_os = "linux")]
+pub fn set_default(_identifier: &str) -> Result<(), String> {
+    let path = terminals_list()?;
+    let raw = match std::fs::read_to_string(&path) {
+        Ok(raw) => raw,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(format!("{}: {}", path.display(), e)),
+    };
+    // Keep the user's other choices as fallbacks, after ours
+    let mut list = vec![DESKTOP_ID];
+    list.extend(entries(&raw).filter(|&entry| entry != DESKTOP_ID));
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    }
+    std::fs::write(&path, list.join("\n") + "\n").map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+#[cfg(target_os = "macos")]
+mod launch_services {
+    use std::ffi::{c_char, c_void};
+
+    pub type CFStringRef = *const c_void;
+    const UTF8: u32 = 0x0800_0100;
+    pub const ROLE_SHELL: u32 = 0x0000_0008;
+
+    #[link(name = "CoreFoundation", kind = "framework")]
+    unsafe extern "C" {
+        fn CFStringCreateWithBytes(
+            alloc: *const c_void,
+            bytes: *const u8,
+            len: isize,
+            encoding: u32,
+            external: u8,
+        ) -> CFStringRef;
+        fn CFStringGetCString(s: CFStringRef, buf: *mut c_char, size: isize, encoding: u32) -> u8;
+        fn CFRelease(cf: *const c_void);
+    }
+
+    #[link(name = "CoreServices", kind = "framework")]
+    unsafe extern "C" {
+        pub fn LSSetDefaultRoleHandlerForContentType(content_type: CFStringRef, role: u32, handler: CFStringRef) -> i32;
+        pub fn LSCopyDefaultRoleHandlerForContentType(content_type: CFStringRef, role: u32) -> CFStringRef;
+    }
+
+    /// A CFString released on drop.
+    pub struct Str(pub CFStringRef);
+
+    impl Str {
+        pub fn new(s: &str) -> Str {
+            // SAFETY: the bytes are valid UTF-8 of the given length
+            Str(unsafe { CFStringCreateWithBytes(std::ptr::null(), s.as_ptr(), s.len() as isize, UTF8, 0) })
+        }
+
+        pub fn get(&self) -> Option<String> {
+            if self.0.is_null() {
+                return None;
+            }
+            let mut buf = [0 as c_char; 512];
+            // SAFETY: writes at most `buf.len()` bytes, NUL included
+            let ok = unsafe { CFStringGetCString(self.0, buf.as_mut_ptr(), buf.len() as isize, UTF8) };
+            if ok == 0 {
+                return None;
+            }
+            // SAFETY: CFStringGetCString NUL-terminated the buffer
+            let s = unsafe { std::ffi::CStr::from_ptr(buf.as_ptr()) };
+            Some(s.to_string_lossy().into_owned())
+        }
+    }
+
+    impl Drop for Str {
+        fn drop(&mut self) {
+            if !self.0.is_null() {
+                // SAFETY: we own this reference
+                unsafe { CFRelease(self.0) };
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "macos")]
+const CONTENT_TYPES: [&str; 2] = ["public.unix-executable", "com.apple.terminal.shell-script"];
+
+#[cfg(target_os = "macos")]
+pub fn is_default(identifier: &str) -> bool {
+    use launch_services::*;
+    let content_type = Str::new(CONTENT_TYPES[0]);
+    // SAFETY: a valid CFString in; the result follows the Copy rule, so Str releases it
+    let handler = Str(unsafe { LSCopyDefaultRoleHandlerForContentType(content_type.0, ROLE_SHELL) });
+    handler.get().is_some_and(|h| h.eq_ignore_ascii_case(identifier))
+}
+
+#[cfg(target_os = "macos")]
+pub fn set_default(identifier: &str) -> Result<(), String> {
+    use launch_services::*;
+    let handler = Str::new(identifier);
+    for content_type in CONTENT_TYPES {
+        let uti = Str::new(content_type);
+        // SAFETY: both arguments are valid CFStrings
+        let status = unsafe { LSSetDefaultRoleHandlerForContentType(uti.0, ROLE_SHELL, handler.0) };
+        if status != 0 {
+            return Err(format!("Launch Services refused {} (error {})", content_type, status));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub fn is_default(_identifier: &str) -> bool {
+    false
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub fn set_default(_identifier: &str) -> Result<(), String> {
+    Err("Setting the default terminal is not supported on this platform".into())
+}
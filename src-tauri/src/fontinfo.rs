@@ -0,0 +1,83 @@
+//! Minimal TrueType/OpenType table reading: just enough to get a face's
+//! family name and whether it's fixed-pitch, without a font crate.
+
+/// One face in a font file.
+pub struct Face {
+    pub family: String,
+    /// `isFixedPitch` from the `post` table
+    pub fixed_pitch: bool,
+}
+
+/// Faces in a .ttf/.otf (one) or .ttc collection (several). `None` if the
+/// data isn't a font we can read.
+pub fn faces(data: &[u8]) -> Option<Vec<Face>> {
+    if data.starts_with(b"ttcf") {
+        let count = be32(data, 8)? as usize;
+        (0..count)
+            .map(|i| face(data, be32(data, 12 + 4 * i)? as usize))
+            .collect()
+    } else {
+        Some(vec![face(data, 0)?])
+    }
+}
+
+fn face(data: &[u8], offset: usize) -> Option<Face> {
+    let num_tables = be16(data, offset + 4)? as usize;
+    let mut post = None;
+    let mut name = None;
+    for i in 0..num_tables {
+        let record = offset + 12 + 16 * i;
+        let tag = data.get(record..record + 4)?;
+        let table = be32(data, record + 8)? as usize;
+        match tag {
+            b"post" => post = Some(table),
+            b"name" => name = Some(table),
+            _ => {}
+        }
+    }
+    let name = name?;
+    // Typographic family (16) groups all weights; older fonts only have 1
+    let family = name_string(data, name, 16).or_else(|| name_string(data, name, 1))?;
+    Some(Face {
+        family,
+        fixed_pitch: be32(data, post? + 12)? != 0,
+    })
+}
+
+/// Name record `id`, preferring the Unicode/Windows (UTF-16BE) entries.
+fn name_string(data: &[u8], table: usize, id: u16) -> Option<String> {
+    let count = be16(data, table + 2)? as usize;
+    let strings = table + be16(data, table + 4)? as usize;
+    let mut fallback = None;
+    for i in 0..count {
+        let record = table + 6 + 12 * i;
+        if be16(data, record + 6)? != id {
+            continue;
+        }
+        let platform = be16(data, record)?;
+        let length = be16(data, record + 8)? as usize;
+        let start = strings + be16(data, record + 10)? as usize;
+        let bytes = data.get(start..start + length)?;
+        match platform {
+            0 | 3 => {
+                let units: Vec<u16> = bytes
+                    .chunks_exact(2)
+                    .map(|c| u16::from_be_bytes([c[0], c[1]]))
+                    .collect();
+                return String::from_utf16(&units).ok();
+            }
+            // Mac Roman; close enough to Latin-1 for family names
+            1 => fallback = Some(bytes.iter().map(|&b| b as char).collect()),
+            _ => {}
+        }
+    }
+    fallback
+}
+
+fn be16(data: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(data.get(at..at + 2)?.try_into().ok()?))
+}
+
+fn be32(data: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(data.get(at..at + 4)?.try_into().ok()?))
+}
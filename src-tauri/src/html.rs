@@ -0,0 +1,259 @@
+//! Rendering captured output as a standalone HTML page. SGR colors and
+//! styles become inline-styled spans; every other escape sequence is
+//! dropped, so cursor-addressed (full-screen) output won't look right.
+
+use crate::theme::Theme;
+use std::fmt::Write;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Color {
+    /// Palette index: the theme's 16 colors, then the 6x6x6 cube and grays
+    Index(u8),
+    Rgb(u8, u8, u8),
+}
+
+#[derive(Clone, Copy, Default, PartialEq)]
+struct Style {
+    fg: Option<Color>,
+    bg: Option<Color>,
+    bold: bool,
+    italic: bool,
+    underline: bool,
+    inverse: bool,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Ground,
+    Escape,
+    /// `ESC` followed by intermediates, e.g. the charset selection in `ESC ( B`
+    EscapeIntermediate,
+    Csi,
+    /// OSC, DCS and the other string sequences, up to BEL or ST
+    Str,
+    StrEscape,
+}
+
+struct Renderer<'a> {
+    theme: &'a Theme,
+    out: String,
+    style: Style,
+    /// Style of the span currently open, if any
+    open: Option<Style>,
+}
+
+/// `data` as an HTML page in `theme`'s colors.
+pub fn render(data: &[u8], theme: &Theme) -> String {
+    let mut r = Renderer {
+        theme,
+        out: String::new(),
+        style: Style::default(),
+        open: None,
+    };
+    let mut state = State::Ground;
+    let mut csi = String::new();
+    for c in String::from_utf8_lossy(data).chars() {
+        state = match (state, c) {
+            (State::Ground, '\x1b') | (State::StrEscape, '\x1b') => State::Escape,
+            (State::Ground, '\n' | '\t') => {
+                r.text(c);
+                State::Ground
+            }
+            (State::Ground, c) if c.is_control() => State::Ground,
+            (State::Ground, c) => {
+                r.text(c);
+                State::Ground
+            }
+            (State::Escape, '[') => {
+                csi.clear();
+                State::Csi
+            }
+            (State::Escape, ']' | 'P' | 'X' | '^' | '_') => State::Str,
+            (State::Escape | State::EscapeIntermediate, '\x20'..='\x2f') => {
+                State::EscapeIntermediate
+            }
+            (State::Escape | State::EscapeIntermediate, _) => State::Ground,
+            (State::Csi, '\x20'..='\x3f') => {
+                csi.push(c);
+                State::Csi
+            }
+            (State::Csi, '\x40'..='\x7e') => {
+                if c == 'm' {
+                    r.sgr(&csi);
+                }
+                State::Ground
+            }
+            (State::Csi, '\x1b') => State::Escape,
+            (State::Csi, '\x18' | '\x1a') => State::Ground,
+            (State::Csi, _) => State::Csi,
+            (State::Str, '\x07') => State::Ground,
+            (State::Str, '\x1b') => State::StrEscape,
+            (State::Str, _) => State::Str,
+            (State::StrEscape, _) => State::Ground,
+        };
+    }
+    r.close();
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>nanoprompt output</title>\n</head>\n\
+         <body style=\"margin:0;background:{bg}\">\n\
+         <pre style=\"margin:0;padding:1em;color:{fg};background:{bg};font-family:ui-monospace,Menlo,Consolas,monospace\">{}</pre>\n\
+         </body>\n</html>\n",
+        r.out,
+        fg = theme.foreground,
+        bg = theme.background,
+    )
+}
+
+impl Renderer<'_> {
+    fn text(&mut self, c: char) {
+        if self.open != Some(self.style) {
+            self.close();
+            if self.style != Style::default() {
+                let css = self.css(self.style);
+                let _ = write!(self.out, "<span style=\"{}\">", css);
+                self.open = Some(self.style);
+            }
+        }
+        match c {
+            '&' => self.out.push_str("&amp;"),
+            '<' => self.out.push_str("&lt;"),
+            '>' => self.out.push_str("&gt;"),
+            c => self.out.push(c),
+        }
+    }
+
+    fn close(&mut self) {
+        if self.open.take().is_some() {
+            self.out.push_str("</span>");
+        }
+    }
+
+    fn css(&self, style: Style) -> String {
+        let (mut fg, mut bg) = (
+            style.fg.map(|c| self.color(c)),
+            style.bg.map(|c| self.color(c)),
+        );
+        if style.inverse {
+            let swapped_fg = bg.unwrap_or_else(|| self.theme.background.clone());
+            bg = Some(fg.unwrap_or_else(|| self.theme.foreground.clone()));
+            fg = Some(swapped_fg);
+        }
+        let mut css = String::new();
+        if let Some(fg) = fg {
+            let _ = write!(css, "color:{};", fg);
+        }
+        if let Some(bg) = bg {
+            let _ = write!(css, "background:{};", bg);
+        }
+        if style.bold {
+            css.push_str("font-weight:bold;");
+        }
+        if style.italic {
+            css.push_str("font-style:italic;");
+        }
+        if style.underline {
+            css.push_str("text-decoration:underline;");
+        }
+        css
+    }
+
+    fn color(&self, color: Color) -> String {
+        const CUBE: [u8; 6] = [0, 95, 135, 175, 215, 255];
+        let (r, g, b) = match color {
+            Color::Index(i @ 0..16) => return self.theme.ansi[i as usize].clone(),
+            Color::Index(i @ 16..232) => {
+                let i = (i - 16) as usize;
+                (CUBE[i / 36], CUBE[i / 6 % 6], CUBE[i % 6])
+            }
+            Color::Index(i) => {
+                let gray = 8 + 10 * (i - 232);
+                (gray, gray, gray)
+            }
+            Color::Rgb(r, g, b) => (r, g, b),
+        };
+        format!("#{:02x}{:02x}{:02x}", r, g, b)
+    }
+
+    /// Apply an SGR (`CSI ... m`) parameter string.
+    fn sgr(&mut self, params: &str) {
+        // Private (`CSI > ... m`) sequences aren't SGR
+        if params.starts_with(['<', '=', '>', '?']) {
+            return;
+        }
+        let mut groups = params.split(';');
+        while let Some(group) = groups.next() {
+            // Colon sub-parameters: 38:5:n, 38:2:r:g:b or 38:2:space:r:g:b, 4:n
+            if group.contains(':') {
+                let sub: Vec<u32> = group.split(':').map(|p| p.parse().unwrap_or(0)).collect();
+                match sub.as_slice() {
+                    [4, n, ..] => self.style.underline = *n != 0,
+                    [kind @ (38 | 48), 5, n, ..] => {
+                        self.set_color(*kind, Some(Color::Index(*n as u8)))
+                    }
+                    [kind @ (38 | 48), 2, _, r, g, b] | [kind @ (38 | 48), 2, r, g, b] => {
+                        self.set_color(*kind, Some(Color::Rgb(*r as u8, *g as u8, *b as u8)))
+                    }
+                    _ => {}
+                }
+                continue;
+            }
+            // An empty parameter means 0
+            let code: u32 = group.parse().unwrap_or(0);
+            match code {
+                0 => self.style = Style::default(),
+                1 => self.style.bold = true,
+                3 => self.style.italic = true,
+                4 => self.style.underline = true,
+                7 => self.style.inverse = true,
+                22 => self.style.bold = false,
+                23 => self.style.italic = false,
+                24 => self.style.underline = false,
+                27 => self.style.inverse = false,
+                30..=37 => self.style.fg = Some(Color::Index((code - 30) as u8)),
+                39 => self.style.fg = None,
+                40..=47 => self.style.bg = Some(Color::Index((code - 40) as u8)),
+                49 => self.style.bg = None,
+                90..=97 => self.style.fg = Some(Color::Index((code - 90 + 8) as u8)),
+                100..=107 => self.style.bg = Some(Color::Index((code - 100 + 8) as u8)),
+                38 | 48 => {
+                    let mut next = || {
+                        groups
+                            .next()
+                            .and_then(|p| p.parse::<u32>().ok())
+                            .unwrap_or(0)
+                    };
+                    let color = match next() {
+                        5 => Some(Color::Index(next() as u8)),
+                        2 => Some(Color::Rgb(next() as u8, next() as u8, next() as u8)),
+                        _ => None,
+                    };
+                    if color.is_some() {
+                        self.set_color(code, color);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn set_color(&mut self, kind: u32, color: Option<Color>) {
+        if kind == 38 {
+            self.style.fg = color;
+        } else {
+            self.style.bg = color;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sgr_red_becomes_a_span_closed_by_reset() {
+        let theme = &crate::theme::builtin()[0];
+        let html = render(b"\x1b[31mred\x1b[0m plain", theme);
+        let span = format!("<span style=\"color:{};\">red</span> plain", theme.ansi[1]);
+        assert!(html.contains(&span), "{}", html);
+    }
+}
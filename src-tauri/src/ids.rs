@@ -0,0 +1,120 @@
+//! The session id high-water mark, kept in `session_ids.json` in the app
+//! data dir so ids stay unique across launches: a reference saved by one
+//! launch (a layout, say) never aliases a session of a later one.
+//!
+//! Ids are reserved in blocks: the saved mark runs `RESERVE_BLOCK` ahead of
+//! the ids handed out, so the file is written once per block rather than on
+//! every spawn. A later launch starts at the mark, skipping whatever of the
+//! block went unused.
+
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tauri::{AppHandle, Manager};
+
+/// Ids reserved past the one that needed a write
+const RESERVE_BLOCK: u32 = 64;
+
+/// The mark last written, so ids below it need no write and concurrent
+/// spawns never move it backwards.
+static SAVED: Mutex<u32> = Mutex::new(0);
+
+fn path(app: &AppHandle) -> Result<PathBuf, String> {
+    app.path()
+        .app_data_dir()
+        .map(|dir| dir.join("session_ids.json"))
+        .map_err(|e| e.to_string())
+}
+
+/// The first id this launch may use; 1 if nothing was saved.
+pub fn load(app: &AppHandle) -> u32 {
+    let next = path(app).map(|path| load_from(&path)).unwrap_or(1);
+    if let Ok(mut saved) = SAVED.lock() {
+        *saved = next;
+    }
+    next
+}
+
+/// The mark saved at `path`; 1 if there is none.
+pub fn load_from(path: &Path) -> u32 {
+    std::fs::read_to_string(path)
+        .ok()
+        .and_then(|raw| serde_json::from_str::<u32>(&raw).ok())
+        .unwrap_or(1)
+        .max(1)
+}
+
+/// Record that ids below `next` have been handed out.
+pub fn reserve(app: &AppHandle, next: u32) {
+    let Ok(mut saved) = SAVED.lock() else { return };
+    if next <= *saved {
+        return;
+    }
+    match path(app).and_then(|path| reserve_at(&path, next)) {
+        Ok(mark) => *saved = mark,
+        Err(e) => log::warn!("could not save session id counter: {}", e),
+    }
+}
+
+/// Save a mark a block past `next` at `path`, returning it.
+pub fn reserve_at(path: &Path, next: u32) -> Result<u32, String> {
+    let mark = next.saturating_add(RESERVE_BLOCK);
+    save(path, mark)?;
+    Ok(mark)
+}
+
+/// Start over from 1, forgetting the saved mark.
+pub fn reset(app: &AppHandle) -> Result<(), String> {
+    let mut saved = SAVED.lock().map_err(|e| e.to_string())?;
+    save(&path(app)?, 1)?;
+    *saved = 1;
+    Ok(())
+}
+
+fn save(path: &Path, next: u32) -> Result<(), String> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    }
+    std::fs::write(path, next.to_string()).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// One launch spawning `sessions` sessions the way `reserve` does,
+    /// returning their ids and how many times the mark was written.
+    fn launch(path: &Path, sessions: u32) -> (Vec<u32>, u32) {
+        let mut saved = load_from(path);
+        let mut writes = 0;
+        let ids = (saved..saved + sessions)
+            .inspect(|&id| {
+                if id + 1 > saved {
+                    saved = reserve_at(path, id + 1).unwrap();
+                    writes += 1;
+                }
+            })
+            .collect();
+        (ids, writes)
+    }
+
+    #[test]
+    fn launches_never_overlap() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data").join("session_ids.json");
+        let (first, writes) = launch(&path, 3);
+        assert_eq!(first, [1, 2, 3]);
+        assert_eq!(writes, 1);
+        let (second, _) = launch(&path, 100);
+        assert!(second[0] > *first.last().unwrap());
+        let (third, _) = launch(&path, 1);
+        assert!(third[0] > *second.last().unwrap());
+    }
+
+    #[test]
+    fn writes_once_per_block() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("session_ids.json");
+        let (_, writes) = launch(&path, RESERVE_BLOCK * 2);
+        assert_eq!(writes, 2);
+    }
+}
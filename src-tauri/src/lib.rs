@@ -1,9 +1,33 @@
-use portable_pty::{native_pty_system, CommandBuilder, MasterPty, PtySize, Child};
-use std::collections::HashMap;
+mod accelerator;
+mod blocks;
+mod config;
+mod defaultterm;
+mod fontinfo;
+mod html;
+mod ids;
+#[cfg(feature = "logging")]
+mod logging;
+mod parser;
+mod password;
+mod procinfo;
+mod ptylog;
+mod recent;
+mod recording;
+mod theme;
+
+use portable_pty::{native_pty_system, CommandBuilder, MasterPty, PtySize, PtySystem, Child};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::io::{Read, Write};
-use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
-use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::{Duration, Instant};
+use serde::{Deserialize, Serialize};
+use config::Profile;
+use parser::{ModeChange, Parser, TermModes};
+use theme::Theme;
 use tauri::{AppHandle, Emitter, Manager, State};
+use tauri_plugin_deep_link::DeepLinkExt;
+use tauri_plugin_opener::OpenerExt;
 use tauri::menu::{MenuBuilder, MenuItemBuilder, PredefinedMenuItem, SubmenuBuilder};
 
 struct PtySession {
@@ -11,17 +35,197 @@ struct PtySession {
     writer: Box<dyn Write + Send>,
     child: Box<dyn Child + Send + Sync>,
     exited: Arc<AtomicBool>,
+    buffer_size: usize,
+    bytes_in: Arc<AtomicU64>,
+    /// Input bytes written to the child
+    bytes_out: AtomicU64,
+    /// When output last arrived or input was written, in ms since the epoch
+    /// (0 = never)
+    last_activity: Arc<AtomicU64>,
+    tty_name: Option<String>,
+    /// What the session was spawned with, for restarts
+    options: PtyOptions,
+    /// Set when a restart replaces this session's PTY under the same id
+    superseded: Arc<AtomicBool>,
+    /// Previous (cpu time, wall clock) reading for `get_pty_resources`
+    cpu_sample: Option<(Duration, Instant)>,
+    /// Modes the reader has seen the child switch on (alt screen, ...)
+    modes: Arc<TermModes>,
+    /// External FIFO/socket also receiving raw output, see `tap_pty`
+    tap: Tap,
+    /// asciicast file receiving output, see `start_recording`
+    recording: Recording,
+    /// Recent raw output, when `scrollback_bytes` asked for it
+    scrollback: Option<Scrollback>,
+    /// Commands' output ranges from OSC 133 marks, see `get_command_blocks`
+    command_blocks: Arc<blocks::CommandBlocks>,
+    /// Windows also receiving output as `pty-mirror-output`, see `mirror_pty`
+    mirrors: Arc<Mutex<Vec<String>>>,
+    /// Raw output log when `NANOPROMPT_PTY_LOG` is set, see `rotate_pty_log`
+    pty_log: Option<Arc<Mutex<ptylog::PtyLog>>>,
+    /// Spawned from `output_filter`; shut down when the session is dropped
+    output_filter: Option<OutputFilter>,
+    /// What shell startup printed, when `capture_init` asked for it
+    init_output: Option<Arc<Mutex<Vec<u8>>>>,
+    /// Receives a copy of output while `query_pty` waits for a reply
+    capture: Capture,
+    /// Off while the session is locked read-only, see `set_pty_input_enabled`
+    input_enabled: bool,
+    /// Locked input is dropped silently instead of rejected
+    drop_locked_input: bool,
+    /// Set by `cancel_write` to stop a `write_file_to_pty` in progress
+    write_cancelled: bool,
+    /// Opaque JSON the frontend keeps with the session, see `set_pty_metadata`
+    metadata: Option<String>,
+}
+
+type Tap = Arc<Mutex<Option<Box<dyn Write + Send>>>>;
+type Capture = Arc<Mutex<Option<mpsc::Sender<Vec<u8>>>>>;
+type Recording = Arc<Mutex<Option<recording::Recorder>>>;
+type Scrollback = Arc<Mutex<VecDeque<u8>>>;
+
+impl PtySession {
+    /// Write and flush input, counting it in `bytes_out`.
+    fn send(&mut self, data: &[u8]) -> std::io::Result<()> {
+        self.writer.write_all(data)?;
+        self.writer.flush()?;
+        self.bytes_out.fetch_add(data.len() as u64, Ordering::Relaxed);
+        self.last_activity.store(epoch_millis(), Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Whether input may go to the child: `Ok(false)` means drop it quietly.
+    fn accepts_input(&self) -> Result<bool, String> {
+        match (self.input_enabled, self.drop_locked_input) {
+            (true, _) => Ok(true),
+            (false, true) => Ok(false),
+            (false, false) => Err("input-locked".into()),
+        }
+    }
+
+    /// The shell's current directory: what it last reported via OSC 7, else
+    /// what the OS says for the child process.
+    fn cwd(&self) -> Option<String> {
+        let reported = self.modes.cwd.lock().ok().and_then(|cwd| cwd.clone());
+        reported.or_else(|| procinfo::cwd(self.child.process_id()?))
+    }
+
+    fn last_activity(&self) -> Option<u64> {
+        match self.last_activity.load(Ordering::Relaxed) {
+            0 => None,
+            ms => Some(ms),
+        }
+    }
 }
 
 struct PtyState {
     sessions: Mutex<HashMap<u32, PtySession>>,
     next_id: AtomicU32,
+    /// Sessions being spawned, counted against `max_sessions` until inserted
+    reserved: AtomicUsize,
+}
+
+const DEFAULT_BUFFER_SIZE: usize = 4096;
+const MAX_BUFFER_SIZE: usize = 256 * 1024;
+const MAX_SCROLLBACK_BYTES: usize = 64 * 1024 * 1024;
+const INIT_CAPTURE_WINDOW: Duration = Duration::from_secs(2);
+const INIT_CAPTURE_MAX: usize = 64 * 1024;
+
+/// Optional spawn settings passed to `create_pty`. Every field may be omitted.
+#[derive(Clone, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+struct PtyOptions {
+    /// Reader buffer size in bytes (default 4KB, capped at 256KB)
+    buffer_size: Option<usize>,
+    /// Hard ceiling on `pty-output` events per second (0 = unlimited)
+    max_events_per_sec: u32,
+    /// Windows only: "conpty" or "winpty". Ignored elsewhere.
+    backend: Option<String>,
+    /// Program to run instead of the default shell
+    program: Option<String>,
+    args: Vec<String>,
+    cwd: Option<String>,
+    /// Extra environment, applied after TERM/COLORTERM so it can override them
+    env: HashMap<String, String>,
+    /// Start from an empty environment instead of the app's
+    clear_env: bool,
+    /// Clear the environment, then copy only these variables from the app's
+    /// (e.g. PATH, HOME, LANG). `None` inherits everything. Programs are
+    /// looked up on the child's PATH, so usually keep it in the list.
+    inherit_env: Option<Vec<String>>,
+    /// Pass `-l` to an explicit `program` (the default shell is always a login shell)
+    login: bool,
+    /// Skip startup files and blank the prompt, for shells spawned to run
+    /// commands rather than for people. See `quiet_args`; overrides `login`.
+    quiet: bool,
+    /// Unix only: argv[0] for the child, independent of the program path.
+    /// Takes precedence over the login shell's `-` prefix. Needs bash on
+    /// PATH: the child is started as `bash -c 'exec -a "$0" "$@"'`.
+    arg0: Option<String>,
+    term: Option<String>,
+    /// Keep the session (flagged exited) after the child dies until
+    /// `dismiss_pty`, instead of removing it right away
+    hold_on_exit: bool,
+    /// Panes split from one window share a group, see `resize_group`
+    group_id: Option<u32>,
+    /// Prefix for this session's events (`<ns>:pty-output`, ...) so several
+    /// terminal surfaces in one app don't see each other's sessions
+    event_namespace: Option<String>,
+    /// Linux only: run the child as this user/group. Needs the app to run as
+    /// root. portable_pty has no pre-exec hook, so this goes through
+    /// util-linux's `setpriv`; HOME and friends still come from our env.
+    uid: Option<u32>,
+    gid: Option<u32>,
+    /// Close the session (EOF, then kill) after this long with no output or
+    /// input; it exits with reason `"idle-timeout"`
+    idle_timeout_ms: Option<u64>,
+    /// Keep this many bytes of raw output in the backend (capped at 64MB),
+    /// for `get_scrollback` and `trim_scrollback`. Off by default.
+    scrollback_bytes: Option<usize>,
+    /// Keep a copy of startup output (the first 2s, ending early at the first
+    /// OSC 7 cwd report, which shells send before their first prompt) for
+    /// `get_init_output`, so rc file errors can be surfaced
+    capture_init: bool,
+    /// Advanced: program and arguments to spawn alongside the session with
+    /// its raw output on stdin (e.g. a recorder or annotator). The frontend
+    /// still gets the output untouched. A filter that falls behind loses
+    /// data (Unix) rather than stalling the terminal; one that dies just
+    /// stops receiving (`pty-filter-ended`). It gets EOF when the session
+    /// closes, and is killed if it lingers.
+    output_filter: Option<Vec<String>>,
+    /// Unix only: emit `pty-foreground-changed` as programs take over and
+    /// hand back the terminal, see `spawn_foreground_watch`
+    watch_foreground: bool,
+    /// Emit `pty-password-prompt` `{ id, active }` when output looks like it
+    /// is asking for a password (see `Config::password_prompts`), and again
+    /// once the line is done. A heuristic, for masking input or warning.
+    detect_password_prompts: bool,
+    /// Label of the window hosting the session, filled in by the backend
+    #[serde(skip)]
+    window: Option<String>,
+    /// Initial size of the text area in pixels, from `create_pty`
+    #[serde(skip)]
+    pixel_size: (u16, u16),
+}
+
+impl From<&Profile> for PtyOptions {
+    fn from(profile: &Profile) -> Self {
+        PtyOptions {
+            program: profile.program.clone(),
+            args: profile.args.clone(),
+            cwd: profile.cwd.clone(),
+            env: profile.env.clone(),
+            login: profile.login,
+            term: profile.term.clone(),
+            ..Default::default()
+        }
+    }
 }
 
 static BASE64_CHARS: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
 
 fn base64_encode(input: &[u8]) -> String {
-    let mut result = String::with_capacity((input.len() + 2) / 3 * 4);
+    let mut result = String::with_capacity(input.len().div_ceil(3) * 4);
     for chunk in input.chunks(3) {
         let b0 = chunk[0] as u32;
         let b1 = if chunk.len() > 1 { chunk[1] as u32 } else { 0 };
@@ -43,255 +247,4078 @@ fn base64_encode(input: &[u8]) -> String {
     result
 }
 
-#[tauri::command]
-fn create_pty(
-    app: AppHandle,
-    state: State<'_, PtyState>,
-    rows: u16,
-    cols: u16,
-) -> Result<u32, String> {
-    let pty_system = native_pty_system();
+/// Inverse of `base64_encode`. Input must be padded to a multiple of 4.
+fn base64_decode(input: &str) -> Result<Vec<u8>, String> {
+    let bytes = input.as_bytes();
+    if !bytes.len().is_multiple_of(4) {
+        return Err(format!("Invalid base64 length {} (must be a multiple of 4)", bytes.len()));
+    }
+    let padding = bytes.iter().rev().take_while(|&&b| b == b'=').count();
+    if padding > 2 {
+        return Err("Invalid base64 padding".into());
+    }
 
-    let pair = pty_system
-        .openpty(PtySize {
-            rows,
-            cols,
-            pixel_width: 0,
-            pixel_height: 0,
-        })
-        .map_err(|e| e.to_string())?;
+    let mut result = Vec::with_capacity(bytes.len() / 4 * 3);
+    let data_len = bytes.len() - padding;
+    for (chunk_index, chunk) in bytes.chunks(4).enumerate() {
+        let mut triple: u32 = 0;
+        for (i, &c) in chunk.iter().enumerate() {
+            let pos = chunk_index * 4 + i;
+            let value = if pos >= data_len {
+                0
+            } else {
+                match BASE64_CHARS.iter().position(|&b| b == c) {
+                    Some(v) => v as u32,
+                    None => return Err(format!("Invalid base64 character {:?} at {}", c as char, pos)),
+                }
+            };
+            triple = (triple << 6) | value;
+        }
+        let out = [(triple >> 16) as u8, (triple >> 8) as u8, triple as u8];
+        let keep = (data_len.saturating_sub(chunk_index * 4) * 3 / 4).min(3);
+        result.extend_from_slice(&out[..keep]);
+    }
+    Ok(result)
+}
 
-    let mut cmd = CommandBuilder::new_default_prog();
-    cmd.env("TERM", "xterm-256color");
-    cmd.env("COLORTERM", "truecolor");
+/// Decode base64 the same way the backend encodes it, for diagnostics.
+#[tauri::command]
+fn decode_base64(input: String) -> Result<Vec<u8>, String> {
+    base64_decode(&input)
+}
 
-    let child = pair.slave.spawn_command(cmd).map_err(|e| e.to_string())?;
+#[derive(Clone, Copy, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct PtyDimensions {
+    rows: u16,
+    cols: u16,
+    /// Size of the text area in pixels (0 = unknown), which sixel and kitty
+    /// graphics programs read via TIOCGWINSZ to scale images
+    pixel_width: u16,
+    pixel_height: u16,
+}
 
-    // Drop slave so we get EOF when the child exits
-    drop(pair.slave);
+impl From<PtySize> for PtyDimensions {
+    fn from(size: PtySize) -> Self {
+        PtyDimensions {
+            rows: size.rows,
+            cols: size.cols,
+            pixel_width: size.pixel_width,
+            pixel_height: size.pixel_height,
+        }
+    }
+}
 
-    let master = pair.master;
-    let writer = master.take_writer().map_err(|e| e.to_string())?;
-    let mut reader = master.try_clone_reader().map_err(|e| e.to_string())?;
+impl From<PtyDimensions> for PtySize {
+    fn from(size: PtyDimensions) -> Self {
+        PtySize {
+            rows: size.rows,
+            cols: size.cols,
+            pixel_width: size.pixel_width,
+            pixel_height: size.pixel_height,
+        }
+    }
+}
 
-    let id = state.next_id.fetch_add(1, Ordering::Relaxed);
-    let exited = Arc::new(AtomicBool::new(false));
+const DEFAULT_SIZE: PtyDimensions = PtyDimensions {
+    rows: 24,
+    cols: 80,
+    pixel_width: 0,
+    pixel_height: 0,
+};
 
-    // Spawn reader thread
-    let app_handle = app.clone();
-    let exited_flag = exited.clone();
-    std::thread::spawn(move || {
-        let mut buf = [0u8; 4096];
-        loop {
-            match reader.read(&mut buf) {
-                Ok(0) => {
-                    exited_flag.store(true, Ordering::Relaxed);
-                    let _ = app_handle.emit("pty-exit", id);
-                    break;
-                }
-                Ok(n) => {
-                    let encoded = base64_encode(&buf[..n]);
-                    let _ = app_handle.emit("pty-output", serde_json::json!({
-                        "id": id,
-                        "data": encoded
-                    }));
-                }
-                Err(_) => {
-                    exited_flag.store(true, Ordering::Relaxed);
-                    let _ = app_handle.emit("pty-exit", id);
-                    break;
-                }
-            }
+/// Guard against the frontend passing 0 rows/cols while its layout is still
+/// settling. A fully zero size at creation falls back to 24x80.
+fn normalize_size(rows: u16, cols: u16, creating: bool) -> PtyDimensions {
+    let size = if creating && rows == 0 && cols == 0 {
+        DEFAULT_SIZE
+    } else {
+        PtyDimensions {
+            rows: rows.max(1),
+            cols: cols.max(1),
+            ..Default::default()
         }
-    });
+    };
+    if size.rows != rows || size.cols != cols {
+        log::warn!("clamped PTY size {}x{} to {}x{}", rows, cols, size.rows, size.cols);
+    }
+    size
+}
 
-    let session = PtySession {
-        master,
-        writer,
-        child,
-        exited,
+/// Cell size of the frontend's default font (14px monospace), in logical px.
+const DEFAULT_CELL_WIDTH: f64 = 8.4;
+const DEFAULT_CELL_HEIGHT: f64 = 17.0;
+
+/// Best guess at a new tab's grid before the frontend has measured it: the
+/// window's logical inner size divided by the default cell metric. Chrome
+/// (tab bar, padding) isn't accounted for, so the frontend's first fit may
+/// still shave a row or two. Falls back to 24x80 without a usable window.
+fn estimate_size(window: &tauri::WebviewWindow) -> PtyDimensions {
+    let fallback = DEFAULT_SIZE;
+    let (Ok(size), Ok(scale)) = (window.inner_size(), window.scale_factor()) else {
+        return fallback;
     };
+    let logical = size.to_logical::<f64>(scale);
+    let rows = (logical.height / DEFAULT_CELL_HEIGHT).floor() as u16;
+    let cols = (logical.width / DEFAULT_CELL_WIDTH).floor() as u16;
+    if rows == 0 || cols == 0 {
+        return fallback;
+    }
+    PtyDimensions {
+        rows,
+        cols,
+        ..Default::default()
+    }
+}
 
-    state
-        .sessions
-        .lock()
-        .map_err(|e| e.to_string())?
-        .insert(id, session);
+#[cfg(windows)]
+fn pty_system_for(backend: Option<&str>) -> Result<Box<dyn PtySystem + Send>, String> {
+    match backend {
+        None => Ok(native_pty_system()),
+        Some("conpty") => Ok(Box::new(portable_pty::NativePtySystem::default())),
+        Some("winpty") => Err("WinPTY backend is not available in this build, use ConPTY".into()),
+        Some(other) => Err(format!("Unknown PTY backend: {}", other)),
+    }
+}
 
-    Ok(id)
+#[cfg(not(windows))]
+fn pty_system_for(_backend: Option<&str>) -> Result<Box<dyn PtySystem + Send>, String> {
+    Ok(native_pty_system())
 }
 
-#[tauri::command]
-fn write_pty(state: State<'_, PtyState>, id: u32, data: String) -> Result<(), String> {
-    let mut sessions = state.sessions.lock().map_err(|e| e.to_string())?;
-    let session = sessions.get_mut(&id).ok_or("Session not found")?;
-    session
-        .writer
-        .write_all(data.as_bytes())
-        .map_err(|e| e.to_string())?;
-    session.writer.flush().map_err(|e| e.to_string())?;
-    Ok(())
+/// Why `create_pty` failed, tagged by the stage that failed so the frontend
+/// can show something actionable. Serializes as `{ kind, message, osError }`.
+#[derive(Debug, Serialize)]
+#[serde(tag = "kind", rename_all = "camelCase", rename_all_fields = "camelCase")]
+enum PtyError {
+    ProgramNotFound { program: String },
+    OpenPty { message: String, os_error: Option<i32> },
+    Spawn { message: String, os_error: Option<i32> },
+    TakeWriter { message: String, os_error: Option<i32> },
+    CloneReader { message: String, os_error: Option<i32> },
+    /// `max_sessions` sessions are already running
+    LimitReached { limit: usize },
+    Other { message: String },
 }
 
-#[tauri::command]
-fn resize_pty(state: State<'_, PtyState>, id: u32, rows: u16, cols: u16) -> Result<(), String> {
-    let sessions = state.sessions.lock().map_err(|e| e.to_string())?;
-    let session = sessions.get(&id).ok_or("Session not found")?;
-    session
-        .master
-        .resize(PtySize {
-            rows,
-            cols,
-            pixel_width: 0,
-            pixel_height: 0,
-        })
-        .map_err(|e| e.to_string())?;
-    Ok(())
+impl std::fmt::Display for PtyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PtyError::ProgramNotFound { program } => write!(f, "Program not found: {}", program),
+            PtyError::OpenPty { message, .. } => write!(f, "Failed to open PTY: {}", message),
+            PtyError::Spawn { message, .. } => write!(f, "Failed to spawn: {}", message),
+            PtyError::TakeWriter { message, .. } => write!(f, "Failed to open PTY writer: {}", message),
+            PtyError::CloneReader { message, .. } => write!(f, "Failed to open PTY reader: {}", message),
+            PtyError::LimitReached { limit } => write!(f, "Too many terminals open (limit {})", limit),
+            PtyError::Other { message } => f.write_str(message),
+        }
+    }
 }
 
-#[tauri::command]
-fn load_font(family: String) -> Result<Option<String>, String> {
-    let needle = family.replace(' ', "").to_lowercase();
-    if needle.is_empty() {
-        return Ok(None);
+impl From<String> for PtyError {
+    fn from(message: String) -> Self {
+        PtyError::Other { message }
     }
+}
 
-    let home = std::env::var("HOME").unwrap_or_default();
+impl From<PtyError> for String {
+    fn from(e: PtyError) -> Self {
+        e.to_string()
+    }
+}
 
-    #[cfg(target_os = "macos")]
-    let dirs = vec![
-        format!("{}/Library/Fonts", home),
-        "/Library/Fonts".to_string(),
-        "/System/Library/Fonts".to_string(),
-    ];
-    #[cfg(target_os = "windows")]
-    let dirs = vec![
-        format!("{}\\Microsoft\\Windows\\Fonts", std::env::var("LOCALAPPDATA").unwrap_or_default()),
-        "C:\\Windows\\Fonts".to_string(),
-    ];
-    #[cfg(target_os = "linux")]
-    let dirs = vec![
-        format!("{}/.local/share/fonts", home),
-        "/usr/share/fonts".to_string(),
-        "/usr/local/share/fonts".to_string(),
-    ];
+/// OS error code behind a portable_pty error, if there is one.
+fn os_error(e: &(dyn std::error::Error + 'static)) -> Option<i32> {
+    std::iter::successors(Some(e), |e| e.source())
+        .find_map(|e| e.downcast_ref::<std::io::Error>())
+        .and_then(|e| e.raw_os_error())
+}
 
-    let mut candidates: Vec<std::path::PathBuf> = Vec::new();
+/// Whether `program` resolves to a file, either as a path (relative to
+/// `cwd`) or via PATH.
+fn program_exists(program: &str, cwd: Option<&str>) -> bool {
+    let path = expand_home(program);
+    if path.components().count() > 1 || path.is_absolute() {
+        let path = match cwd {
+            Some(cwd) if path.is_relative() => expand_home(cwd).join(path),
+            _ => path,
+        };
+        return path.is_file();
+    }
+    let Some(dirs) = std::env::var_os("PATH") else {
+        return false;
+    };
+    // Windows resolves bare names against PATHEXT; cover the common ones
+    let extensions: &[&str] = if cfg!(windows) { &["exe", "cmd", "bat", "com"] } else { &[] };
+    std::env::split_paths(&dirs).any(|dir| {
+        let candidate = dir.join(&path);
+        candidate.is_file() || extensions.iter().any(|ext| candidate.with_extension(ext).is_file())
+    })
+}
 
-    for dir in &dirs {
-        collect_fonts(dir.as_ref(), &needle, &mut candidates);
+/// Expand a leading `~` to the user's home directory.
+fn expand_home(path: &str) -> std::path::PathBuf {
+    let home = std::env::var("HOME")
+        .or_else(|_| std::env::var("USERPROFILE"))
+        .unwrap_or_default();
+    match path.strip_prefix('~') {
+        Some("") => home.into(),
+        Some(rest) if rest.starts_with(['/', '\\']) => std::path::Path::new(&home).join(&rest[1..]),
+        _ => path.into(),
     }
+}
 
-    // Prefer Regular weight
-    candidates.sort_by(|a, b| {
-        let a_reg = a.to_string_lossy().to_lowercase().contains("regular");
-        let b_reg = b.to_string_lossy().to_lowercase().contains("regular");
-        b_reg.cmp(&a_reg)
-    });
+/// Set in every child's environment so `find_orphans` can recognise
+/// processes we spawned after a crash left them behind.
+const SESSION_MARKER: &str = "NANOPROMPT_SESSION";
 
-    if let Some(path) = candidates.first() {
-        let data = std::fs::read(path).map_err(|e| e.to_string())?;
-        let b64 = base64_encode(&data);
-        let ext = match path.extension().and_then(|e| e.to_str()) {
-            Some("otf") => "opentype",
-            _ => "truetype",
-        };
-        return Ok(Some(format!("data:font/{};base64,{}", ext, b64)));
-    }
+/// The shell a session without an explicit program gets, resolved the way
+/// `CommandBuilder::new_default_prog` does: a usable `$SHELL`, else the
+/// login shell from the password database, else `/bin/sh`.
+#[cfg(unix)]
+#[tauri::command]
+fn default_shell() -> String {
+    // SAFETY: getpwuid returns null or a pointer to static storage, which we
+    // copy out of before anything else can call it
+    let login_shell = unsafe {
+        let pw = libc::getpwuid(libc::getuid());
+        (!pw.is_null() && !(*pw).pw_shell.is_null())
+            .then(|| std::ffi::CStr::from_ptr((*pw).pw_shell).to_string_lossy().into_owned())
+    };
+    pick_shell(std::env::var("SHELL").ok(), login_shell)
+}
 
-    Ok(None)
+/// The first of `env_shell` and `login_shell` that is executable, else `/bin/sh`.
+#[cfg(unix)]
+fn pick_shell(env_shell: Option<String>, login_shell: Option<String>) -> String {
+    env_shell
+        .into_iter()
+        .chain(login_shell)
+        .find(|shell| is_executable(std::path::Path::new(shell)))
+        .unwrap_or_else(|| "/bin/sh".into())
 }
 
-fn collect_fonts(dir: &std::path::Path, needle: &str, out: &mut Vec<std::path::PathBuf>) {
-    let Ok(entries) = std::fs::read_dir(dir) else { return };
-    for entry in entries.flatten() {
-        let path = entry.path();
-        if path.is_dir() {
-            collect_fonts(&path, needle, out);
-            continue;
+/// `%COMSPEC%` (normally cmd.exe), else Windows PowerShell.
+#[cfg(not(unix))]
+#[tauri::command]
+fn default_shell() -> String {
+    std::env::var("COMSPEC").unwrap_or_else(|_| {
+        let root = std::env::var("SystemRoot").unwrap_or_else(|_| "C:\\Windows".into());
+        format!("{}\\System32\\WindowsPowerShell\\v1.0\\powershell.exe", root)
+    })
+}
+
+#[cfg(unix)]
+fn is_executable(path: &std::path::Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    path.is_absolute()
+        && std::fs::metadata(path).is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+}
+
+fn build_command(options: &PtyOptions, size: PtyDimensions) -> CommandBuilder {
+    let mut cmd = match (&options.program, options.arg0.as_deref()) {
+        // CommandBuilder always passes the program path as argv[0], so an
+        // explicit arg0 goes through bash's `exec -a`
+        #[cfg(unix)]
+        (program, Some(arg0)) => {
+            let mut cmd = CommandBuilder::new("bash");
+            cmd.args(["-c", "exec -a \"$0\" \"$@\"", arg0]);
+            let shell = program.clone().unwrap_or_else(default_shell);
+            cmd.arg(&shell);
+            if options.quiet {
+                cmd.args(quiet_args(&shell));
+            } else if program.is_some() && options.login {
+                cmd.arg("-l");
+            }
+            if program.is_some() {
+                cmd.args(&options.args);
+            }
+            cmd
         }
-        let name = entry.file_name().to_string_lossy().to_lowercase();
-        if !(name.ends_with(".otf") || name.ends_with(".ttf") || name.ends_with(".ttc")) {
-            continue;
+        (Some(program), _) => {
+            let mut cmd = CommandBuilder::new(program);
+            if options.quiet {
+                cmd.args(quiet_args(program));
+            } else if options.login {
+                cmd.arg("-l");
+            }
+            cmd.args(&options.args);
+            cmd
         }
-        // Strip extension, then check if filename starts with needle
-        let stem = name.rsplitn(2, '.').last().unwrap_or(&name);
-        let stem_clean = stem.replace(['-', '_'], "");
-        if stem_clean.starts_with(needle) {
-            out.push(path);
+        (None, _) if options.quiet => {
+            let shell = default_shell();
+            let mut cmd = CommandBuilder::new(&shell);
+            cmd.args(quiet_args(&shell));
+            cmd
         }
+        (None, _) => CommandBuilder::new_default_prog(),
+    };
+    #[cfg(target_os = "linux")]
+    if options.uid.is_some() || options.gid.is_some() {
+        cmd = with_credentials(cmd, options);
+    }
+    if let Some(cwd) = &options.cwd {
+        cmd.cwd(expand_home(cwd));
+    }
+    if options.clear_env || options.inherit_env.is_some() {
+        cmd.env_clear();
+    }
+    for key in options.inherit_env.iter().flatten() {
+        if let Some(value) = std::env::var_os(key) {
+            cmd.env(key, value);
+        }
+    }
+    cmd.env("TERM", options.term.as_deref().unwrap_or("xterm-256color"));
+    cmd.env("COLORTERM", "truecolor");
+    cmd.env(SESSION_MARKER, "1");
+    // For programs that size themselves from these and ignore SIGWINCH;
+    // only the spawn size, later resizes don't update them
+    cmd.env("COLUMNS", size.cols.to_string());
+    cmd.env("LINES", size.rows.to_string());
+    if options.quiet {
+        // POSIX shells source $ENV when interactive
+        cmd.env_remove("ENV");
+        cmd.env("PS1", "");
     }
+    for (key, value) in &options.env {
+        cmd.env(key, value);
+    }
+    cmd
 }
 
-#[tauri::command]
-fn close_pty(state: State<'_, PtyState>, id: u32) -> Result<(), String> {
-    let mut sessions = state.sessions.lock().map_err(|e| e.to_string())?;
-    if let Some(mut session) = sessions.remove(&id) {
-        let _ = session.child.kill();
+/// Flags that keep a shell from reading its startup files, by basename:
+///
+/// - bash: `--norc --noprofile` (no .bashrc, .bash_profile or /etc/profile)
+/// - zsh: `-f` (NO_RCS: only /etc/zshenv is read)
+/// - fish: `--no-config`
+/// - sh, dash, ksh: no flag; `build_command` unsets `ENV` instead
+///
+/// Anything else gets no flags, just the blank `PS1`.
+fn quiet_args(program: &str) -> &'static [&'static str] {
+    let name = std::path::Path::new(program)
+        .file_stem()
+        .and_then(|n| n.to_str())
+        .unwrap_or_default();
+    match name {
+        "bash" => &["--norc", "--noprofile"],
+        "zsh" => &["-f"],
+        "fish" => &["--no-config"],
+        _ => &[],
+    }
+}
+
+/// Wrap `inner` in `setpriv` so it execs as `options.uid`/`options.gid`.
+#[cfg(target_os = "linux")]
+fn with_credentials(inner: CommandBuilder, options: &PtyOptions) -> CommandBuilder {
+    let mut cmd = CommandBuilder::new("setpriv");
+    if let Some(uid) = options.uid {
+        cmd.arg(format!("--reuid={}", uid));
+    }
+    if let Some(gid) = options.gid {
+        cmd.arg(format!("--regid={}", gid));
+    }
+    cmd.args(["--clear-groups", "--"]);
+    if inner.is_default_prog() {
+        cmd.arg(default_shell());
+        cmd.arg("-l");
+    } else {
+        cmd.args(inner.get_argv());
+    }
+    cmd
+}
+
+/// Switching user needs root unless the ids are already ours, and
+/// util-linux's `setpriv` (see `with_credentials`) either way.
+#[cfg(target_os = "linux")]
+fn check_credentials(options: &PtyOptions) -> Result<(), PtyError> {
+    if !program_exists("setpriv", None) {
+        return Err(PtyError::Spawn {
+            message: "uid/gid need util-linux's setpriv, which is not on PATH".into(),
+            os_error: None,
+        });
+    }
+    // SAFETY: these id getters have no preconditions and can't fail
+    let (euid, uid, gid) = unsafe { (libc::geteuid(), libc::getuid(), libc::getgid()) };
+    let changing = options.uid.is_some_and(|u| u != uid) || options.gid.is_some_and(|g| g != gid);
+    if changing && euid != 0 {
+        return Err(PtyError::Spawn {
+            message: "Spawning as another user requires root".into(),
+            os_error: Some(libc::EPERM),
+        });
     }
     Ok(())
 }
 
-#[tauri::command]
-fn open_config(app: AppHandle) -> Result<(), String> {
-    if let Some(window) = app.get_webview_window("config") {
-        let _ = window.set_focus();
-        return Ok(());
+#[cfg(not(target_os = "linux"))]
+fn check_credentials(_options: &PtyOptions) -> Result<(), PtyError> {
+    Err("uid/gid are only supported on Linux (they go through util-linux's setpriv)".to_string().into())
+}
+
+/// Catch a missing program before openpty, so the error names it rather
+/// than surfacing as a generic spawn failure.
+fn check_program(options: &PtyOptions) -> Result<(), PtyError> {
+    if let Some(program) = &options.program
+        && !program_exists(program, options.cwd.as_deref())
+    {
+        return Err(PtyError::ProgramNotFound {
+            program: program.clone(),
+        });
+    }
+    #[cfg(unix)]
+    if options.arg0.is_some() && !program_exists("bash", None) {
+        return Err(PtyError::Spawn {
+            message: "arg0 needs bash, which is not on PATH".into(),
+            os_error: None,
+        });
     }
-    tauri::WebviewWindowBuilder::new(
-        &app,
-        "config",
-        tauri::WebviewUrl::App("config.html".into()),
-    )
-    .title("Settings")
-    .inner_size(420.0, 520.0)
-    .resizable(false)
-    .build()
-    .map_err(|e| e.to_string())?;
     Ok(())
 }
 
+/// Without explicit `options`, the tab spawns from the calling window's
+/// profile (see `set_window_profile`), then the global default profile.
+/// Omitted `rows`/`cols` are estimated from the window (see `estimate_size`),
+/// omitted pixel dimensions are reported to the child as 0.
 #[tauri::command]
-fn close_window(app: AppHandle) {
-    if let Some(window) = app.get_webview_window("main") {
-        let _ = window.hide();
-    }
+#[allow(clippy::too_many_arguments)]
+fn create_pty(
+    app: AppHandle,
+    window: tauri::WebviewWindow,
+    state: State<'_, PtyState>,
+    rows: Option<u16>,
+    cols: Option<u16>,
+    options: Option<PtyOptions>,
+    pixel_width: Option<u16>,
+    pixel_height: Option<u16>,
+) -> Result<u32, PtyError> {
+    let mut options = options.unwrap_or_else(|| window_options(&app, &window));
+    options.pixel_size = (pixel_width.unwrap_or(0), pixel_height.unwrap_or(0));
+    create_in_window(&app, &window, &state, rows, cols, Some(options))
 }
 
+/// `create_pty` without blocking the command thread: the config read,
+/// openpty and exec run on a blocking task, so a slow cwd mount or program
+/// can't stall IPC. Resolves once the session is registered and emits
+/// `pty-started` `{ id }`.
 #[tauri::command]
-fn force_quit(app: AppHandle) {
-    let state = app.state::<PtyState>();
-    if let Ok(mut sessions) = state.sessions.lock() {
-        for (_, mut session) in sessions.drain() {
-            let _ = session.child.kill();
+async fn create_pty_async(
+    app: AppHandle,
+    window: tauri::WebviewWindow,
+    rows: Option<u16>,
+    cols: Option<u16>,
+    options: Option<PtyOptions>,
+    pixel_width: Option<u16>,
+    pixel_height: Option<u16>,
+) -> Result<u32, PtyError> {
+    let handle = app.clone();
+    let (id, namespace) = tauri::async_runtime::spawn_blocking(move || {
+        let mut options = options.unwrap_or_else(|| window_options(&handle, &window));
+        options.pixel_size = (pixel_width.unwrap_or(0), pixel_height.unwrap_or(0));
+        let namespace = options.event_namespace.clone();
+        create_in_window(&handle, &window, &handle.state::<PtyState>(), rows, cols, Some(options))
+            .map(|id| (id, namespace))
+    })
+    .await
+    .map_err(|e| e.to_string())??;
+    emit_session(&app, namespace.as_deref(), "pty-started", serde_json::json!({ "id": id }));
+    Ok(id)
+}
+
+fn create_in_window(
+    app: &AppHandle,
+    window: &tauri::WebviewWindow,
+    state: &PtyState,
+    rows: Option<u16>,
+    cols: Option<u16>,
+    options: Option<PtyOptions>,
+) -> Result<u32, PtyError> {
+    let (rows, cols) = match (rows, cols) {
+        (Some(rows), Some(cols)) => (rows, cols),
+        _ => {
+            let estimate = estimate_size(window);
+            (rows.unwrap_or(estimate.rows), cols.unwrap_or(estimate.cols))
         }
+    };
+    let mut options = options.unwrap_or_else(|| window_options(app, window));
+    options.window = Some(window.label().to_string());
+    spawn_session(app, state, rows, cols, options)
+}
+
+/// Spawn options for a new tab in `window`, from its profile if it has one.
+fn window_options(app: &AppHandle, window: &tauri::WebviewWindow) -> PtyOptions {
+    config::load(app)
+        .profile_for_window(window.label())
+        .map(PtyOptions::from)
+        .unwrap_or_default()
+}
+
+#[tauri::command]
+fn create_pty_from_profile(
+    app: AppHandle,
+    window: tauri::WebviewWindow,
+    state: State<'_, PtyState>,
+    rows: u16,
+    cols: u16,
+    profile_name: String,
+) -> Result<u32, PtyError> {
+    let config = config::read(&app)?;
+    let profile = config
+        .profiles
+        .iter()
+        .find(|p| p.name == profile_name)
+        .ok_or_else(|| format!("Profile not found: {}", profile_name))?;
+    let mut options = PtyOptions::from(profile);
+    options.window = Some(window.label().to_string());
+    spawn_session(&app, &state, rows, cols, options)
+}
+
+const DEFAULT_TMUX_SESSION: &str = "nanoprompt";
+
+/// Attach to tmux session `session_name` (default `nanoprompt`), creating it
+/// if it doesn't exist. Works from inside tmux too: the app's own `TMUX` is
+/// blanked so tmux doesn't refuse to nest.
+#[tauri::command]
+fn create_tmux_pty(
+    app: AppHandle,
+    window: tauri::WebviewWindow,
+    state: State<'_, PtyState>,
+    rows: Option<u16>,
+    cols: Option<u16>,
+    session_name: Option<String>,
+) -> Result<u32, PtyError> {
+    if !program_exists("tmux", None) {
+        return Err(PtyError::ProgramNotFound { program: "tmux".into() });
     }
-    app.exit(0);
+    let options = tmux_options(session_name)?;
+    create_in_window(&app, &window, &state, rows, cols, Some(options))
 }
 
-fn has_running_sessions(app: &AppHandle) -> bool {
-    let state = app.state::<PtyState>();
-    let sessions = state.sessions.lock().unwrap();
-    sessions.values().any(|s| !s.exited.load(Ordering::Relaxed))
+/// Options that run `tmux new-session -A` for `session_name`.
+fn tmux_options(session_name: Option<String>) -> Result<PtyOptions, String> {
+    let name = session_name.unwrap_or_else(|| DEFAULT_TMUX_SESSION.into());
+    // tmux turns these into its target separators
+    if name.is_empty() || name.contains([':', '.']) {
+        return Err(format!("Invalid tmux session name {:?}", name));
+    }
+    Ok(PtyOptions {
+        program: Some("tmux".into()),
+        args: vec!["new-session".into(), "-A".into(), "-s".into(), name],
+        env: HashMap::from([("TMUX".into(), String::new())]),
+        ..Default::default()
+    })
 }
 
-#[cfg_attr(mobile, tauri::mobile_entry_point)]
-pub fn run() {
-    let app = tauri::Builder::default()
+fn spawn_session(
+    app: &AppHandle,
+    state: &PtyState,
+    rows: u16,
+    cols: u16,
+    mut options: PtyOptions,
+) -> Result<u32, PtyError> {
+    let config = config::load(app);
+    let slot = reserve_slot(state, config.max_sessions)?;
+    if options.cwd.is_none() {
+        options.cwd = existing_cwd(config.default_cwd);
+    }
+    add_global_env(&mut options, config.global_env);
+    let size = PtyDimensions {
+        pixel_width: options.pixel_size.0,
+        pixel_height: options.pixel_size.1,
+        ..normalize_size(rows, cols, true)
+    };
+    let id = state.next_id.fetch_add(1, Ordering::Relaxed);
+    ids::reserve(app, id.wrapping_add(1));
+    let session = open_session(app, id, size, options)
+        .inspect_err(|e| log::warn!("session {}: spawn failed: {}", id, e))?;
+
+    let mut sessions = state.sessions.lock().map_err(|e| e.to_string())?;
+    sessions.insert(id, session);
+    // Under the lock, so a concurrent `reserve_slot` counts it exactly once
+    drop(slot);
+
+    Ok(id)
+}
+
+/// Layer `global` beneath the session's (or its profile's) own env.
+fn add_global_env(options: &mut PtyOptions, global: HashMap<String, String>) {
+    for (key, value) in global {
+        options.env.entry(key).or_insert(value);
+    }
+}
+
+/// Claim a slot for a new session, refusing if `limit` (0 for none) are
+/// already running or being spawned. Exited sessions kept by
+/// `hold_on_exit` don't count. The slot is given back when dropped, which
+/// the caller does once the session is inserted or has failed to spawn.
+fn reserve_slot(state: &PtyState, limit: usize) -> Result<SlotReservation<'_>, PtyError> {
+    let sessions = state.sessions.lock().map_err(|e| e.to_string())?;
+    if limit != 0 {
+        let running = sessions.values().filter(|s| !s.exited.load(Ordering::Relaxed)).count();
+        if running + state.reserved.load(Ordering::Relaxed) >= limit {
+            return Err(PtyError::LimitReached { limit });
+        }
+    }
+    state.reserved.fetch_add(1, Ordering::Relaxed);
+    Ok(SlotReservation(&state.reserved))
+}
+
+struct SlotReservation<'a>(&'a AtomicUsize);
+
+impl Drop for SlotReservation<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// The configured `default_cwd`, if it still exists. A stale one is logged
+/// and ignored so the session starts in `$HOME` instead of failing.
+fn existing_cwd(default_cwd: Option<String>) -> Option<String> {
+    let cwd = default_cwd?;
+    if expand_home(&cwd).is_dir() {
+        Some(cwd)
+    } else {
+        log::warn!("default cwd {} does not exist, using home", cwd);
+        None
+    }
+}
+
+fn check_namespace(ns: &str) -> Result<(), String> {
+    // Tauri only accepts these characters in event names
+    if ns.is_empty() || !ns.chars().all(|c| c.is_ascii_alphanumeric() || "-/_".contains(c)) {
+        return Err(format!("Invalid event namespace: {:?}", ns));
+    }
+    Ok(())
+}
+
+/// Open a PTY, spawn the child `options` describes and start a reader thread
+/// emitting under `id`. The caller registers the returned session.
+fn open_session(
+    app: &AppHandle,
+    id: u32,
+    size: PtyDimensions,
+    options: PtyOptions,
+) -> Result<PtySession, PtyError> {
+    if let Some(ns) = &options.event_namespace {
+        check_namespace(ns)?;
+    }
+    if options.uid.is_some() || options.gid.is_some() {
+        check_credentials(&options)?;
+    }
+    let pty_system = pty_system_for(options.backend.as_deref())?;
+    check_program(&options)?;
+
+    let output_filter = options
+        .output_filter
+        .as_deref()
+        .map(spawn_output_filter)
+        .transpose()?;
+
+    let pair = pty_system
+        .openpty(size.into())
+        .map_err(|e| PtyError::OpenPty {
+            os_error: os_error(&*e),
+            // ConPTY needs Windows 10 1809+, so name the backend when it fails
+            message: match &options.backend {
+                Some(backend) => format!("{} backend unavailable: {}", backend, e),
+                None => e.to_string(),
+            },
+        })?;
+
+    let cmd = build_command(&options, size);
+    let child = pair.slave.spawn_command(cmd).map_err(|e| PtyError::Spawn {
+        os_error: os_error(&*e),
+        message: e.to_string(),
+    })?;
+
+    log::info!(
+        "session {}: spawned pid {:?} ({}, {}x{})",
+        id,
+        child.process_id(),
+        options.program.as_deref().unwrap_or("default shell"),
+        size.cols,
+        size.rows
+    );
+
+    // Drop slave so we get EOF when the child exits
+    drop(pair.slave);
+
+    let master = pair.master;
+    let writer = master.take_writer().map_err(|e| PtyError::TakeWriter {
+        os_error: os_error(&*e),
+        message: e.to_string(),
+    })?;
+    let mut reader = master.try_clone_reader().map_err(|e| PtyError::CloneReader {
+        os_error: os_error(&*e),
+        message: e.to_string(),
+    })?;
+
+    #[cfg(unix)]
+    let tty_name = master.tty_name().map(|p| p.to_string_lossy().into_owned());
+    #[cfg(not(unix))]
+    let tty_name = None;
+
+    let exited = Arc::new(AtomicBool::new(false));
+    let superseded = Arc::new(AtomicBool::new(false));
+    let bytes_in = Arc::new(AtomicU64::new(0));
+    let last_activity = Arc::new(AtomicU64::new(0));
+
+    let buffer_size = options
+        .buffer_size
+        .unwrap_or(DEFAULT_BUFFER_SIZE)
+        .clamp(1, MAX_BUFFER_SIZE);
+
+    let namespace = options.event_namespace.clone();
+    let window_label = options.window.clone();
+    let throttle = (options.max_events_per_sec > 0)
+        .then(|| spawn_throttled_emitter(app.clone(), namespace.clone(), id, options.max_events_per_sec));
+
+    // Spawn reader thread
+    let app_handle = app.clone();
+    let exited_flag = exited.clone();
+    let superseded_flag = superseded.clone();
+    let bytes_read = bytes_in.clone();
+    let activity = last_activity.clone();
+    let hold_on_exit = options.hold_on_exit;
+    let modes = Arc::new(TermModes::default());
+    let session_modes = modes.clone();
+    let tap: Tap = Arc::new(Mutex::new(None));
+    let session_tap = tap.clone();
+    let recording: Recording = Arc::new(Mutex::new(None));
+    let session_recording = recording.clone();
+    let scrollback_limit = options.scrollback_bytes.map(|n| n.min(MAX_SCROLLBACK_BYTES));
+    let scrollback: Option<Scrollback> = scrollback_limit.map(|_| Arc::new(Mutex::new(VecDeque::new())));
+    let session_scrollback = scrollback.clone();
+    let command_blocks = Arc::new(blocks::CommandBlocks::default());
+    let session_blocks = command_blocks.clone();
+    let mirrors: Arc<Mutex<Vec<String>>> = Arc::new(Mutex::new(Vec::new()));
+    let session_mirrors = mirrors.clone();
+    let pty_log = match ptylog::PtyLog::from_env(id, || config::load(app).pty_log_max_bytes) {
+        Some(Ok(pty_log)) => Some(Arc::new(Mutex::new(pty_log))),
+        Some(Err(e)) => {
+            log::warn!("session {}: PTY log disabled: {}", id, e);
+            None
+        }
+        None => None,
+    };
+    let session_pty_log = pty_log.clone();
+    let filter_stdin = output_filter
+        .as_ref()
+        .map(|filter| filter.stdin.clone())
+        .unwrap_or_default();
+    let init_output = options.capture_init.then(|| Arc::new(Mutex::new(Vec::new())));
+    let mut init_capture = init_output.clone();
+    let spawned = Instant::now();
+    let capture: Capture = Arc::new(Mutex::new(None));
+    let session_capture = capture.clone();
+    let mut password_watch = options
+        .detect_password_prompts
+        .then(|| password::PromptWatch::new(&config::load(app).password_prompts));
+    std::thread::spawn(move || {
+        let mut buf = vec![0u8; buffer_size];
+        let mut parser = Parser::default();
+        loop {
+            match reader.read(&mut buf) {
+                Ok(0) => break,
+                Err(e) => {
+                    log::debug!("session {}: read ended: {}", id, e);
+                    break;
+                }
+                Ok(n) => {
+                    bytes_read.fetch_add(n as u64, Ordering::Relaxed);
+                    activity.store(epoch_millis(), Ordering::Relaxed);
+                    let mut prompt_seen = false;
+                    parser.feed(&buf[..n], |seq, span| {
+                        session_blocks.apply(&seq, span);
+                        session_modes.apply(&seq, |change| {
+                            if let ModeChange::Cwd(dir) = &change {
+                                prompt_seen = true;
+                                if let Some(recent) = app_handle.try_state::<recent::RecentDirs>() {
+                                    recent.visit(&app_handle, dir);
+                                }
+                            }
+                            if let (ModeChange::Title(title), Some(label)) = (&change, &window_label) {
+                                apply_shell_title(&app_handle, label, title);
+                            }
+                            emit_mode_change(&app_handle, namespace.as_deref(), id, change)
+                        });
+                    });
+                    match &throttle {
+                        Some((tx, _)) => {
+                            let _ = tx.send(buf[..n].to_vec());
+                        }
+                        None => emit_output(&app_handle, namespace.as_deref(), id, &buf[..n]),
+                    }
+                    if let Some(active) = password_watch.as_mut().and_then(|w| w.feed(&buf[..n])) {
+                        emit_session(&app_handle, namespace.as_deref(), "pty-password-prompt", serde_json::json!({
+                            "id": id,
+                            "active": active
+                        }));
+                    }
+                    if let Ok(mut filter) = filter_stdin.lock()
+                        && let Some(stdin) = filter.as_mut()
+                        && !write_tap(stdin.as_mut(), &buf[..n])
+                    {
+                        *filter = None;
+                        log::warn!("session {}: output filter stopped reading", id);
+                        emit_session(
+                            &app_handle,
+                            namespace.as_deref(),
+                            "pty-filter-ended",
+                            serde_json::json!({ "id": id }),
+                        );
+                    }
+                    if let Some(pty_log) = &session_pty_log
+                        && let Ok(mut pty_log) = pty_log.lock()
+                        && let Err(e) = pty_log.write(&buf[..n])
+                    {
+                        log::warn!("session {}: PTY log: {}", id, e);
+                    }
+                    send_to_mirrors(&session_mirrors, id, &buf[..n], |label, payload| {
+                        let _ = app_handle.emit_to(label, "pty-mirror-output", payload);
+                    });
+                    if let Some(tx) = session_capture.lock().ok().and_then(|c| c.clone()) {
+                        let _ = tx.send(buf[..n].to_vec());
+                    }
+                    if let Ok(mut tap) = session_tap.lock()
+                        && let Some(target) = tap.as_mut()
+                        && !write_tap(target.as_mut(), &buf[..n])
+                    {
+                        *tap = None;
+                        emit_session(
+                            &app_handle,
+                            namespace.as_deref(),
+                            "pty-tap-ended",
+                            serde_json::json!({ "id": id }),
+                        );
+                    }
+                    if let Some(init) = &init_capture {
+                        if let Ok(mut init) = init.lock() {
+                            let room = INIT_CAPTURE_MAX.saturating_sub(init.len());
+                            init.extend_from_slice(&buf[..n.min(room)]);
+                        }
+                        if prompt_seen || spawned.elapsed() >= INIT_CAPTURE_WINDOW {
+                            init_capture = None;
+                        }
+                    }
+                    if let (Some(scrollback), Some(limit)) = (&session_scrollback, scrollback_limit)
+                        && let Ok(mut scrollback) = scrollback.lock()
+                    {
+                        scrollback.extend(&buf[..n]);
+                        let excess = scrollback.len().saturating_sub(limit);
+                        scrollback.drain(..excess);
+                        session_blocks.scrollback_end.store(parser.offset(), Ordering::Relaxed);
+                    }
+                    if let Ok(mut recording) = session_recording.lock()
+                        && let Some(recorder) = recording.as_mut()
+                        && let Err(e) = recorder.output(&buf[..n])
+                    {
+                        log::warn!("session {}: recording stopped: {}", id, e);
+                        *recording = None;
+                    }
+                }
+            }
+        }
+        // Let the emitter flush what it's holding so output lands before the exit
+        if let Some((tx, handle)) = throttle {
+            drop(tx);
+            let _ = handle.join();
+        }
+        if let Some(recorder) = session_recording.lock().ok().and_then(|mut r| r.take()) {
+            let _ = recorder.finish();
+        }
+        if let Some(mut pty_log) = session_pty_log.as_ref().and_then(|l| l.lock().ok()) {
+            pty_log.flush();
+        }
+        // A restarted session lives on under the same id, so stay quiet
+        if superseded_flag.load(Ordering::Relaxed) {
+            return;
+        }
+        // A failed write may already have reported the exit
+        let reported = exited_flag.swap(true, Ordering::Relaxed);
+        let code = reap_exit_code(&app_handle, id);
+        log::info!(
+            "session {}: exited with code {:?} after {} bytes",
+            id,
+            code,
+            bytes_read.load(Ordering::Relaxed)
+        );
+        if !hold_on_exit
+            && let Ok(mut sessions) = app_handle.state::<PtyState>().sessions.lock()
+        {
+            sessions.remove(&id);
+        }
+        if reported {
+            return;
+        }
+        emit_session(&app_handle, namespace.as_deref(), "pty-exit", serde_json::json!({
+            "id": id,
+            "code": code
+        }));
+    });
+
+    if let Some(timeout) = options.idle_timeout_ms {
+        spawn_idle_watch(app.clone(), id, Duration::from_millis(timeout), &last_activity, &exited);
+    }
+    #[cfg(unix)]
+    if options.watch_foreground {
+        spawn_foreground_watch(app.clone(), id, options.event_namespace.clone(), &exited);
+    }
+
+    Ok(PtySession {
+        master,
+        writer,
+        child,
+        exited,
+        buffer_size,
+        bytes_in,
+        bytes_out: AtomicU64::new(0),
+        last_activity,
+        tty_name,
+        options,
+        superseded,
+        cpu_sample: None,
+        modes,
+        tap,
+        recording,
+        scrollback,
+        command_blocks,
+        mirrors,
+        pty_log,
+        output_filter,
+        init_output,
+        capture,
+        input_enabled: true,
+        drop_locked_input: false,
+        write_cancelled: false,
+        metadata: None,
+    })
+}
+
+const FILTER_GRACE: Duration = Duration::from_millis(500);
+
+/// A session's `output_filter` process. Dropping it closes the filter's
+/// stdin and kills it if it hasn't exited after `FILTER_GRACE`.
+struct OutputFilter {
+    child: Option<std::process::Child>,
+    /// Shared with the reader, which writes output here
+    stdin: Tap,
+}
+
+impl Drop for OutputFilter {
+    fn drop(&mut self) {
+        if let Ok(mut stdin) = self.stdin.lock() {
+            stdin.take();
+        }
+        let Some(mut child) = self.child.take() else { return };
+        std::thread::spawn(move || {
+            let deadline = Instant::now() + FILTER_GRACE;
+            while Instant::now() < deadline {
+                if let Ok(Some(_)) = child.try_wait() {
+                    return;
+                }
+                std::thread::sleep(CLOSE_POLL);
+            }
+            let _ = child.kill();
+            let _ = child.wait();
+        });
+    }
+}
+
+fn spawn_output_filter(argv: &[String]) -> Result<OutputFilter, PtyError> {
+    let (program, args) = argv.split_first().ok_or_else(|| "Output filter needs a program".to_string())?;
+    if !program_exists(program, None) {
+        return Err(PtyError::ProgramNotFound { program: program.clone() });
+    }
+    let mut child = std::process::Command::new(expand_home(program))
+        .args(args)
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::null())
+        .spawn()
+        .map_err(|e| format!("Failed to start output filter: {}", e))?;
+    let stdin = child.stdin.take().ok_or_else(|| "Output filter has no stdin".to_string())?;
+    #[cfg(unix)]
+    {
+        use std::os::fd::AsRawFd;
+        // SAFETY: adjusting flags on a pipe fd we own
+        unsafe {
+            let fd = stdin.as_raw_fd();
+            let flags = libc::fcntl(fd, libc::F_GETFL);
+            libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK);
+        }
+    }
+    let stdin: Box<dyn Write + Send> = Box::new(stdin);
+    Ok(OutputFilter {
+        child: Some(child),
+        stdin: Arc::new(Mutex::new(Some(stdin))),
+    })
+}
+
+/// Open a FIFO or Unix socket for writing without blocking the reader: a
+/// FIFO with no reader fails here instead of hanging.
+#[cfg(unix)]
+fn open_tap(path: &str) -> Result<Box<dyn Write + Send>, String> {
+    use std::os::unix::fs::{FileTypeExt, OpenOptionsExt};
+    let path = expand_home(path);
+    let file_type = std::fs::metadata(&path).map_err(|e| e.to_string())?.file_type();
+    if file_type.is_socket() {
+        let stream = std::os::unix::net::UnixStream::connect(&path).map_err(|e| e.to_string())?;
+        stream.set_nonblocking(true).map_err(|e| e.to_string())?;
+        Ok(Box::new(stream))
+    } else if file_type.is_fifo() {
+        let fifo = std::fs::OpenOptions::new()
+            .write(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(&path)
+            .map_err(|e| e.to_string())?;
+        Ok(Box::new(fifo))
+    } else {
+        Err(format!("{} is not a FIFO or socket", path.display()))
+    }
+}
+
+#[cfg(not(unix))]
+fn open_tap(_path: &str) -> Result<Box<dyn Write + Send>, String> {
+    Err("Output taps are only supported on Unix".into())
+}
+
+/// Write a chunk to a tap. A consumer that can't keep up loses data rather
+/// than stalling the terminal; returns false once the target is gone.
+fn write_tap(target: &mut dyn Write, mut data: &[u8]) -> bool {
+    while !data.is_empty() {
+        match target.write(data) {
+            Ok(0) => return false,
+            Ok(n) => data = &data[n..],
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => return true,
+            Err(_) => return false,
+        }
+    }
+    true
+}
+
+/// Copy session `id`'s raw output to the FIFO or Unix socket at `target`
+/// as well as the frontend. Replaces any existing tap.
+#[tauri::command]
+fn tap_pty(state: State<'_, PtyState>, id: u32, target: String) -> Result<(), String> {
+    let tap = {
+        let sessions = state.sessions.lock().map_err(|e| e.to_string())?;
+        sessions.get(&id).ok_or("Session not found")?.tap.clone()
+    };
+    let writer = open_tap(&target)?;
+    *tap.lock().map_err(|e| e.to_string())? = Some(writer);
+    Ok(())
+}
+
+#[tauri::command]
+fn untap_pty(state: State<'_, PtyState>, id: u32) -> Result<(), String> {
+    let sessions = state.sessions.lock().map_err(|e| e.to_string())?;
+    let session = sessions.get(&id).ok_or("Session not found")?;
+    *session.tap.lock().map_err(|e| e.to_string())? = None;
+    Ok(())
+}
+
+/// Also send session `source_id`'s output to window `target_window`, as
+/// `pty-mirror-output` `{ id, data }` events only it receives, for a
+/// read-only viewer. Input stays with the source. Mirrors end on
+/// `unmirror_pty` or when the target window closes.
+#[tauri::command]
+fn mirror_pty(app: AppHandle, state: State<'_, PtyState>, source_id: u32, target_window: String) -> Result<(), String> {
+    if app.get_webview_window(&target_window).is_none() {
+        return Err(format!("Window not found: {}", target_window));
+    }
+    let sessions = state.sessions.lock().map_err(|e| e.to_string())?;
+    add_mirror(sessions.get(&source_id).ok_or("Session not found")?, target_window)
+}
+
+fn add_mirror(session: &PtySession, label: String) -> Result<(), String> {
+    let mut mirrors = session.mirrors.lock().map_err(|e| e.to_string())?;
+    if !mirrors.contains(&label) {
+        mirrors.push(label);
+    }
+    Ok(())
+}
+
+/// Hand a chunk of output to `emit` once per mirror target, by label.
+fn send_to_mirrors(mirrors: &Mutex<Vec<String>>, id: u32, data: &[u8], mut emit: impl FnMut(&str, serde_json::Value)) {
+    let Ok(mirrors) = mirrors.lock() else { return };
+    if mirrors.is_empty() {
+        return;
+    }
+    let data = base64_encode(data);
+    for label in mirrors.iter() {
+        emit(label, serde_json::json!({ "id": id, "data": data }));
+    }
+}
+
+/// Stop every mirror of session `source_id`.
+#[tauri::command]
+fn unmirror_pty(state: State<'_, PtyState>, source_id: u32) -> Result<(), String> {
+    let sessions = state.sessions.lock().map_err(|e| e.to_string())?;
+    let session = sessions.get(&source_id).ok_or("Session not found")?;
+    session.mirrors.lock().map_err(|e| e.to_string())?.clear();
+    Ok(())
+}
+
+/// Drop window `label` from every session's mirrors once it's gone.
+fn remove_mirror_target(app: &AppHandle, label: &str) {
+    forget_mirror_target(&app.state::<PtyState>(), label);
+}
+
+fn forget_mirror_target(state: &PtyState, label: &str) {
+    let Ok(sessions) = state.sessions.lock() else { return };
+    for session in sessions.values() {
+        if let Ok(mut mirrors) = session.mirrors.lock() {
+            mirrors.retain(|m| m != label);
+        }
+    }
+}
+
+/// Start a fresh `NANOPROMPT_PTY_LOG` file for session `id`, returning the
+/// path the old one was renamed to. Logs also rotate by themselves past
+/// the configured `ptyLogMaxBytes`.
+#[tauri::command]
+fn rotate_pty_log(state: State<'_, PtyState>, id: u32) -> Result<String, String> {
+    let pty_log = {
+        let sessions = state.sessions.lock().map_err(|e| e.to_string())?;
+        let session = sessions.get(&id).ok_or("Session not found")?;
+        session.pty_log.clone().ok_or("PTY logging is off (set NANOPROMPT_PTY_LOG)")?
+    };
+    let rotated = pty_log.lock().map_err(|e| e.to_string())?.rotate()?;
+    Ok(rotated.to_string_lossy().into_owned())
+}
+
+/// Start an asciicast v2 recording of session `id` at `path`, replacing any
+/// recording already running for it.
+#[tauri::command]
+fn start_recording(state: State<'_, PtyState>, id: u32, path: String) -> Result<(), String> {
+    let sessions = state.sessions.lock().map_err(|e| e.to_string())?;
+    let session = sessions.get(&id).ok_or("Session not found")?;
+    begin_recording(session, &expand_home(&path), false)
+}
+
+#[tauri::command]
+fn stop_recording(state: State<'_, PtyState>, id: u32) -> Result<(), String> {
+    let recorder = {
+        let sessions = state.sessions.lock().map_err(|e| e.to_string())?;
+        let session = sessions.get(&id).ok_or("Session not found")?;
+        let mut recording = session.recording.lock().map_err(|e| e.to_string())?;
+        recording.take().ok_or("Session is not being recorded")?
+    };
+    recorder.finish().map_err(|e| e.to_string())
+}
+
+#[derive(Serialize)]
+struct RecordingBatch {
+    /// Files created, one per session
+    files: Vec<String>,
+    /// Sessions that couldn't be recorded, by id
+    errors: HashMap<u32, String>,
+}
+
+/// Record every live session into `<dir>/<id>.cast`, creating `dir` if
+/// needed. Sessions already being recorded individually are left alone; a
+/// session that fails to start is reported without stopping the rest.
+#[tauri::command]
+fn start_recording_all(state: State<'_, PtyState>, dir: String) -> Result<RecordingBatch, String> {
+    record_all(&state, &expand_home(&dir))
+}
+
+fn record_all(state: &PtyState, dir: &std::path::Path) -> Result<RecordingBatch, String> {
+    std::fs::create_dir_all(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+    let sessions = state.sessions.lock().map_err(|e| e.to_string())?;
+    let mut batch = RecordingBatch {
+        files: Vec::new(),
+        errors: HashMap::new(),
+    };
+    for (&id, session) in sessions.iter() {
+        if session.exited.load(Ordering::Relaxed) {
+            continue;
+        }
+        let busy = session.recording.lock().map(|r| r.is_some()).unwrap_or(true);
+        if busy {
+            continue;
+        }
+        let path = dir.join(format!("{}.cast", id));
+        match begin_recording(session, &path, true) {
+            Ok(()) => batch.files.push(path.to_string_lossy().into_owned()),
+            Err(e) => {
+                batch.errors.insert(id, e);
+            }
+        }
+    }
+    Ok(batch)
+}
+
+/// Stop the recordings `start_recording_all` started, returning their files.
+/// Individually started recordings keep running.
+#[tauri::command]
+fn stop_recording_all(state: State<'_, PtyState>) -> Result<Vec<String>, String> {
+    stop_batch(&state)
+}
+
+fn stop_batch(state: &PtyState) -> Result<Vec<String>, String> {
+    let recorders: Vec<recording::Recorder> = {
+        let sessions = state.sessions.lock().map_err(|e| e.to_string())?;
+        sessions
+            .values()
+            .filter_map(|session| {
+                let mut recording = session.recording.lock().ok()?;
+                if recording.as_ref()?.batch { recording.take() } else { None }
+            })
+            .collect()
+    };
+    let mut files = Vec::new();
+    for recorder in recorders {
+        let path = recorder.path.to_string_lossy().into_owned();
+        if let Err(e) = recorder.finish() {
+            log::warn!("{}: {}", path, e);
+        }
+        files.push(path);
+    }
+    Ok(files)
+}
+
+fn begin_recording(session: &PtySession, path: &std::path::Path, batch: bool) -> Result<(), String> {
+    let size = session.master.get_size().map_err(|e| e.to_string())?;
+    let recorder = recording::Recorder::create(path, size.cols, size.rows, batch)?;
+    let previous = session.recording.lock().map_err(|e| e.to_string())?.replace(recorder);
+    if let Some(previous) = previous {
+        let _ = previous.finish();
+    }
+    Ok(())
+}
+
+const IDLE_CHECK_MAX: Duration = Duration::from_secs(1);
+
+/// Close session `id` once it has gone `timeout` without output or input.
+/// Stops watching when the session exits or is replaced by a restart.
+fn spawn_idle_watch(
+    app: AppHandle,
+    id: u32,
+    timeout: Duration,
+    last_activity: &Arc<AtomicU64>,
+    exited: &Arc<AtomicBool>,
+) {
+    let last_activity = last_activity.clone();
+    let exited = exited.clone();
+    std::thread::spawn(move || {
+        let Some(session) = wait_until_idle(&app.state(), id, timeout, &last_activity, &exited) else {
+            return;
+        };
+        log::info!("session {}: idle for {}ms, closing", id, timeout.as_millis());
+        emit_session(
+            &app,
+            session.options.event_namespace.as_deref(),
+            "pty-exit",
+            serde_json::json!({ "id": id, "code": null, "reason": "idle-timeout" }),
+        );
+        end_child(session, "eof");
+    });
+}
+
+/// Block until session `id` has been idle for `timeout`, then take it out
+/// of `state` and claim its exit. `None` if it exits or is replaced first.
+fn wait_until_idle(
+    state: &PtyState,
+    id: u32,
+    timeout: Duration,
+    last_activity: &AtomicU64,
+    exited: &Arc<AtomicBool>,
+) -> Option<PtySession> {
+    let started = epoch_millis();
+    let timeout_ms = timeout.as_millis() as u64;
+    loop {
+        std::thread::sleep(timeout.min(IDLE_CHECK_MAX));
+        if exited.load(Ordering::Relaxed) {
+            return None;
+        }
+        let last = last_activity.load(Ordering::Relaxed).max(started);
+        if epoch_millis().saturating_sub(last) >= timeout_ms {
+            break;
+        }
+    }
+    let session = {
+        let mut sessions = state.sessions.lock().ok()?;
+        // A restart swaps in a session with its own flags and watcher
+        if !sessions.get(&id).is_some_and(|s| Arc::ptr_eq(&s.exited, exited)) {
+            return None;
+        }
+        sessions.remove(&id)
+    };
+    // Claim the exit so the reader doesn't report it a second time
+    exited.store(true, Ordering::Relaxed);
+    session
+}
+
+const FOREGROUND_POLL: Duration = Duration::from_millis(250);
+/// Polls a new foreground group must last before it's reported, so a shell
+/// briefly forking a pipeline doesn't flicker the title
+const FOREGROUND_SETTLE: u32 = 2;
+
+/// Poll the terminal's foreground process group and emit
+/// `pty-foreground-changed` `{ id, pid, name }` when it settles on a new one,
+/// starting with the shell itself.
+#[cfg(unix)]
+fn spawn_foreground_watch(app: AppHandle, id: u32, namespace: Option<String>, exited: &Arc<AtomicBool>) {
+    let exited = exited.clone();
+    std::thread::spawn(move || {
+        let mut current = None;
+        let mut pending = None;
+        let mut seen = 0;
+        loop {
+            std::thread::sleep(FOREGROUND_POLL);
+            if exited.load(Ordering::Relaxed) {
+                return;
+            }
+            let leader = {
+                let state = app.state::<PtyState>();
+                let Ok(sessions) = state.sessions.lock() else { return };
+                match sessions.get(&id) {
+                    Some(session) if Arc::ptr_eq(&session.exited, &exited) => session.master.process_group_leader(),
+                    // Gone, or replaced by a restart with its own watcher
+                    _ => return,
+                }
+            };
+            let Some(leader) = leader.and_then(|pid| u32::try_from(pid).ok()) else { continue };
+            if current == Some(leader) {
+                pending = None;
+                continue;
+            }
+            if pending == Some(leader) {
+                seen += 1;
+            } else {
+                pending = Some(leader);
+                seen = 1;
+            }
+            if seen < FOREGROUND_SETTLE {
+                continue;
+            }
+            current = pending.take();
+            emit_session(&app, namespace.as_deref(), "pty-foreground-changed", serde_json::json!({
+                "id": id,
+                "pid": leader,
+                "name": procinfo::name(leader)
+            }));
+        }
+    });
+}
+
+fn epoch_millis() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// Total `(bytes read, bytes written)` for session `id`. A restart starts
+/// both from zero.
+#[tauri::command]
+fn get_pty_counters(state: State<'_, PtyState>, id: u32) -> Result<(u64, u64), String> {
+    let sessions = state.sessions.lock().map_err(|e| e.to_string())?;
+    let session = sessions.get(&id).ok_or("Session not found")?;
+    Ok((
+        session.bytes_in.load(Ordering::Relaxed),
+        session.bytes_out.load(Ordering::Relaxed),
+    ))
+}
+
+fn session_scrollback(state: &PtyState, id: u32) -> Result<Scrollback, String> {
+    let sessions = state.sessions.lock().map_err(|e| e.to_string())?;
+    let session = sessions.get(&id).ok_or("Session not found")?;
+    session
+        .scrollback
+        .clone()
+        .ok_or_else(|| "Session has no scrollback buffer (see scrollbackBytes)".into())
+}
+
+/// What the shell printed while starting up (see `capture_init`), decoded
+/// lossily. `None` when the session wasn't spawned with `capture_init`.
+#[tauri::command]
+fn get_init_output(state: State<'_, PtyState>, id: u32) -> Result<Option<String>, String> {
+    let sessions = state.sessions.lock().map_err(|e| e.to_string())?;
+    let session = sessions.get(&id).ok_or("Session not found")?;
+    let Some(init) = &session.init_output else { return Ok(None) };
+    let init = init.lock().map_err(|e| e.to_string())?;
+    Ok(Some(String::from_utf8_lossy(&init).into_owned()))
+}
+
+/// The session's buffered output, decoded lossily.
+#[tauri::command]
+fn get_scrollback(state: State<'_, PtyState>, id: u32) -> Result<String, String> {
+    let scrollback = session_scrollback(&state, id)?;
+    let scrollback = scrollback.lock().map_err(|e| e.to_string())?;
+    let (front, back) = scrollback.as_slices();
+    Ok(String::from_utf8_lossy(&[front, back].concat()).into_owned())
+}
+
+/// Write the session's buffered output to `path` as a standalone HTML page,
+/// colored with the active theme, for bug reports and the like.
+#[tauri::command]
+fn export_scrollback_html(app: AppHandle, state: State<'_, PtyState>, id: u32, path: String) -> Result<(), String> {
+    let data = {
+        let scrollback = session_scrollback(&state, id)?;
+        let scrollback = scrollback.lock().map_err(|e| e.to_string())?;
+        let (front, back) = scrollback.as_slices();
+        [front, back].concat()
+    };
+    let config = config::load(&app);
+    let mut themes = config.all_themes();
+    let active = config
+        .active_theme
+        .as_ref()
+        .and_then(|name| themes.iter().position(|t| &t.name == name))
+        .unwrap_or(0);
+    let page = html::render(&data, &themes.swap_remove(active));
+    let path = expand_home(&path);
+    std::fs::write(&path, page).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+/// Each command's output as a range of the scrollback buffer (see
+/// `get_scrollback`), from the OSC 133 marks the shell integration emits.
+/// Oldest first; commands whose output has been trimmed away are left out.
+#[tauri::command]
+fn get_command_blocks(state: State<'_, PtyState>, id: u32) -> Result<Vec<blocks::CommandBlock>, String> {
+    let command_blocks = {
+        let sessions = state.sessions.lock().map_err(|e| e.to_string())?;
+        sessions.get(&id).ok_or("Session not found")?.command_blocks.clone()
+    };
+    let scrollback = session_scrollback(&state, id)?;
+    // Held so the reader can't move the end of the buffer meanwhile
+    let scrollback = scrollback.lock().map_err(|e| e.to_string())?;
+    Ok(command_blocks.list(scrollback.len()))
+}
+
+#[tauri::command]
+fn clear_command_blocks(state: State<'_, PtyState>, id: u32) -> Result<(), String> {
+    let sessions = state.sessions.lock().map_err(|e| e.to_string())?;
+    sessions.get(&id).ok_or("Session not found")?.command_blocks.clear();
+    Ok(())
+}
+
+/// Drop buffered output older than the last `keep_last_lines` complete
+/// (`\n`-terminated) lines; a trailing partial line such as the prompt is
+/// kept too. A no-op when there aren't that many. Returns the new size in bytes.
+#[tauri::command]
+fn trim_scrollback(state: State<'_, PtyState>, id: u32, keep_last_lines: usize) -> Result<usize, String> {
+    let scrollback = session_scrollback(&state, id)?;
+    let mut scrollback = scrollback.lock().map_err(|e| e.to_string())?;
+    trim_lines(&mut scrollback, keep_last_lines);
+    Ok(scrollback.len())
+}
+
+/// The trimming behind `trim_scrollback`.
+fn trim_lines(buffer: &mut VecDeque<u8>, lines: usize) {
+    // The newline ending the line just before the ones we keep
+    let cut = buffer
+        .iter()
+        .enumerate()
+        .rev()
+        .filter(|&(_, &b)| b == b'\n')
+        .nth(lines)
+        .map(|(i, _)| i);
+    if let Some(cut) = cut {
+        buffer.drain(..=cut);
+    }
+}
+
+/// When session `id` last produced output (ms since the epoch), `None` if
+/// it never has. Lets idle detection tell a quiet command from a hung one.
+#[tauri::command]
+fn last_activity(state: State<'_, PtyState>, id: u32) -> Result<Option<u64>, String> {
+    let sessions = state.sessions.lock().map_err(|e| e.to_string())?;
+    let session = sessions.get(&id).ok_or("Session not found")?;
+    Ok(session.last_activity())
+}
+
+const REAP_POLL: Duration = Duration::from_millis(10);
+const QUIT_REAP_BUDGET: Duration = Duration::from_secs(1);
+const REAP_ATTEMPTS: usize = 20;
+
+/// Exit code of a session's child. The reader can see EOF slightly before
+/// the child is reapable, so poll briefly.
+fn reap_exit_code(app: &AppHandle, id: u32) -> Option<u32> {
+    let state = app.state::<PtyState>();
+    for _ in 0..REAP_ATTEMPTS {
+        if let Ok(mut sessions) = state.sessions.lock() {
+            let session = sessions.get_mut(&id)?;
+            if let Ok(Some(status)) = session.child.try_wait() {
+                return Some(status.exit_code());
+            }
+        }
+        std::thread::sleep(REAP_POLL);
+    }
+    None
+}
+
+/// Emit a per-session event, as `<namespace>:<event>` when the session
+/// was created with an `event_namespace`.
+fn emit_session(app: &AppHandle, namespace: Option<&str>, event: &str, payload: serde_json::Value) {
+    let _ = app.emit(&session_event(namespace, event), payload);
+}
+
+fn session_event<'a>(namespace: Option<&str>, event: &'a str) -> std::borrow::Cow<'a, str> {
+    match namespace {
+        Some(ns) => format!("{}:{}", ns, event).into(),
+        None => event.into(),
+    }
+}
+
+fn emit_mode_change(app: &AppHandle, namespace: Option<&str>, id: u32, change: ModeChange) {
+    match change {
+        ModeChange::AltScreen(active) => {
+            emit_session(app, namespace, "pty-altscreen", serde_json::json!({
+                "id": id,
+                "active": active
+            }));
+        }
+        ModeChange::AppCursorKeys(enabled) => {
+            emit_session(app, namespace, "pty-mode-changed", serde_json::json!({
+                "id": id,
+                "mode": "appCursorKeys",
+                "enabled": enabled
+            }));
+        }
+        ModeChange::BracketedPaste(enabled) => {
+            emit_session(app, namespace, "pty-mode-changed", serde_json::json!({
+                "id": id,
+                "mode": "bracketedPaste",
+                "enabled": enabled
+            }));
+        }
+        ModeChange::Cwd(cwd) => {
+            emit_session(app, namespace, "pty-cwd", serde_json::json!({
+                "id": id,
+                "cwd": cwd
+            }));
+        }
+        ModeChange::Title(title) => {
+            emit_session(app, namespace, "pty-title", serde_json::json!({
+                "id": id,
+                "title": title
+            }));
+        }
+        ModeChange::KeyboardFlags(flags) => {
+            emit_session(app, namespace, "pty-key-encoding-changed", serde_json::json!({
+                "id": id,
+                "encoding": KeyEncoding::from_flags(flags)
+            }));
+        }
+        ModeChange::SyncUpdate(active) => {
+            emit_session(app, namespace, "pty-sync-update", serde_json::json!({
+                "id": id,
+                "active": active
+            }));
+        }
+    }
+}
+
+/// How the frontend should encode keys for a session.
+#[derive(Serialize)]
+struct KeyEncoding {
+    /// `"legacy"` (xterm) or `"kitty"` (CSI u keyboard protocol)
+    protocol: &'static str,
+    /// Kitty progressive enhancement flags (disambiguate = 1, report event
+    /// types = 2, alternate keys = 4, all keys as escapes = 8, text = 16)
+    flags: u32,
+}
+
+impl KeyEncoding {
+    fn from_flags(flags: u32) -> Self {
+        KeyEncoding {
+            protocol: if flags == 0 { "legacy" } else { "kitty" },
+            flags,
+        }
+    }
+}
+
+/// The key encoding the session's foreground program asked for. Changes
+/// arrive as `pty-key-encoding-changed`.
+#[tauri::command]
+fn get_key_encoding(state: State<'_, PtyState>, id: u32) -> Result<KeyEncoding, String> {
+    let sessions = state.sessions.lock().map_err(|e| e.to_string())?;
+    let session = sessions.get(&id).ok_or("Session not found")?;
+    Ok(KeyEncoding::from_flags(session.modes.keyboard_flags()))
+}
+
+fn emit_output(app: &AppHandle, namespace: Option<&str>, id: u32, data: &[u8]) {
+    emit_session(app, namespace, "pty-output", serde_json::json!({
+        "id": id,
+        "data": base64_encode(data)
+    }));
+}
+
+/// Forwards reader chunks to the frontend at most `max_per_sec` times a second,
+/// merging whatever arrives in between into a single event. Emits
+/// `pty-throttled` whenever the session crosses the cap in either direction.
+fn spawn_throttled_emitter(
+    app: AppHandle,
+    namespace: Option<String>,
+    id: u32,
+    max_per_sec: u32,
+) -> (mpsc::Sender<Vec<u8>>, std::thread::JoinHandle<()>) {
+    let (tx, rx) = mpsc::channel::<Vec<u8>>();
+    let handle = std::thread::spawn(move || {
+        throttle(
+            rx,
+            max_per_sec,
+            |data| emit_output(&app, namespace.as_deref(), id, data),
+            |throttled| {
+                emit_session(&app, namespace.as_deref(), "pty-throttled", serde_json::json!({
+                    "id": id,
+                    "throttled": throttled
+                }))
+            },
+        )
+    });
+    (tx, handle)
+}
+
+/// The loop behind `spawn_throttled_emitter`, until the sender is dropped.
+/// A throttled session is reported clear once a window ends under the cap,
+/// even if nothing more arrives, and when the sender goes away.
+fn throttle(
+    rx: mpsc::Receiver<Vec<u8>>,
+    max_per_sec: u32,
+    mut emit: impl FnMut(&[u8]),
+    mut on_throttled: impl FnMut(bool),
+) {
+    let interval = Duration::from_secs(1) / max_per_sec;
+    let mut last_emit: Option<Instant> = None;
+    let mut window_start = Instant::now();
+    let mut window_chunks: u32 = 0;
+    let mut throttled = false;
+
+    loop {
+        // While throttled, wake at the end of the window even without
+        // output, so a flood that stopped is reported as over
+        let received = if throttled {
+            match rx.recv_timeout(Duration::from_secs(1).saturating_sub(window_start.elapsed())) {
+                Ok(chunk) => Some(chunk),
+                Err(mpsc::RecvTimeoutError::Timeout) => None,
+                Err(mpsc::RecvTimeoutError::Disconnected) => break,
+            }
+        } else {
+            match rx.recv() {
+                Ok(chunk) => Some(chunk),
+                Err(_) => break,
+            }
+        };
+
+        let pending = received.map(|mut pending| {
+            window_chunks += 1;
+            // Hold the chunk until the interval since the last emit has
+            // passed, folding in anything else the reader produces meanwhile
+            while let Some(wait) = last_emit.and_then(|t| interval.checked_sub(t.elapsed())) {
+                match rx.recv_timeout(wait) {
+                    Ok(chunk) => {
+                        pending.extend_from_slice(&chunk);
+                        window_chunks += 1;
+                    }
+                    Err(_) => break,
+                }
+            }
+            pending
+        });
+
+        if window_start.elapsed() >= Duration::from_secs(1) {
+            let over = window_chunks > max_per_sec;
+            if over != throttled {
+                throttled = over;
+                on_throttled(throttled);
+            }
+            window_start = Instant::now();
+            window_chunks = 0;
+        }
+
+        if let Some(pending) = pending {
+            emit(&pending);
+            last_emit = Some(Instant::now());
+        }
+    }
+    if throttled {
+        on_throttled(false);
+    }
+}
+
+/// Write input as-is. Keystrokes are mostly control sequences, so unlike
+/// `paste_pty` this only sanitizes when asked (for text of outside origin);
+/// returns whether anything was stripped.
+#[tauri::command]
+fn write_pty(
+    app: AppHandle,
+    state: State<'_, PtyState>,
+    id: u32,
+    data: String,
+    sanitize: Option<bool>,
+) -> Result<bool, String> {
+    let (data, stripped) = if sanitize.unwrap_or(false) { sanitize_input(data) } else { (data, false) };
+    let mut sessions = state.sessions.lock().map_err(|e| e.to_string())?;
+    let session = sessions.get_mut(&id).ok_or("Session not found")?;
+    if !session.accepts_input()? {
+        return Ok(stripped);
+    }
+    let result = session.send(data.as_bytes());
+    result.map_err(|e| input_error(&app, id, session, e))?;
+    Ok(stripped)
+}
+
+/// Push anything buffered in the session's writer through to the child.
+#[tauri::command]
+fn flush_pty(app: AppHandle, state: State<'_, PtyState>, id: u32) -> Result<(), String> {
+    let mut sessions = state.sessions.lock().map_err(|e| e.to_string())?;
+    let session = sessions.get_mut(&id).ok_or("Session not found")?;
+    let result = session.writer.flush();
+    result.map_err(|e| input_error(&app, id, session, e))
+}
+
+/// Write `request` (e.g. `ESC [6n`) and wait up to `timeout_ms` for output
+/// ending in `terminator`, returning everything captured up to and including
+/// it. The output still reaches the frontend as usual. One query at a time
+/// per session.
+#[tauri::command]
+async fn query_pty(
+    app: AppHandle,
+    id: u32,
+    request: Vec<u8>,
+    terminator: u8,
+    timeout_ms: u64,
+) -> Result<Vec<u8>, String> {
+    let timeout = Duration::from_millis(timeout_ms);
+    tauri::async_runtime::spawn_blocking(move || {
+        query(&app.state::<PtyState>(), id, &request, terminator, timeout, |id, session, e| {
+            input_error(&app, id, session, e)
+        })
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+fn query(
+    state: &PtyState,
+    id: u32,
+    request: &[u8],
+    terminator: u8,
+    timeout: Duration,
+    on_error: impl FnOnce(u32, &PtySession, std::io::Error) -> String,
+) -> Result<Vec<u8>, String> {
+    let (capture, rx) = begin_query(state, id, request, on_error)?;
+    let deadline = Instant::now() + timeout;
+    let mut response = Vec::new();
+    let result = loop {
+        let Some(remaining) = deadline.checked_duration_since(Instant::now()) else {
+            break Err("Timed out waiting for a response".to_string());
+        };
+        match rx.recv_timeout(remaining) {
+            Ok(chunk) => {
+                response.extend_from_slice(&chunk);
+                if let Some(end) = response.iter().position(|&b| b == terminator) {
+                    response.truncate(end + 1);
+                    break Ok(response);
+                }
+            }
+            Err(mpsc::RecvTimeoutError::Timeout) => break Err("Timed out waiting for a response".to_string()),
+            Err(mpsc::RecvTimeoutError::Disconnected) => break Err("Session closed".to_string()),
+        }
+    };
+    end_query(&capture);
+    result
+}
+
+const PING_REQUEST: &[u8] = b"\x1b[c";
+const DEFAULT_PING_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Round trip through the PTY in ms: write a Device Attributes request and
+/// time the first output that comes back (the child's echo or reaction).
+/// The foreground program does see the request, so this is meant for an
+/// idle shell prompt. Fails after `timeout_ms` (default 2s).
+#[tauri::command]
+async fn ping_pty(app: AppHandle, id: u32, timeout_ms: Option<u64>) -> Result<u64, String> {
+    let timeout = timeout_ms.map(Duration::from_millis).unwrap_or(DEFAULT_PING_TIMEOUT);
+    tauri::async_runtime::spawn_blocking(move || {
+        ping(&app.state::<PtyState>(), id, timeout, |id, session, e| input_error(&app, id, session, e))
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+fn ping(
+    state: &PtyState,
+    id: u32,
+    timeout: Duration,
+    on_error: impl FnOnce(u32, &PtySession, std::io::Error) -> String,
+) -> Result<u64, String> {
+    let started = Instant::now();
+    let (capture, rx) = begin_query(state, id, PING_REQUEST, on_error)?;
+    let result = match rx.recv_timeout(timeout) {
+        Ok(_) => Ok(started.elapsed().as_millis() as u64),
+        Err(mpsc::RecvTimeoutError::Timeout) => Err("Timed out waiting for a response".to_string()),
+        Err(mpsc::RecvTimeoutError::Disconnected) => Err("Session closed".to_string()),
+    };
+    end_query(&capture);
+    result
+}
+
+/// Install a capture on session `id` and write `request`, for a caller that
+/// then reads replies from the receiver and calls `end_query`. A failed
+/// write goes through `on_error` (`input_error` outside of tests).
+fn begin_query(
+    state: &PtyState,
+    id: u32,
+    request: &[u8],
+    on_error: impl FnOnce(u32, &PtySession, std::io::Error) -> String,
+) -> Result<(Capture, mpsc::Receiver<Vec<u8>>), String> {
+    let (tx, rx) = mpsc::channel();
+    let capture = {
+        let mut sessions = state.sessions.lock().map_err(|e| e.to_string())?;
+        let session = sessions.get_mut(&id).ok_or("Session not found")?;
+        if !session.accepts_input()? {
+            return Err("Session input is disabled".into());
+        }
+        let capture = session.capture.clone();
+        {
+            let mut slot = capture.lock().map_err(|e| e.to_string())?;
+            if slot.is_some() {
+                return Err("A query is already in progress".into());
+            }
+            *slot = Some(tx);
+        }
+        // Install the capture before writing so a fast reply isn't missed
+        if let Err(e) = session.send(request) {
+            *capture.lock().map_err(|e| e.to_string())? = None;
+            return Err(on_error(id, session, e));
+        }
+        capture
+    };
+    Ok((capture, rx))
+}
+
+fn end_query(capture: &Capture) {
+    if let Ok(mut slot) = capture.lock() {
+        *slot = None;
+    }
+}
+
+/// Lock or unlock a session for input, e.g. for demos. While locked, writes
+/// fail with `"input-locked"`, or succeed without doing anything when
+/// `drop_silently` is set. Output carries on either way.
+#[tauri::command]
+fn set_pty_input_enabled(
+    state: State<'_, PtyState>,
+    id: u32,
+    enabled: bool,
+    drop_silently: Option<bool>,
+) -> Result<(), String> {
+    let mut sessions = state.sessions.lock().map_err(|e| e.to_string())?;
+    let session = sessions.get_mut(&id).ok_or("Session not found")?;
+    session.input_enabled = enabled;
+    session.drop_locked_input = drop_silently.unwrap_or(false);
+    Ok(())
+}
+
+/// Describe a failed write. If the failure means the child's side of the
+/// PTY is gone, flag the session exited and emit `pty-exit` now rather
+/// than leaving it looking alive while input goes nowhere.
+fn input_error(app: &AppHandle, id: u32, session: &PtySession, e: std::io::Error) -> String {
+    if mark_gone(session, &e) {
+        emit_session(
+            app,
+            session.options.event_namespace.as_deref(),
+            "pty-exit",
+            serde_json::json!({ "id": id, "code": null }),
+        );
+    }
+    e.to_string()
+}
+
+/// Flag `session` exited if `e` means its child is gone. Returns whether
+/// this did so, i.e. `pty-exit` is still to be sent.
+fn mark_gone(session: &PtySession, e: &std::io::Error) -> bool {
+    let gone = e.kind() == std::io::ErrorKind::BrokenPipe;
+    #[cfg(unix)]
+    let gone = gone || e.raw_os_error() == Some(libc::EIO);
+    gone && !session.exited.swap(true, Ordering::Relaxed)
+}
+
+/// DECSTR soft reset, then SGR reset, G0 back to ASCII (and shifted in),
+/// and a visible cursor. Leaves the screen and scrollback alone.
+const SOFT_RESET: &[u8] = b"\x1b[!p\x1b[0m\x1b(B\x0f\x1b[?25h";
+
+/// "Fix my terminal": soft-reset session `id` after output left it in a
+/// stuck charset or color mode, without clearing the screen or scrollback.
+/// The sequence is written to the PTY's input, the same way as keystrokes.
+#[tauri::command]
+fn soft_reset_pty(app: AppHandle, state: State<'_, PtyState>, id: u32) -> Result<(), String> {
+    let mut sessions = state.sessions.lock().map_err(|e| e.to_string())?;
+    let session = sessions.get_mut(&id).ok_or("Session not found")?;
+    if session.exited.load(Ordering::Relaxed) {
+        return Err("Session has exited".into());
+    }
+    soft_reset(session).map_err(|e| input_error(&app, id, session, e))
+}
+
+fn soft_reset(session: &mut PtySession) -> std::io::Result<()> {
+    session.send(SOFT_RESET)
+}
+
+const PASTE_START: &[u8] = b"\x1b[200~";
+const PASTE_END: &[u8] = b"\x1b[201~";
+const PASTE_CHUNK_SIZE: usize = 4096;
+const MAX_INPUT_FILE_SIZE: u64 = 10 * 1024 * 1024;
+
+/// Wrap `data` in bracketed-paste markers when asked. End markers are always
+/// stripped so pasted text can't break out of the bracket early; loop since
+/// removing one can splice together another.
+fn paste_payload(mut data: Vec<u8>, bracketed: bool) -> Vec<u8> {
+    while let Some(pos) = data.windows(PASTE_END.len()).position(|w| w == PASTE_END) {
+        data.drain(pos..pos + PASTE_END.len());
+    }
+    if !bracketed {
+        return data;
+    }
+    let mut payload = Vec::with_capacity(data.len() + PASTE_START.len() + PASTE_END.len());
+    payload.extend_from_slice(PASTE_START);
+    payload.extend_from_slice(&data);
+    payload.extend_from_slice(PASTE_END);
+    payload
+}
+
+/// Strip control characters other than tab, newline and CR, so text copied
+/// from a web page can't smuggle escape sequences or a stray Ctrl-C/Enter
+/// into the shell. That includes DEL and the C1 controls. Escape sequences
+/// go whole, not just their ESC, and so do their one-character C1 forms
+/// (e.g. U+009B for `ESC [`). Returns whether anything was removed.
+fn sanitize_input(text: String) -> (String, bool) {
+    let dangerous = |c: char| c.is_control() && !matches!(c, '\t' | '\n' | '\r');
+    if !text.contains(dangerous) {
+        return (text, false);
+    }
+    let mut clean = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            let introducer = chars.next();
+            skip_escape(&mut chars, introducer);
+        } else if ('\u{80}'..='\u{9f}').contains(&c) {
+            skip_escape(&mut chars, char::from_u32(c as u32 - 0x40));
+        } else if !dangerous(c) {
+            clean.push(c);
+        }
+    }
+    (clean, true)
+}
+
+/// Consume the rest of an escape sequence after ESC `introducer`: a CSI up
+/// to its final byte, an OSC/DCS/APC/PM/SOS string up to BEL or ST,
+/// otherwise nothing more.
+fn skip_escape(chars: &mut std::str::Chars, introducer: Option<char>) {
+    match introducer {
+        Some('[') => {
+            for c in chars.by_ref() {
+                if ('\x40'..='\x7e').contains(&c) {
+                    break;
+                }
+            }
+        }
+        Some(']' | 'P' | 'X' | '^' | '_') => {
+            let mut escape = false;
+            for c in chars.by_ref() {
+                if c == '\x07' || c == '\u{9c}' || (escape && c == '\\') {
+                    break;
+                }
+                escape = c == '\x1b';
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Re-lock per chunk so a huge write doesn't hold up every other session.
+fn write_chunked(app: &AppHandle, state: &PtyState, id: u32, payload: &[u8]) -> Result<(), String> {
+    for chunk in payload.chunks(PASTE_CHUNK_SIZE) {
+        let mut sessions = state.sessions.lock().map_err(|e| e.to_string())?;
+        let session = sessions.get_mut(&id).ok_or("Session not found")?;
+        if !session.accepts_input()? {
+            return Ok(());
+        }
+        let result = session.send(chunk);
+        result.map_err(|e| input_error(app, id, session, e))?;
+    }
+    Ok(())
+}
+
+/// Rewrite every `\r\n`, `\r` or `\n` in `text` as `ending`.
+fn normalize_line_endings(text: &str, ending: &str) -> String {
+    text.replace("\r\n", "\n").replace('\r', "\n").replace('\n', ending)
+}
+
+/// Write `text` as a line of input: embedded line endings are normalized to
+/// `line_ending` ("cr", "lf" or "crlf"; default "cr", what Enter sends) and
+/// one is appended if the text doesn't already end with it.
+#[tauri::command]
+fn write_pty_line(
+    app: AppHandle,
+    state: State<'_, PtyState>,
+    id: u32,
+    text: String,
+    line_ending: Option<String>,
+) -> Result<(), String> {
+    let ending = match line_ending.as_deref().unwrap_or("cr") {
+        "cr" => "\r",
+        "lf" => "\n",
+        "crlf" => "\r\n",
+        other => return Err(format!("Unknown line ending: {}", other)),
+    };
+    let mut data = normalize_line_endings(&text, ending);
+    if !data.ends_with(ending) {
+        data.push_str(ending);
+    }
+    write_chunked(&app, &state, id, data.as_bytes())
+}
+
+/// Paste `text`, by default with control characters stripped (see
+/// `sanitize_input`); pass `sanitize: false` for a deliberate raw paste.
+/// Returns whether anything was stripped so the frontend can warn.
+#[tauri::command]
+fn paste_pty(
+    app: AppHandle,
+    state: State<'_, PtyState>,
+    id: u32,
+    text: String,
+    bracketed: bool,
+    sanitize: Option<bool>,
+) -> Result<bool, String> {
+    let (text, stripped) = if sanitize.unwrap_or(true) { sanitize_input(text) } else { (text, false) };
+    write_chunked(&app, &state, id, &paste_payload(text.into_bytes(), bracketed))?;
+    Ok(stripped)
+}
+
+/// Feed a file's contents to the session as input, emitting
+/// `pty-write-progress` `{ id, written, total }` after each chunk. A final
+/// event with an `error` field reports a write that failed, e.g. because
+/// the child exited. Returns the bytes written, fewer than the file's size
+/// if `cancel_write` stopped it.
+#[tauri::command(async)]
+fn write_file_to_pty(
+    app: AppHandle,
+    state: State<'_, PtyState>,
+    id: u32,
+    path: String,
+    bracketed: bool,
+) -> Result<usize, String> {
+    let payload = paste_payload(read_input_file(&expand_home(&path))?, bracketed);
+    let written = write_streamed(&app, &state, id, &payload)?;
+    // Don't leave the shell stuck in paste mode
+    if bracketed && written > 0 && written < payload.len() {
+        write_chunked(&app, &state, id, PASTE_END)?;
+    }
+    Ok(written)
+}
+
+/// The contents of `path`, refusing files over `MAX_INPUT_FILE_SIZE`.
+fn read_input_file(path: &std::path::Path) -> Result<Vec<u8>, String> {
+    let meta = std::fs::metadata(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    if meta.len() > MAX_INPUT_FILE_SIZE {
+        return Err(format!(
+            "{} is larger than the {}MB limit",
+            path.display(),
+            MAX_INPUT_FILE_SIZE / 1024 / 1024
+        ));
+    }
+    std::fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+/// `write_chunked` with progress events, stopping early on `cancel_write`.
+fn write_streamed(app: &AppHandle, state: &PtyState, id: u32, payload: &[u8]) -> Result<usize, String> {
+    let namespace = {
+        let mut sessions = state.sessions.lock().map_err(|e| e.to_string())?;
+        let session = sessions.get_mut(&id).ok_or("Session not found")?;
+        session.write_cancelled = false;
+        session.options.event_namespace.clone()
+    };
+    stream_chunks(
+        id,
+        payload,
+        |chunk| write_stream_chunk(app, state, id, chunk),
+        |progress| emit_session(app, namespace.as_deref(), "pty-write-progress", progress),
+    )
+}
+
+/// Feed `payload` to `write` in `PASTE_CHUNK_SIZE` chunks, reporting
+/// `{ id, written, total }` to `progress` after each, with an `error` field
+/// for a failed one. Stops at the first chunk `write` declines.
+fn stream_chunks(
+    id: u32,
+    payload: &[u8],
+    mut write: impl FnMut(&[u8]) -> Result<bool, String>,
+    mut progress: impl FnMut(serde_json::Value),
+) -> Result<usize, String> {
+    let total = payload.len();
+    let mut written = 0;
+    for chunk in payload.chunks(PASTE_CHUNK_SIZE) {
+        let result = write(chunk);
+        progress(match result {
+            Ok(false) => break,
+            Ok(true) => {
+                written += chunk.len();
+                serde_json::json!({ "id": id, "written": written, "total": total })
+            }
+            Err(ref e) => serde_json::json!({ "id": id, "written": written, "total": total, "error": e }),
+        });
+        result?;
+    }
+    Ok(written)
+}
+
+/// One chunk of `write_streamed`: `Ok(false)` if it was cancelled or input
+/// is locked.
+fn write_stream_chunk(app: &AppHandle, state: &PtyState, id: u32, chunk: &[u8]) -> Result<bool, String> {
+    let mut sessions = state.sessions.lock().map_err(|e| e.to_string())?;
+    let session = sessions.get_mut(&id).ok_or("Session not found")?;
+    if session.write_cancelled || !session.accepts_input()? {
+        return Ok(false);
+    }
+    let result = session.send(chunk);
+    result.map_err(|e| input_error(app, id, session, e))?;
+    Ok(true)
+}
+
+/// Stop session `id`'s `write_file_to_pty` after the chunk in flight.
+#[tauri::command]
+fn cancel_write(state: State<'_, PtyState>, id: u32) -> Result<(), String> {
+    let mut sessions = state.sessions.lock().map_err(|e| e.to_string())?;
+    sessions.get_mut(&id).ok_or("Session not found")?.write_cancelled = true;
+    Ok(())
+}
+
+/// Omitted pixel dimensions keep the current size of a cell in pixels.
+#[tauri::command]
+fn resize_pty(
+    state: State<'_, PtyState>,
+    id: u32,
+    rows: u16,
+    cols: u16,
+    pixel_width: Option<u16>,
+    pixel_height: Option<u16>,
+) -> Result<PtyDimensions, String> {
+    let sessions = state.sessions.lock().map_err(|e| e.to_string())?;
+    let session = sessions.get(&id).ok_or("Session not found")?;
+    match (pixel_width, pixel_height) {
+        (None, None) => resize_session(session, rows, cols),
+        _ => resize_session_pixels(session, rows, cols, (pixel_width.unwrap_or(0), pixel_height.unwrap_or(0))),
+    }
+}
+
+/// The size the PTY currently reports to the child, pixels included.
+#[tauri::command]
+fn get_pty_size(state: State<'_, PtyState>, id: u32) -> Result<PtyDimensions, String> {
+    let sessions = state.sessions.lock().map_err(|e| e.to_string())?;
+    session_size(sessions.get(&id).ok_or("Session not found")?)
+}
+
+fn session_size(session: &PtySession) -> Result<PtyDimensions, String> {
+    session.master.get_size().map(PtyDimensions::from).map_err(|e| e.to_string())
+}
+
+/// Resize to `rows` x `cols`, scaling the pixel size so a cell stays the
+/// size it was. A session that never had one keeps reporting 0.
+fn resize_session(session: &PtySession, rows: u16, cols: u16) -> Result<PtyDimensions, String> {
+    let current = session_size(session)?;
+    let size = normalize_size(rows, cols, false);
+    let scale = |pixels: u16, before: u16, after: u16| match before {
+        0 => 0,
+        before => (pixels as u32 * after as u32 / before as u32).min(u16::MAX as u32) as u16,
+    };
+    let pixels = (
+        scale(current.pixel_width, current.cols, size.cols),
+        scale(current.pixel_height, current.rows, size.rows),
+    );
+    resize_session_pixels(session, rows, cols, pixels)
+}
+
+fn resize_session_pixels(
+    session: &PtySession,
+    rows: u16,
+    cols: u16,
+    (pixel_width, pixel_height): (u16, u16),
+) -> Result<PtyDimensions, String> {
+    let size = PtyDimensions {
+        pixel_width,
+        pixel_height,
+        ..normalize_size(rows, cols, false)
+    };
+    session.master.resize(size.into()).map_err(|e| e.to_string())?;
+    Ok(size)
+}
+
+/// Form feed (Ctrl-L): shells redraw the prompt on a cleared screen and most
+/// full-screen programs repaint
+const REDRAW_REQUEST: &[u8] = b"\x0c";
+
+/// `resize_pty`, then with `request_redraw` also send Ctrl-L so a prompt left
+/// garbled by the reflow gets redrawn. Opt-in since it clears the screen in
+/// shells and is just a keystroke to anything else.
+#[tauri::command]
+fn resize_pty_repaint(
+    app: AppHandle,
+    state: State<'_, PtyState>,
+    id: u32,
+    rows: u16,
+    cols: u16,
+    request_redraw: bool,
+) -> Result<PtyDimensions, String> {
+    let mut sessions = state.sessions.lock().map_err(|e| e.to_string())?;
+    let session = sessions.get_mut(&id).ok_or("Session not found")?;
+    let size = resize_session(session, rows, cols)?;
+    if request_redraw && session.accepts_input()? {
+        let result = send_redraw(session);
+        result.map_err(|e| input_error(&app, id, session, e))?;
+    }
+    Ok(size)
+}
+
+fn send_redraw(session: &mut PtySession) -> std::io::Result<()> {
+    session.send(REDRAW_REQUEST)
+}
+
+/// Resize every running session in window `label` at once, so background
+/// tabs already have the right size when they're switched to. Returns an
+/// error message per session that couldn't be resized.
+#[tauri::command]
+fn resize_window_sessions(
+    state: State<'_, PtyState>,
+    label: String,
+    rows: u16,
+    cols: u16,
+) -> Result<HashMap<u32, String>, String> {
+    resize_window(&state, &label, rows, cols)
+}
+
+fn resize_window(state: &PtyState, label: &str, rows: u16, cols: u16) -> Result<HashMap<u32, String>, String> {
+    let sessions = state.sessions.lock().map_err(|e| e.to_string())?;
+    let mut errors = HashMap::new();
+    let owned = sessions.iter().filter(|(_, s)| {
+        s.options.window.as_deref() == Some(label) && !s.exited.load(Ordering::Relaxed)
+    });
+    for (&id, session) in owned {
+        if let Err(e) = resize_session(session, rows, cols) {
+            errors.insert(id, e);
+        }
+    }
+    Ok(errors)
+}
+
+/// Resize the panes of group `group_id` in one locked pass, e.g. while a
+/// split is being dragged. `sizes` maps session id to `(rows, cols)`.
+/// Returns an error message per id that wasn't resized.
+#[tauri::command]
+fn resize_group(
+    state: State<'_, PtyState>,
+    group_id: u32,
+    sizes: HashMap<u32, (u16, u16)>,
+) -> Result<HashMap<u32, String>, String> {
+    resize_members(&state, group_id, sizes)
+}
+
+fn resize_members(
+    state: &PtyState,
+    group_id: u32,
+    sizes: HashMap<u32, (u16, u16)>,
+) -> Result<HashMap<u32, String>, String> {
+    let sessions = state.sessions.lock().map_err(|e| e.to_string())?;
+    let mut errors = HashMap::new();
+    for (id, (rows, cols)) in sizes {
+        let result = match sessions.get(&id) {
+            Some(session) if session.options.group_id == Some(group_id) => {
+                resize_session(session, rows, cols).map(|_| ())
+            }
+            Some(_) => Err(format!("Session is not in group {}", group_id)),
+            None => Err("Session not found".to_string()),
+        };
+        if let Err(e) = result {
+            errors.insert(id, e);
+        }
+    }
+    Ok(errors)
+}
+
+/// A data URL for `family`. With `use_fallback`, a family that isn't
+/// installed gets the bundled `fallback_font` instead of `None`.
+#[tauri::command]
+fn load_font(family: String, use_fallback: Option<bool>) -> Result<Option<String>, String> {
+    let candidates = find_fonts(&font_dirs(), &family);
+    match candidates.first() {
+        Some(path) => font_data_url(path).map(Some),
+        None if use_fallback.unwrap_or(false) => Ok(Some(fallback_font())),
+        None => Ok(None),
+    }
+}
+
+/// The platform's usual monospace families, in order of preference.
+#[cfg(target_os = "macos")]
+const DEFAULT_MONOSPACE: &[&str] = &["SF Mono", "Menlo", "Monaco"];
+#[cfg(windows)]
+const DEFAULT_MONOSPACE: &[&str] = &["Cascadia Mono", "Cascadia Code", "Consolas"];
+#[cfg(not(any(target_os = "macos", windows)))]
+const DEFAULT_MONOSPACE: &[&str] = &["DejaVu Sans Mono", "Liberation Mono", "Noto Sans Mono"];
+
+/// A data URL for the first installed `DEFAULT_MONOSPACE` family, else the
+/// bundled `fallback_font`. For first launch, before a font is configured.
+#[tauri::command]
+fn load_default_monospace() -> Result<String, String> {
+    first_font(&font_dirs(), DEFAULT_MONOSPACE)
+}
+
+/// A data URL for the first of `families` found in `dirs`, else the
+/// bundled `fallback_font`.
+fn first_font(dirs: &[impl AsRef<std::path::Path>], families: &[&str]) -> Result<String, String> {
+    for family in families {
+        if let Some(path) = find_fonts(dirs, family).first() {
+            return font_data_url(path);
+        }
+    }
+    Ok(fallback_font())
+}
+
+/// Source Code Pro Medium (SIL OFL, see fonts/SourceCodePro-LICENSE.txt),
+/// so there's always a monospace font to render with.
+static FALLBACK_FONT: &[u8] = include_bytes!("../fonts/SourceCodePro-Medium.woff2");
+
+/// The bundled monospace font as a data URL.
+#[tauri::command]
+fn fallback_font() -> String {
+    format!("data:font/woff2;base64,{}", base64_encode(FALLBACK_FONT))
+}
+
+/// Data URLs for the four standard variants of a family, found in one scan.
+#[derive(Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct FontFamily {
+    regular: Option<String>,
+    bold: Option<String>,
+    italic: Option<String>,
+    bold_italic: Option<String>,
+}
+
+#[tauri::command]
+fn load_font_family(family: String) -> Result<FontFamily, String> {
+    let paths = font_variants(find_fonts(&font_dirs(), &family));
+    let [regular, bold, italic, bold_italic] = paths.map(|p| p.and_then(|p| font_data_url(&p).ok()));
+    Ok(FontFamily {
+        regular,
+        bold,
+        italic,
+        bold_italic,
+    })
+}
+
+/// One file per `FontFamily` slot out of `candidates`.
+fn font_variants(candidates: Vec<std::path::PathBuf>) -> [Option<std::path::PathBuf>; 4] {
+    let mut paths: [Option<std::path::PathBuf>; 4] = Default::default();
+    // Candidates are sorted Regular-first, so keep the first hit per slot
+    for path in candidates {
+        if let Some(slot) = font_variant(&path) {
+            paths[slot].get_or_insert(path);
+        }
+    }
+    paths
+}
+
+/// Slot in `FontFamily` order (regular, bold, italic, bold-italic) guessed
+/// from the file name. Other weights (light, semibold, ...) don't qualify.
+fn font_variant(path: &std::path::Path) -> Option<usize> {
+    let stem = path.file_stem()?.to_string_lossy().to_lowercase();
+    const OTHER_WEIGHTS: [&str; 9] = [
+        "thin", "light", "medium", "semibold", "demibold", "extrabold", "ultrabold", "black", "heavy",
+    ];
+    if OTHER_WEIGHTS.iter().any(|w| stem.contains(w)) {
+        return None;
+    }
+    let bold = stem.contains("bold");
+    let italic = stem.contains("italic") || stem.contains("oblique");
+    Some(match (bold, italic) {
+        (false, false) => 0,
+        (true, false) => 1,
+        (false, true) => 2,
+        (true, true) => 3,
+    })
+}
+
+/// Font files in `dirs` whose names start with `family` (spaces ignored),
+/// Regular first.
+fn find_fonts(dirs: &[impl AsRef<std::path::Path>], family: &str) -> Vec<std::path::PathBuf> {
+    let needle = family.replace(' ', "").to_lowercase();
+    if needle.is_empty() {
+        return Vec::new();
+    }
+
+    let mut candidates: Vec<std::path::PathBuf> = Vec::new();
+
+    for dir in dirs {
+        collect_fonts(dir.as_ref(), &needle, &mut candidates);
+    }
+
+    // Prefer Regular weight
+    candidates.sort_by(|a, b| {
+        let a_reg = a.to_string_lossy().to_lowercase().contains("regular");
+        let b_reg = b.to_string_lossy().to_lowercase().contains("regular");
+        b_reg.cmp(&a_reg)
+    });
+
+    candidates
+}
+
+/// System and user font directories for this platform.
+fn font_dirs() -> Vec<String> {
+    let home = std::env::var("HOME").unwrap_or_default();
+
+    #[cfg(target_os = "macos")]
+    let dirs = vec![
+        format!("{}/Library/Fonts", home),
+        "/Library/Fonts".to_string(),
+        "/System/Library/Fonts".to_string(),
+    ];
+    #[cfg(target_os = "windows")]
+    let dirs = vec![
+        format!("{}\\Microsoft\\Windows\\Fonts", std::env::var("LOCALAPPDATA").unwrap_or_default()),
+        "C:\\Windows\\Fonts".to_string(),
+    ];
+    #[cfg(target_os = "linux")]
+    let dirs = vec![
+        format!("{}/.local/share/fonts", home),
+        "/usr/share/fonts".to_string(),
+        "/usr/local/share/fonts".to_string(),
+    ];
+    dirs
+}
+
+static MONOSPACE_FONTS: std::sync::OnceLock<Vec<String>> = std::sync::OnceLock::new();
+
+/// Installed fixed-pitch font families, sorted, for the font picker. Reads
+/// each font's `post` table; files that don't parse are judged by name.
+/// Scanned once per run.
+#[tauri::command(async)]
+fn list_monospace_fonts() -> Vec<String> {
+    MONOSPACE_FONTS
+        .get_or_init(|| {
+            let mut files = Vec::new();
+            for dir in font_dirs() {
+                collect_fonts(dir.as_ref(), "", &mut files);
+            }
+            monospace_families(files)
+        })
+        .clone()
+}
+
+/// The fixed-pitch families among font `files`, sorted and deduplicated.
+fn monospace_families(files: Vec<std::path::PathBuf>) -> Vec<String> {
+    let mut families = std::collections::BTreeSet::new();
+    for path in files {
+        let faces = std::fs::read(&path).ok().and_then(|data| fontinfo::faces(&data));
+        match faces {
+            Some(faces) => {
+                families.extend(faces.into_iter().filter(|f| f.fixed_pitch).map(|f| f.family));
+            }
+            None => families.extend(monospace_by_name(&path)),
+        }
+    }
+    families.into_iter().collect()
+}
+
+/// Family guessed from a font's file name (`Fira_Code-Bold.ttf` -> `Fira
+/// Code`) when the name suggests a monospace font.
+fn monospace_by_name(path: &std::path::Path) -> Option<String> {
+    let stem = path.file_stem()?.to_string_lossy();
+    let lower = stem.to_lowercase();
+    let hints = ["mono", "code", "consola", "courier", "term"];
+    if !hints.iter().any(|hint| lower.contains(hint)) {
+        return None;
+    }
+    let family = stem.split('-').next().unwrap_or(&stem).replace('_', " ");
+    Some(family.trim().to_string())
+}
+
+fn font_data_url(path: &std::path::Path) -> Result<String, String> {
+    let data = std::fs::read(path).map_err(|e| e.to_string())?;
+    let b64 = base64_encode(&data);
+    let ext = match path.extension().and_then(|e| e.to_str()) {
+        Some("otf") => "opentype",
+        _ => "truetype",
+    };
+    Ok(format!("data:font/{};base64,{}", ext, b64))
+}
+
+const FONT_SCAN_MAX_DEPTH: usize = 8;
+
+fn collect_fonts(dir: &std::path::Path, needle: &str, out: &mut Vec<std::path::PathBuf>) {
+    let mut visited = HashSet::new();
+    scan_font_dir(dir, needle, out, 0, &mut visited);
+}
+
+/// Recursive half of `collect_fonts`. Depth-capped, and skips directories it
+/// has already seen by canonical path so symlink loops terminate.
+fn scan_font_dir(
+    dir: &std::path::Path,
+    needle: &str,
+    out: &mut Vec<std::path::PathBuf>,
+    depth: usize,
+    visited: &mut HashSet<std::path::PathBuf>,
+) {
+    if depth > FONT_SCAN_MAX_DEPTH {
+        return;
+    }
+    let Ok(canonical) = std::fs::canonicalize(dir) else { return };
+    if !visited.insert(canonical) {
+        return;
+    }
+    let Ok(entries) = std::fs::read_dir(dir) else { return };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            scan_font_dir(&path, needle, out, depth + 1, visited);
+            continue;
+        }
+        let name = entry.file_name().to_string_lossy().to_lowercase();
+        if !(name.ends_with(".otf") || name.ends_with(".ttf") || name.ends_with(".ttc")) {
+            continue;
+        }
+        // Strip extension, then check if filename starts with needle
+        let stem = name.rsplitn(2, '.').last().unwrap_or(&name);
+        let stem_clean = stem.replace(['-', '_'], "");
+        if stem_clean.starts_with(needle) {
+            out.push(path);
+        }
+    }
+}
+
+const DRAIN_POLL: Duration = Duration::from_millis(20);
+const DRAIN_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// Give the reader thread a moment to emit output the child already wrote,
+/// waiting until it stops making progress or the timeout passes.
+fn drain_output(session: &PtySession) {
+    if session.exited.load(Ordering::Relaxed) {
+        return;
+    }
+    let deadline = Instant::now() + DRAIN_TIMEOUT;
+    let mut seen = session.bytes_in.load(Ordering::Relaxed);
+    while Instant::now() < deadline {
+        std::thread::sleep(DRAIN_POLL);
+        let now = session.bytes_in.load(Ordering::Relaxed);
+        if now == seen {
+            break;
+        }
+        seen = now;
+    }
+}
+
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(2);
+const CLOSE_POLL: Duration = Duration::from_millis(20);
+
+/// Close a session. `mode` is how the child is asked to leave:
+/// - `"eof"` (default): close its input so shells run exit hooks and save history
+/// - `"term"`: SIGTERM (Windows has no equivalent and kills)
+/// - `"kill"`: kill immediately
+///
+/// `eof` and `term` fall back to a kill if the child is still around after
+/// `CLOSE_TIMEOUT`. The wait happens off the command thread.
+#[tauri::command]
+fn close_pty(state: State<'_, PtyState>, id: u32, mode: Option<String>) -> Result<(), String> {
+    let mode = mode.unwrap_or_else(|| "eof".into());
+    if !matches!(mode.as_str(), "eof" | "term" | "kill") {
+        return Err(format!("Unknown close mode: {}", mode));
+    }
+    // Take the session out first so the drain doesn't hold the lock
+    let session = state.sessions.lock().map_err(|e| e.to_string())?.remove(&id);
+    if let Some(session) = session {
+        log::info!("session {}: closing ({})", id, mode);
+        std::thread::spawn(move || shut_down(session, &mode));
+    }
+    Ok(())
+}
+
+fn shut_down(mut session: PtySession, mode: &str) {
+    drain_output(&session);
+    if session.exited.load(Ordering::Relaxed) {
+        let _ = session.child.try_wait();
+        return;
+    }
+    end_child(session, mode);
+}
+
+/// Ask the child to leave as `mode` says, killing it if it's still there
+/// after `CLOSE_TIMEOUT`. A killed child is reaped, so this can block.
+fn end_child(mut session: PtySession, mode: &str) {
+    let asked = match mode {
+        // Dropping the master's writer sends the terminal's EOF character
+        "eof" => {
+            drop(session.writer);
+            true
+        }
+        "term" => session
+            .child
+            .process_id()
+            .is_some_and(|pid| procinfo::terminate(pid).is_ok()),
+        _ => false,
+    };
+    if asked {
+        let deadline = Instant::now() + CLOSE_TIMEOUT;
+        while Instant::now() < deadline {
+            if let Ok(Some(_)) = session.child.try_wait() {
+                return;
+            }
+            std::thread::sleep(CLOSE_POLL);
+        }
+        log::info!("pid {:?} ignored {} close, killing", session.child.process_id(), mode);
+    }
+    if session.child.kill().is_ok() {
+        let _ = session.child.wait();
+    }
+}
+
+/// Remove a session kept around by `hold_on_exit` once the user is done with it.
+#[tauri::command]
+fn dismiss_pty(state: State<'_, PtyState>, id: u32) -> Result<(), String> {
+    let mut sessions = state.sessions.lock().map_err(|e| e.to_string())?;
+    let session = sessions.get(&id).ok_or("Session not found")?;
+    if !session.exited.load(Ordering::Relaxed) {
+        return Err("Session is still running".into());
+    }
+    sessions.remove(&id);
+    Ok(())
+}
+
+/// Close several sessions under one lock, e.g. stale ids after a reload.
+/// Each goes the way `close_pty` does with `mode` (default `"eof"`),
+/// draining output first. Returns an error message per id that couldn't be
+/// closed.
+#[tauri::command]
+fn close_ptys(
+    state: State<'_, PtyState>,
+    ids: Vec<u32>,
+    mode: Option<String>,
+) -> Result<HashMap<u32, String>, String> {
+    let mode = mode.unwrap_or_else(|| "eof".into());
+    if !matches!(mode.as_str(), "eof" | "term" | "kill") {
+        return Err(format!("Unknown close mode: {}", mode));
+    }
+    let mut errors = HashMap::new();
+    let mut closing = Vec::new();
+    {
+        let mut sessions = state.sessions.lock().map_err(|e| e.to_string())?;
+        for id in ids {
+            match sessions.remove(&id) {
+                Some(session) => closing.push((id, session)),
+                None => {
+                    errors.insert(id, "Session not found".to_string());
+                }
+            }
+        }
+    }
+    for (id, session) in closing {
+        log::info!("session {}: closing ({})", id, mode);
+        let mode = mode.clone();
+        std::thread::spawn(move || shut_down(session, &mode));
+    }
+    Ok(errors)
+}
+
+/// Whether a terminfo entry for `term` is installed, so the settings UI can
+/// warn before the user picks a TERM that would break colors and keys.
+#[tauri::command]
+fn check_terminfo(term: String) -> bool {
+    if cfg!(windows) {
+        // Windows console programs don't consult terminfo
+        return true;
+    }
+    let Some(first) = term.chars().next() else { return false };
+    if term.contains(['/', '\\']) {
+        return false;
+    }
+    let mut dirs: Vec<std::path::PathBuf> = Vec::new();
+    if let Some(dir) = std::env::var_os("TERMINFO") {
+        dirs.push(dir.into());
+    }
+    dirs.push(expand_home("~/.terminfo"));
+    if let Some(list) = std::env::var_os("TERMINFO_DIRS") {
+        dirs.extend(std::env::split_paths(&list).filter(|d| !d.as_os_str().is_empty()));
+    }
+    dirs.extend(
+        [
+            "/etc/terminfo",
+            "/lib/terminfo",
+            "/usr/share/terminfo",
+            "/usr/lib/terminfo",
+            "/usr/local/share/terminfo",
+            "/opt/homebrew/share/terminfo",
+        ]
+        .map(std::path::PathBuf::from),
+    );
+    // ncurses files entries under their first letter, or its hex code on
+    // case-insensitive filesystems (macOS)
+    let subdirs = [first.to_string(), format!("{:02x}", first as u32)];
+    dirs.iter()
+        .any(|dir| subdirs.iter().any(|sub| dir.join(sub).join(&term).is_file()))
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct BackendInfo {
+    crate_version: &'static str,
+    pty_backend: &'static str,
+    os: &'static str,
+    arch: &'static str,
+}
+
+/// Environment facts for bug reports.
+#[tauri::command]
+fn backend_info() -> BackendInfo {
+    BackendInfo {
+        crate_version: env!("CARGO_PKG_VERSION"),
+        // What native_pty_system() resolves to here
+        pty_backend: if cfg!(windows) { "conpty" } else { "unix" },
+        os: std::env::consts::OS,
+        arch: std::env::consts::ARCH,
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SessionDiagnostics {
+    id: u32,
+    pid: Option<u32>,
+    program: Option<String>,
+    size: Option<PtyDimensions>,
+    exited: bool,
+    bytes_in: u64,
+    bytes_out: u64,
+    cwd: Option<String>,
+    title: Option<String>,
+    foreground: Option<String>,
+    last_activity: Option<u64>,
+    output_filter_pid: Option<u32>,
+}
+
+#[derive(Serialize)]
+struct DiagnosticsReport {
+    backend: BackendInfo,
+    /// Env values (which may hold tokens) are replaced with `"<redacted>"`
+    config: config::Config,
+    sessions: Vec<SessionDiagnostics>,
+}
+
+/// Everything the backend knows, for a "Copy diagnostics" button.
+#[tauri::command]
+fn diagnostics(app: AppHandle, state: State<'_, PtyState>) -> Result<DiagnosticsReport, String> {
+    diagnostics_report(&state, config::load(&app))
+}
+
+/// The report for `state`, with `config`'s env values blanked out.
+fn diagnostics_report(state: &PtyState, mut config: config::Config) -> Result<DiagnosticsReport, String> {
+    let envs = std::iter::once(&mut config.global_env).chain(config.profiles.iter_mut().map(|p| &mut p.env));
+    for value in envs.flat_map(|env| env.values_mut()) {
+        *value = "<redacted>".into();
+    }
+    let sessions = state.sessions.lock().map_err(|e| e.to_string())?;
+    let mut report: Vec<SessionDiagnostics> = sessions
+        .iter()
+        .map(|(&id, s)| SessionDiagnostics {
+            id,
+            pid: s.child.process_id(),
+            program: s.options.program.clone(),
+            size: s.master.get_size().ok().map(PtyDimensions::from),
+            exited: s.exited.load(Ordering::Relaxed),
+            bytes_in: s.bytes_in.load(Ordering::Relaxed),
+            bytes_out: s.bytes_out.load(Ordering::Relaxed),
+            cwd: s.cwd(),
+            title: s.modes.title.lock().ok().and_then(|t| t.clone()),
+            foreground: foreground_pid(s).map(|pid| procinfo::name(pid).unwrap_or_else(|| pid.to_string())),
+            last_activity: s.last_activity(),
+            output_filter_pid: s.output_filter.as_ref().and_then(|f| Some(f.child.as_ref()?.id())),
+        })
+        .collect();
+    report.sort_by_key(|s| s.id);
+    Ok(DiagnosticsReport {
+        backend: backend_info(),
+        config,
+        sessions: report,
+    })
+}
+
+#[derive(Clone, Serialize)]
+struct GitInfo {
+    branch: Option<String>,
+    dirty: bool,
+    ahead: u32,
+    behind: u32,
+}
+
+/// How long a `git_status` result is reused for the same directory.
+const GIT_STATUS_TTL: Duration = Duration::from_secs(2);
+
+type GitCache = Mutex<HashMap<String, (Instant, Option<GitInfo>)>>;
+static GIT_STATUS_CACHE: std::sync::OnceLock<GitCache> = std::sync::OnceLock::new();
+
+/// Branch and working tree state of the repo at the session's cwd, for a
+/// status bar. `None` outside a repo or without git. Results are cached
+/// for a couple of seconds per directory so polling stays cheap.
+#[tauri::command(async)]
+fn git_status(state: State<'_, PtyState>, id: u32) -> Result<Option<GitInfo>, String> {
+    let cwd = {
+        let sessions = state.sessions.lock().map_err(|e| e.to_string())?;
+        sessions.get(&id).ok_or("Session not found")?.cwd()
+    };
+    let Some(cwd) = cwd else { return Ok(None) };
+    let cache = GIT_STATUS_CACHE.get_or_init(Default::default);
+    if let Some((at, info)) = cache.lock().map_err(|e| e.to_string())?.get(&cwd)
+        && at.elapsed() < GIT_STATUS_TTL
+    {
+        return Ok(info.clone());
+    }
+    let info = read_git_status(&cwd);
+    let mut cache = cache.lock().map_err(|e| e.to_string())?;
+    cache.retain(|_, (at, _)| at.elapsed() < GIT_STATUS_TTL);
+    cache.insert(cwd, (Instant::now(), info.clone()));
+    Ok(info)
+}
+
+fn read_git_status(cwd: &str) -> Option<GitInfo> {
+    let output = std::process::Command::new("git")
+        .args(["status", "--porcelain=v2", "--branch"])
+        .current_dir(cwd)
+        // Don't take index.lock away from the user's own git commands
+        .env("GIT_OPTIONAL_LOCKS", "0")
+        .stdin(std::process::Stdio::null())
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let mut info = GitInfo {
+        branch: None,
+        dirty: false,
+        ahead: 0,
+        behind: 0,
+    };
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        if let Some(head) = line.strip_prefix("# branch.head ") {
+            info.branch = (head != "(detached)").then(|| head.to_string());
+        } else if let Some(ab) = line.strip_prefix("# branch.ab ") {
+            for count in ab.split_whitespace() {
+                match count.split_at_checked(1) {
+                    Some(("+", n)) => info.ahead = n.parse().unwrap_or(0),
+                    Some(("-", n)) => info.behind = n.parse().unwrap_or(0),
+                    _ => {}
+                }
+            }
+        } else if !line.starts_with('#') {
+            info.dirty = true;
+        }
+    }
+    Some(info)
+}
+
+#[derive(Serialize)]
+struct CommandOutput {
+    stdout: String,
+    stderr: String,
+    /// `None` if the process was killed by a signal
+    code: Option<i32>,
+}
+
+const RUN_POLL: Duration = Duration::from_millis(10);
+
+/// Run a one-shot helper command (e.g. `git status`) to completion without
+/// a PTY and return its output. The child is killed if it outlives
+/// `timeout_ms`. With `force_pty` it runs in a throwaway PTY instead (see
+/// `run_in_pty`), so it colors its output as it would interactively; a PTY
+/// has only the one output stream, so everything lands in `stdout`.
+#[tauri::command(async)]
+fn run_command(
+    program: String,
+    args: Vec<String>,
+    cwd: Option<String>,
+    timeout_ms: u64,
+    force_pty: Option<bool>,
+) -> Result<CommandOutput, String> {
+    if force_pty.unwrap_or(false) {
+        let (output, code) = run_pty(program.clone(), args, cwd, HashMap::new(), timeout_ms)?;
+        let code = code.ok_or_else(|| format!("{} timed out after {}ms", program, timeout_ms))?;
+        return Ok(CommandOutput {
+            stdout: String::from_utf8_lossy(&output).into_owned(),
+            stderr: String::new(),
+            code: Some(code as i32),
+        });
+    }
+    let mut cmd = std::process::Command::new(&program);
+    cmd.args(&args)
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped());
+    if let Some(cwd) = &cwd {
+        cmd.current_dir(expand_home(cwd));
+    }
+    let mut child = cmd.spawn().map_err(|e| format!("{}: {}", program, e))?;
+
+    // Drain both pipes while we wait so a chatty child can't fill one and block
+    let read_pipe = |pipe: Option<Box<dyn Read + Send>>| {
+        std::thread::spawn(move || {
+            let mut buf = Vec::new();
+            if let Some(mut pipe) = pipe {
+                let _ = pipe.read_to_end(&mut buf);
+            }
+            String::from_utf8_lossy(&buf).into_owned()
+        })
+    };
+    let stdout = read_pipe(child.stdout.take().map(|p| Box::new(p) as Box<dyn Read + Send>));
+    let stderr = read_pipe(child.stderr.take().map(|p| Box::new(p) as Box<dyn Read + Send>));
+
+    let deadline = Instant::now() + Duration::from_millis(timeout_ms);
+    let status = loop {
+        if let Some(status) = child.try_wait().map_err(|e| e.to_string())? {
+            break status;
+        }
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(format!("{} timed out after {}ms", program, timeout_ms));
+        }
+        std::thread::sleep(RUN_POLL);
+    };
+    Ok(CommandOutput {
+        stdout: stdout.join().unwrap_or_default(),
+        stderr: stderr.join().unwrap_or_default(),
+        code: status.code(),
+    })
+}
+
+#[derive(Serialize)]
+struct PtyRunOutput {
+    /// Everything the program printed, escape sequences included
+    output_base64: String,
+    /// `None` if it timed out and was killed
+    code: Option<u32>,
+    timed_out: bool,
+}
+
+/// How long to keep reading after the child exits, for output still in flight
+const RUN_DRAIN: Duration = Duration::from_millis(200);
+
+/// Like `run_command`, but in a throwaway 80x24 PTY so the program colors
+/// and behaves as it would interactively. Returns everything it printed.
+/// The PTY never joins the session map, so nothing is left behind.
+#[tauri::command(async)]
+fn run_in_pty(
+    program: String,
+    args: Vec<String>,
+    cwd: Option<String>,
+    env: Option<HashMap<String, String>>,
+    timeout_ms: u64,
+) -> Result<PtyRunOutput, String> {
+    let (output, code) = run_pty(program, args, cwd, env.unwrap_or_default(), timeout_ms)?;
+    Ok(PtyRunOutput {
+        output_base64: base64_encode(&output),
+        code,
+        timed_out: code.is_none(),
+    })
+}
+
+/// What `program` printed to a throwaway PTY and its exit code, `None` if
+/// it was killed after `timeout_ms`.
+fn run_pty(
+    program: String,
+    args: Vec<String>,
+    cwd: Option<String>,
+    env: HashMap<String, String>,
+    timeout_ms: u64,
+) -> Result<(Vec<u8>, Option<u32>), String> {
+    if !program_exists(&program, cwd.as_deref()) {
+        return Err(format!("{}: program not found", program));
+    }
+    let options = PtyOptions {
+        program: Some(program.clone()),
+        args,
+        cwd,
+        env,
+        ..Default::default()
+    };
+    let size = DEFAULT_SIZE;
+    let pair = native_pty_system()
+        .openpty(size.into())
+        .map_err(|e| e.to_string())?;
+    let mut child = pair
+        .slave
+        .spawn_command(build_command(&options, size))
+        .map_err(|e| format!("{}: {}", program, e))?;
+    drop(pair.slave);
+    let mut reader = pair.master.try_clone_reader().map_err(|e| e.to_string())?;
+
+    // The read blocks, so it gets its own thread. If a background process
+    // the child left behind keeps the PTY open, that thread lingers until
+    // it exits, but we stop waiting on it after RUN_DRAIN.
+    let (tx, rx) = mpsc::channel();
+    std::thread::spawn(move || {
+        let mut buf = [0u8; 4096];
+        while let Ok(n) = reader.read(&mut buf) {
+            if n == 0 || tx.send(buf[..n].to_vec()).is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut output = Vec::new();
+    let deadline = Instant::now() + Duration::from_millis(timeout_ms);
+    let status = loop {
+        match rx.recv_timeout(RUN_POLL) {
+            Ok(chunk) => output.extend_from_slice(&chunk),
+            Err(mpsc::RecvTimeoutError::Timeout) => {}
+            // EOF, the child is about to be reapable
+            Err(mpsc::RecvTimeoutError::Disconnected) => std::thread::sleep(RUN_POLL),
+        }
+        if let Some(status) = child.try_wait().map_err(|e| e.to_string())? {
+            break Some(status);
+        }
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            break None;
+        }
+    };
+    let drain_until = Instant::now() + RUN_DRAIN;
+    while let Ok(chunk) = rx.recv_timeout(drain_until.saturating_duration_since(Instant::now())) {
+        output.extend_from_slice(&chunk);
+    }
+    Ok((output, status.map(|s| s.exit_code())))
+}
+
+const DEFAULT_WINDOW_TITLE: &str = "nanoprompt";
+
+/// Native window titles: what the user set explicitly, and the latest
+/// title a shell in the window asked for via OSC 0/2.
+#[derive(Default)]
+struct WindowTitles {
+    user: Mutex<HashMap<String, String>>,
+    shell: Mutex<HashMap<String, String>>,
+}
+
+/// Show a shell-set title on window `label` unless the user has set one.
+fn apply_shell_title(app: &AppHandle, label: &str, title: &str) {
+    let titles = app.state::<WindowTitles>();
+    if let Ok(mut shell) = titles.shell.lock() {
+        shell.insert(label.to_string(), title.to_string());
+    }
+    if titles.user.lock().is_ok_and(|user| user.contains_key(label)) {
+        return;
+    }
+    if let Some(window) = app.get_webview_window(label) {
+        let _ = window.set_title(title);
+    }
+}
+
+/// Set window `label`'s title explicitly (e.g. the user renamed it). Takes
+/// precedence over shell-set titles until `clear_window_title`.
+#[tauri::command]
+fn set_window_title(
+    app: AppHandle,
+    titles: State<'_, WindowTitles>,
+    label: String,
+    title: String,
+) -> Result<(), String> {
+    let window = app
+        .get_webview_window(&label)
+        .ok_or_else(|| format!("Window not found: {}", label))?;
+    window.set_title(&title).map_err(|e| e.to_string())?;
+    titles.user.lock().map_err(|e| e.to_string())?.insert(label, title);
+    Ok(())
+}
+
+/// Drop the explicit title, falling back to the shell's latest one.
+#[tauri::command]
+fn clear_window_title(app: AppHandle, titles: State<'_, WindowTitles>, label: String) -> Result<(), String> {
+    let window = app
+        .get_webview_window(&label)
+        .ok_or_else(|| format!("Window not found: {}", label))?;
+    titles.user.lock().map_err(|e| e.to_string())?.remove(&label);
+    let shell = titles.shell.lock().map_err(|e| e.to_string())?;
+    let title = shell.get(&label).map_or(DEFAULT_WINDOW_TITLE, |t| t.as_str());
+    window.set_title(title).map_err(|e| e.to_string())
+}
+
+/// Write the backend config, plus the frontend's settings if given, to
+/// `path` as a versioned bundle for `import_settings`.
+#[tauri::command]
+fn export_settings(app: AppHandle, path: String, frontend: Option<serde_json::Value>) -> Result<(), String> {
+    config::Bundle::new(config::read(&app)?, frontend).write(&expand_home(&path))
+}
+
+/// Load a bundle from `export_settings`, replacing the config or merging
+/// into it (see `Config::merge`). Emits `config-changed` and returns the
+/// bundle's frontend settings for the frontend to apply.
+#[tauri::command]
+fn import_settings(app: AppHandle, path: String, merge: bool) -> Result<Option<serde_json::Value>, String> {
+    let bundle = config::Bundle::read(&expand_home(&path))?;
+    for theme in &bundle.config.themes {
+        theme.validate()?;
+    }
+    let config = config::update(&app, |config| {
+        if merge {
+            config.merge(bundle.config);
+        } else {
+            *config = bundle.config;
+        }
+        config.clone()
+    })?;
+    let _ = app.emit("config-changed", config);
+    Ok(bundle.frontend)
+}
+
+#[tauri::command]
+fn open_config(app: AppHandle) -> Result<(), String> {
+    if let Some(window) = app.get_webview_window("config") {
+        let _ = window.set_focus();
+        return Ok(());
+    }
+    tauri::WebviewWindowBuilder::new(
+        &app,
+        "config",
+        tauri::WebviewUrl::App("config.html".into()),
+    )
+    .title("Settings")
+    .inner_size(420.0, 520.0)
+    .resizable(false)
+    .build()
+    .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+/// Open a new session with the same spawn options as `id`, starting in its
+/// current directory when that's known.
+#[tauri::command]
+fn duplicate_pty(
+    app: AppHandle,
+    state: State<'_, PtyState>,
+    rows: u16,
+    cols: u16,
+    id: u32,
+) -> Result<u32, String> {
+    let options = {
+        let sessions = state.sessions.lock().map_err(|e| e.to_string())?;
+        duplicate_options(sessions.get(&id).ok_or("Session not found")?)
+    };
+    Ok(spawn_session(&app, &state, rows, cols, options)?)
+}
+
+/// `session`'s spawn options, moved to its current directory if known.
+fn duplicate_options(session: &PtySession) -> PtyOptions {
+    let mut options = session.options.clone();
+    if let Some(cwd) = session.cwd() {
+        options.cwd = Some(cwd);
+    }
+    options
+}
+
+/// "New tab here": open a tab with the calling window's usual options, but
+/// starting in `source_id`'s current directory.
+#[tauri::command]
+fn new_tab_in_cwd(
+    app: AppHandle,
+    window: tauri::WebviewWindow,
+    state: State<'_, PtyState>,
+    rows: u16,
+    cols: u16,
+    source_id: u32,
+) -> Result<u32, String> {
+    let cwd = session_cwd(&state, source_id)?;
+    let mut options = window_options(&app, &window);
+    options.window = Some(window.label().to_string());
+    if cwd.is_some() {
+        options.cwd = cwd;
+    }
+    Ok(spawn_session(&app, &state, rows, cols, options)?)
+}
+
+fn session_cwd(state: &PtyState, id: u32) -> Result<Option<String>, String> {
+    let sessions = state.sessions.lock().map_err(|e| e.to_string())?;
+    Ok(sessions.get(&id).ok_or("Session not found")?.cwd())
+}
+
+/// Kill and respawn a session from its stored options, keeping its id so the
+/// frontend's terminal (and scrollback) carries on. Emits `pty-restarted`.
+fn restart_session(app: &AppHandle, state: &PtyState, id: u32) -> Result<(), String> {
+    let (options, size) = {
+        let sessions = state.sessions.lock().map_err(|e| e.to_string())?;
+        let session = sessions.get(&id).ok_or("Session not found")?;
+        let size = session
+            .master
+            .get_size()
+            .map(PtyDimensions::from)
+            .unwrap_or(DEFAULT_SIZE);
+        (session.options.clone(), size)
+    };
+
+    let namespace = options.event_namespace.clone();
+    // Spawn outside the lock; openpty/exec can be slow
+    let mut replacement = open_session(app, id, size, options)?;
+
+    let old = {
+        let mut sessions = state.sessions.lock().map_err(|e| e.to_string())?;
+        // Flag before swapping so the old reader can't mistake the
+        // replacement for its own session on the way out
+        if let Some(old) = sessions.get_mut(&id) {
+            old.superseded.store(true, Ordering::Relaxed);
+            replacement.metadata = old.metadata.take();
+        }
+        sessions.insert(id, replacement)
+    };
+    if let Some(old) = old {
+        // Its reader stays quiet (superseded), so reap it here
+        std::thread::spawn(move || end_child(old, "kill"));
+    }
+    emit_session(app, namespace.as_deref(), "pty-restarted", serde_json::json!({ "id": id }));
+    Ok(())
+}
+
+/// Restart every session so shell-affecting settings take effect. Returns
+/// per-id errors for sessions that failed to respawn.
+#[tauri::command]
+fn restart_all_sessions(app: AppHandle, state: State<'_, PtyState>) -> Result<HashMap<u32, String>, String> {
+    let ids: Vec<u32> = state
+        .sessions
+        .lock()
+        .map_err(|e| e.to_string())?
+        .keys()
+        .copied()
+        .collect();
+    let mut errors = HashMap::new();
+    for id in ids {
+        if let Err(e) = restart_session(&app, &state, id) {
+            errors.insert(id, e);
+        }
+    }
+    Ok(errors)
+}
+
+/// Restart only the sessions whose program path (the default shell for
+/// sessions without one) contains `program_substring`, e.g. the zsh tabs
+/// after upgrading zsh, leaving ssh sessions alone. Returns the restarted
+/// ids; failures are logged and left out.
+#[tauri::command]
+fn restart_sessions_matching(
+    app: AppHandle,
+    state: State<'_, PtyState>,
+    program_substring: String,
+) -> Result<Vec<u32>, String> {
+    if program_substring.is_empty() {
+        return Err("Pattern is empty".into());
+    }
+    let mut ids: Vec<u32> = state
+        .sessions
+        .lock()
+        .map_err(|e| e.to_string())?
+        .iter()
+        .filter(|(_, s)| runs_program(&s.options, &program_substring))
+        .map(|(&id, _)| id)
+        .collect();
+    ids.sort_unstable();
+    ids.retain(|&id| match restart_session(&app, &state, id) {
+        Ok(()) => true,
+        Err(e) => {
+            log::warn!("session {}: restart failed: {}", id, e);
+            false
+        }
+    });
+    Ok(ids)
+}
+
+/// Whether a session spawned with `options` runs a program whose path (the
+/// default shell's, without one) contains `substring`.
+fn runs_program(options: &PtyOptions, substring: &str) -> bool {
+    let program = options.program.clone().unwrap_or_else(default_shell);
+    program.contains(substring)
+}
+
+/// Hook script for `shell` and the rc file it should be sourced from.
+fn integration_script(shell: &str) -> Result<(&'static str, &'static str), String> {
+    match shell {
+        "zsh" => Ok((include_str!("../shell/nanoprompt.zsh"), "~/.zshrc")),
+        "bash" => Ok((include_str!("../shell/nanoprompt.bash"), "~/.bashrc")),
+        "fish" => Ok((include_str!("../shell/nanoprompt.fish"), "~/.config/fish/config.fish")),
+        other => Err(format!("Unsupported shell: {} (expected zsh, bash or fish)", other)),
+    }
+}
+
+/// The integration snippet for `shell`, for display.
+#[tauri::command]
+fn shell_integration_snippet(shell: String) -> Result<String, String> {
+    integration_script(&shell).map(|(script, _)| script.to_string())
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ShellIntegration {
+    path: String,
+    /// Line to add to `rc_file`
+    source_line: String,
+    rc_file: String,
+}
+
+/// Write the integration script for `shell` to the app data dir and report
+/// how to source it. The scripts only activate inside nanoprompt sessions.
+#[tauri::command]
+fn install_shell_integration(app: AppHandle, shell: String) -> Result<ShellIntegration, String> {
+    let (script, rc_file) = integration_script(&shell)?;
+    let dir = app
+        .path()
+        .app_data_dir()
+        .map_err(|e| e.to_string())?
+        .join("shell");
+    std::fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+    let path = dir.join(format!("nanoprompt.{}", shell));
+    std::fs::write(&path, script).map_err(|e| e.to_string())?;
+
+    let path = path.to_string_lossy().into_owned();
+    Ok(ShellIntegration {
+        source_line: format!("source \"{}\"", path),
+        path,
+        rc_file: rc_file.to_string(),
+    })
+}
+
+/// Whether we're registered as the default terminal (see `defaultterm`).
+#[tauri::command]
+fn is_default_terminal(app: AppHandle) -> bool {
+    defaultterm::is_default(&app.config().identifier)
+}
+
+/// Register as the default terminal for "open in terminal" actions. macOS
+/// and Linux (per user, via xdg-terminal-exec) only; see `defaultterm`.
+#[tauri::command]
+fn set_as_default_terminal(app: AppHandle) -> Result<(), String> {
+    defaultterm::set_default(&app.config().identifier)
+}
+
+/// Show or hide the native menu bar on every window and remember the choice.
+/// The macOS menu bar is global, so there it's a no-op.
+#[tauri::command]
+fn set_menu_visible(app: AppHandle, visible: bool) -> Result<(), String> {
+    if cfg!(target_os = "macos") {
+        return Ok(());
+    }
+    config::update(&app, |config| config.menu_visible = visible)?;
+    apply_menu_visibility(&app, visible)
+}
+
+fn apply_menu_visibility(app: &AppHandle, visible: bool) -> Result<(), String> {
+    for window in app.webview_windows().values() {
+        let result = if visible { window.show_menu() } else { window.hide_menu() };
+        result.map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+/// Ids of the menu items `accelerator` would clash with, so the settings UI
+/// can warn before a shortcut is bound; empty if it's free.
+#[tauri::command]
+fn accelerator_conflicts(accelerator: String) -> Result<Vec<String>, String> {
+    accelerator::conflicts(&accelerator)
+}
+
+/// Show `config.json` in the OS file manager, creating it first if needed.
+#[tauri::command]
+fn reveal_config_file(app: AppHandle) -> Result<(), String> {
+    let path = config::path(&app)?;
+    if !path.exists() {
+        config::save(&app, &config::Config::default())?;
+    }
+    app.opener()
+        .reveal_item_in_dir(&path)
+        .map_err(|e| format!("Could not open file manager: {}", e))
+}
+
+#[tauri::command]
+fn close_window(app: AppHandle) {
+    if let Some(window) = app.get_webview_window("main") {
+        let _ = window.hide();
+    }
+}
+
+/// The user agreed to close window `label` after `confirm-quit`: close its
+/// sessions (EOF, then kill, off the command thread) and the window itself.
+/// The main window is hidden rather than destroyed, as on a plain close.
+#[tauri::command]
+fn confirm_close(app: AppHandle, state: State<'_, PtyState>, label: String) -> Result<(), String> {
+    let window = app
+        .get_webview_window(&label)
+        .ok_or_else(|| format!("Window not found: {}", label))?;
+    let owned = take_window_sessions(&mut *state.sessions.lock().map_err(|e| e.to_string())?, &label);
+    log::info!("window {}: closing with {} sessions", label, owned.len());
+    for (id, session) in owned {
+        log::info!("session {}: closing (window closed)", id);
+        std::thread::spawn(move || shut_down(session, "eof"));
+    }
+    if label == "main" {
+        window.hide().map_err(|e| e.to_string())
+    } else {
+        window.destroy().map_err(|e| e.to_string())
+    }
+}
+
+/// Remove and return the sessions hosted by window `label`.
+fn take_window_sessions(sessions: &mut HashMap<u32, PtySession>, label: &str) -> Vec<(u32, PtySession)> {
+    let ids: Vec<u32> = sessions
+        .iter()
+        .filter(|(_, s)| s.options.window.as_deref() == Some(label))
+        .map(|(&id, _)| id)
+        .collect();
+    ids.into_iter().filter_map(|id| Some((id, sessions.remove(&id)?))).collect()
+}
+
+/// The user declined to close window `label`; it stays as it was.
+#[tauri::command]
+fn cancel_close(label: String) {
+    log::debug!("window {}: close cancelled", label);
+}
+
+fn window_has_running_sessions(app: &AppHandle, label: &str) -> bool {
+    let state = app.state::<PtyState>();
+    let Ok(sessions) = state.sessions.lock() else { return false };
+    sessions
+        .values()
+        .any(|s| s.options.window.as_deref() == Some(label) && !s.exited.load(Ordering::Relaxed))
+}
+
+#[tauri::command]
+fn force_quit(app: AppHandle) {
+    log::info!("force quit");
+    kill_all_sessions(&app);
+    app.exit(0);
+}
+
+/// Restart the app, e.g. after settings changes. Sessions are killed first
+/// (so exit isn't held up by the running-sessions check) and config is
+/// re-read from disk on the way back up.
+#[tauri::command]
+fn relaunch_app(app: AppHandle) {
+    kill_all_sessions(&app);
+    app.restart();
+}
+
+fn kill_all_sessions(app: &AppHandle) {
+    let state = app.state::<PtyState>();
+    let mut children = Vec::new();
+    if let Ok(mut sessions) = state.sessions.lock() {
+        for (_, mut session) in sessions.drain() {
+            let _ = session.child.kill();
+            children.push(session.child);
+        }
+    }
+    log::info!("killed {} sessions", children.len());
+    // Reap what we killed so nothing is left as a zombie; give up
+    // once the budget runs out and let the caller exit anyway
+    let deadline = Instant::now() + QUIT_REAP_BUDGET;
+    while !children.is_empty() && Instant::now() < deadline {
+        children.retain_mut(|child| matches!(child.try_wait(), Ok(None)));
+        if !children.is_empty() {
+            std::thread::sleep(REAP_POLL);
+        }
+    }
+    if !children.is_empty() {
+        log::warn!("{} children not reaped before exit", children.len());
+    }
+}
+
+/// Slave device path (e.g. `/dev/ttys003`). Always `None` on Windows.
+#[tauri::command]
+fn get_pty_name(state: State<'_, PtyState>, id: u32) -> Result<Option<String>, String> {
+    let sessions = state.sessions.lock().map_err(|e| e.to_string())?;
+    let session = sessions.get(&id).ok_or("Session not found")?;
+    Ok(session.tty_name.clone())
+}
+
+/// Environment the session's child was launched with. Best-effort: empty
+/// where the platform doesn't let us read another process's environment.
+#[tauri::command]
+fn get_pty_env(state: State<'_, PtyState>, id: u32) -> Result<HashMap<String, String>, String> {
+    let pid = {
+        let sessions = state.sessions.lock().map_err(|e| e.to_string())?;
+        let session = sessions.get(&id).ok_or("Session not found")?;
+        session.child.process_id()
+    };
+    Ok(pid.map(procinfo::environ).unwrap_or_default())
+}
+
+/// Process group leader currently in the terminal's foreground, if it isn't
+/// the shell we spawned.
+#[cfg(unix)]
+fn foreground_pid(session: &PtySession) -> Option<u32> {
+    let leader = session.master.process_group_leader()?;
+    let leader = u32::try_from(leader).ok()?;
+    (Some(leader) != session.child.process_id()).then_some(leader)
+}
+
+#[cfg(not(unix))]
+fn foreground_pid(_session: &PtySession) -> Option<u32> {
+    None
+}
+
+/// Send SIGINT to the terminal's foreground process group, whatever the
+/// line discipline's ISIG setting. Unlike writing `\x03`, this interrupts
+/// programs that put the terminal in raw mode too. On Windows, where there
+/// are no process groups to signal, ConPTY turns `\x03` into Ctrl-C.
+#[tauri::command]
+fn interrupt_pty(app: AppHandle, state: State<'_, PtyState>, id: u32) -> Result<(), String> {
+    let mut sessions = state.sessions.lock().map_err(|e| e.to_string())?;
+    let session = sessions.get_mut(&id).ok_or("Session not found")?;
+    if session.exited.load(Ordering::Relaxed) {
+        return Err("Session has exited".into());
+    }
+    if !session.accepts_input()? {
+        return Ok(());
+    }
+    #[cfg(unix)]
+    {
+        let _ = app;
+        interrupt_foreground(session)
+    }
+    #[cfg(not(unix))]
+    {
+        session.send(b"\x03").map_err(|e| input_error(&app, id, session, e))
+    }
+}
+
+/// SIGINT the session's foreground process group.
+#[cfg(unix)]
+fn interrupt_foreground(session: &PtySession) -> Result<(), String> {
+    let pgid = session
+        .master
+        .process_group_leader()
+        .ok_or("No foreground process group")?;
+    procinfo::interrupt_group(pgid as u32)
+}
+
+/// Name of the foreground program (e.g. `vim`) so the frontend can confirm
+/// before closing the tab. `None` when only the shell itself is in front.
+#[tauri::command]
+fn tab_has_foreground_process(state: State<'_, PtyState>, id: u32) -> Result<Option<String>, String> {
+    let sessions = state.sessions.lock().map_err(|e| e.to_string())?;
+    let session = sessions.get(&id).ok_or("Session not found")?;
+    if session.exited.load(Ordering::Relaxed) {
+        return Ok(None);
+    }
+    Ok(foreground_pid(session).map(|pid| procinfo::name(pid).unwrap_or_else(|| pid.to_string())))
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ResourceStats {
+    cpu_percent: f64,
+    rss_bytes: u64,
+}
+
+/// CPU and memory use of the session's child and its descendants. CPU is
+/// measured since the previous call, so the first reading is 0. `None`
+/// where the platform isn't supported.
+#[tauri::command]
+fn get_pty_resources(state: State<'_, PtyState>, id: u32) -> Result<Option<ResourceStats>, String> {
+    let mut sessions = state.sessions.lock().map_err(|e| e.to_string())?;
+    let session = sessions.get_mut(&id).ok_or("Session not found")?;
+    let Some(pid) = session.child.process_id() else { return Ok(None) };
+    let Some(usage) = procinfo::tree_usage(pid) else { return Ok(None) };
+
+    let now = Instant::now();
+    let cpu_percent = match session.cpu_sample {
+        Some((prev_cpu, prev_at)) => {
+            let wall = now.duration_since(prev_at).as_secs_f64();
+            let cpu = usage.cpu_time.saturating_sub(prev_cpu).as_secs_f64();
+            if wall > 0.0 { cpu / wall * 100.0 } else { 0.0 }
+        }
+        None => 0.0,
+    };
+    session.cpu_sample = Some((usage.cpu_time, now));
+
+    Ok(Some(ResourceStats {
+        cpu_percent,
+        rss_bytes: usage.rss_bytes,
+    }))
+}
+
+#[derive(Serialize)]
+struct ProcessNode {
+    pid: u32,
+    ppid: u32,
+    command: Option<String>,
+}
+
+/// The session's child and everything under it (shell, then `make`, then
+/// `cc`, ...), parents first. Empty where the platform isn't supported.
+#[tauri::command]
+fn get_process_tree(state: State<'_, PtyState>, id: u32) -> Result<Vec<ProcessNode>, String> {
+    let pid = {
+        let sessions = state.sessions.lock().map_err(|e| e.to_string())?;
+        sessions.get(&id).ok_or("Session not found")?.child.process_id()
+    };
+    Ok(pid.map(process_tree).unwrap_or_default())
+}
+
+fn process_tree(pid: u32) -> Vec<ProcessNode> {
+    procinfo::tree(pid)
+        .into_iter()
+        .map(|p| ProcessNode {
+            pid: p.pid,
+            ppid: p.ppid,
+            command: procinfo::name(p.pid),
+        })
+        .collect()
+}
+
+#[derive(Serialize)]
+struct OrphanInfo {
+    pid: u32,
+    ppid: u32,
+    name: Option<String>,
+}
+
+/// Processes left over from an earlier run: they carry our session marker
+/// but neither we nor another marked process is their parent. Unix only.
+#[tauri::command]
+fn find_orphans() -> Vec<OrphanInfo> {
+    let me = std::process::id();
+    let procs = procinfo::list();
+    let marked: HashSet<u32> = procs
+        .iter()
+        .filter(|p| procinfo::environ(p.pid).contains_key(SESSION_MARKER))
+        .map(|p| p.pid)
+        .collect();
+    procs
+        .iter()
+        .filter(|p| marked.contains(&p.pid) && p.ppid != me && !marked.contains(&p.ppid))
+        .map(|p| OrphanInfo {
+            pid: p.pid,
+            ppid: p.ppid,
+            name: procinfo::name(p.pid),
+        })
+        .collect()
+}
+
+/// SIGKILL the given orphans. Pids that no longer carry our marker (exited,
+/// or reused by something else) are refused. Returns per-pid errors.
+#[tauri::command]
+fn kill_orphans(pids: Vec<u32>) -> HashMap<u32, String> {
+    let mut errors = HashMap::new();
+    for pid in pids {
+        let result = if procinfo::environ(pid).contains_key(SESSION_MARKER) {
+            procinfo::kill(pid)
+        } else {
+            Err("Not a nanoprompt process".to_string())
+        };
+        if let Err(e) = result {
+            errors.insert(pid, e);
+        }
+    }
+    errors
+}
+
+#[tauri::command]
+fn list_profiles(app: AppHandle) -> Vec<Profile> {
+    config::load(&app).profiles
+}
+
+/// Check user-edited config JSON before saving it; every problem is returned.
+#[tauri::command]
+fn validate_config(json: String) -> Result<(), Vec<String>> {
+    config::validate(&json)
+}
+
+#[tauri::command]
+fn get_global_env(app: AppHandle) -> HashMap<String, String> {
+    config::load(&app).global_env
+}
+
+/// Replace the environment applied to every new session (e.g. EDITOR).
+/// Running sessions keep what they started with.
+#[tauri::command]
+fn set_global_env(app: AppHandle, vars: HashMap<String, String>) -> Result<(), String> {
+    if let Some(key) = vars.keys().find(|k| k.is_empty() || k.contains('=')) {
+        return Err(format!("Invalid variable name {:?}", key));
+    }
+    config::update(&app, |config| config.global_env = vars)
+}
+
+/// Insert or replace (by name) a profile.
+#[tauri::command]
+fn save_profile(app: AppHandle, profile: Profile) -> Result<(), String> {
+    if profile.name.trim().is_empty() {
+        return Err("Profile name is required".into());
+    }
+    let profiles = config::update(&app, |config| {
+        config.put_profile(profile);
+        config.profiles.clone()
+    })?;
+    let _ = app.emit("profiles-changed", profiles);
+    Ok(())
+}
+
+#[tauri::command]
+fn delete_profile(app: AppHandle, name: String) -> Result<(), String> {
+    let profiles = config::update(&app, |config| {
+        config.profiles.retain(|p| p.name != name);
+        config.profiles.clone()
+    })?;
+    let _ = app.emit("profiles-changed", profiles);
+    Ok(())
+}
+
+#[tauri::command]
+fn list_themes(app: AppHandle) -> Vec<Theme> {
+    config::load(&app).all_themes()
+}
+
+#[tauri::command]
+fn get_theme(app: AppHandle, name: String) -> Result<Theme, String> {
+    config::load(&app)
+        .all_themes()
+        .into_iter()
+        .find(|t| t.name == name)
+        .ok_or_else(|| format!("Theme not found: {}", name))
+}
+
+/// Insert or replace (by name) a user theme.
+#[tauri::command]
+fn save_theme(app: AppHandle, theme: Theme) -> Result<(), String> {
+    theme.validate()?;
+    config::update(&app, |config| config.put_theme(theme))
+}
+
+/// Parse an iTerm2 or Windows Terminal color scheme into a `Theme` the
+/// frontend can preview and pass to `save_theme`. Nothing is saved here.
+#[tauri::command]
+fn import_theme(path: String) -> Result<Theme, String> {
+    theme::import(&expand_home(&path))
+}
+
+/// Remove a user theme. Built-ins can't be deleted, only overridden.
+#[tauri::command]
+fn delete_theme(app: AppHandle, name: String) -> Result<(), String> {
+    config::update(&app, |config| {
+        let before = config.themes.len();
+        config.themes.retain(|t| t.name != name);
+        if config.themes.len() == before {
+            return Err(if theme::builtin().iter().any(|t| t.name == name) {
+                format!("Built-in theme can't be deleted: {}", name)
+            } else {
+                format!("Theme not found: {}", name)
+            });
+        }
+        Ok(())
+    })?
+}
+
+/// Persist `name` as the active theme and emit `theme-changed` with it.
+#[tauri::command]
+fn set_active_theme(app: AppHandle, name: String) -> Result<(), String> {
+    let theme = config::update(&app, |config| {
+        let theme = config.all_themes().into_iter().find(|t| t.name == name);
+        if theme.is_some() {
+            config.active_theme = Some(name.clone());
+        }
+        theme
+    })?
+    .ok_or_else(|| format!("Theme not found: {}", name))?;
+    let _ = app.emit("theme-changed", theme);
+    Ok(())
+}
+
+/// Associate window `label` with a profile for its new tabs; `None` clears
+/// it so the window follows the global default again.
+#[tauri::command]
+fn set_window_profile(app: AppHandle, label: String, profile: Option<String>) -> Result<(), String> {
+    config::update(&app, |config| {
+        if let Some(name) = &profile
+            && !config.profiles.iter().any(|p| &p.name == name)
+        {
+            return Err(format!("Profile not found: {}", name));
+        }
+        match profile {
+            Some(name) => config.window_profiles.insert(label, name),
+            None => config.window_profiles.remove(&label),
+        };
+        Ok(())
+    })?
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct PtyInfo {
+    id: u32,
+    pid: Option<u32>,
+    exited: bool,
+    alt_screen: bool,
+    app_cursor_keys: bool,
+    bracketed_paste: bool,
+    sync_update: bool,
+    input_enabled: bool,
+    group_id: Option<u32>,
+    last_activity: Option<u64>,
+    bytes_in: u64,
+    bytes_out: u64,
+    buffer_size: usize,
+    metadata: Option<String>,
+}
+
+/// Largest `set_pty_metadata` blob
+const MAX_METADATA_BYTES: usize = 16 * 1024;
+
+/// Attach an opaque JSON string (tab color, notes, ...) to session `id`,
+/// replacing any previous one, so the frontend can restore it after a
+/// reload. It survives restarts and shows up in `list_ptys`.
+#[tauri::command]
+fn set_pty_metadata(state: State<'_, PtyState>, id: u32, json: String) -> Result<(), String> {
+    set_metadata(&state, id, json)
+}
+
+fn set_metadata(state: &PtyState, id: u32, json: String) -> Result<(), String> {
+    if json.len() > MAX_METADATA_BYTES {
+        return Err(format!("Metadata is larger than the {}KB limit", MAX_METADATA_BYTES / 1024));
+    }
+    serde_json::from_str::<serde_json::Value>(&json).map_err(|e| format!("Invalid metadata JSON: {}", e))?;
+    let mut sessions = state.sessions.lock().map_err(|e| e.to_string())?;
+    sessions.get_mut(&id).ok_or("Session not found")?.metadata = Some(json);
+    Ok(())
+}
+
+#[tauri::command]
+fn get_pty_metadata(state: State<'_, PtyState>, id: u32) -> Result<Option<String>, String> {
+    get_metadata(&state, id)
+}
+
+fn get_metadata(state: &PtyState, id: u32) -> Result<Option<String>, String> {
+    let sessions = state.sessions.lock().map_err(|e| e.to_string())?;
+    Ok(sessions.get(&id).ok_or("Session not found")?.metadata.clone())
+}
+
+/// Restart session ids from 1, for tests. Refused while any session is
+/// open, since a new one could then reuse a live id.
+#[tauri::command]
+fn reset_id_counter(app: AppHandle, state: State<'_, PtyState>) -> Result<(), String> {
+    let sessions = state.sessions.lock().map_err(|e| e.to_string())?;
+    if !sessions.is_empty() {
+        return Err("Close all sessions first".into());
+    }
+    ids::reset(&app)?;
+    state.next_id.store(1, Ordering::Relaxed);
+    Ok(())
+}
+
+#[tauri::command]
+fn list_ptys(state: State<'_, PtyState>) -> Result<Vec<PtyInfo>, String> {
+    list_sessions(&state)
+}
+
+fn list_sessions(state: &PtyState) -> Result<Vec<PtyInfo>, String> {
+    let sessions = state.sessions.lock().map_err(|e| e.to_string())?;
+    let mut list: Vec<PtyInfo> = sessions
+        .iter()
+        .map(|(&id, s)| PtyInfo {
+            id,
+            pid: s.child.process_id(),
+            exited: s.exited.load(Ordering::Relaxed),
+            alt_screen: s.modes.alt_screen.load(Ordering::Relaxed),
+            app_cursor_keys: s.modes.app_cursor_keys.load(Ordering::Relaxed),
+            bracketed_paste: s.modes.bracketed_paste.load(Ordering::Relaxed),
+            sync_update: s.modes.sync_update.load(Ordering::Relaxed),
+            input_enabled: s.input_enabled,
+            group_id: s.options.group_id,
+            last_activity: s.last_activity(),
+            bytes_in: s.bytes_in.load(Ordering::Relaxed),
+            bytes_out: s.bytes_out.load(Ordering::Relaxed),
+            buffer_size: s.buffer_size,
+            metadata: s.metadata.clone(),
+        })
+        .collect();
+    list.sort_by_key(|info| info.id);
+    Ok(list)
+}
+
+#[derive(Serialize)]
+struct SessionStats {
+    total: usize,
+    running: usize,
+    exited: usize,
+}
+
+#[tauri::command]
+fn session_stats(state: State<'_, PtyState>) -> Result<SessionStats, String> {
+    let sessions = state.sessions.lock().map_err(|e| e.to_string())?;
+    let running = sessions
+        .values()
+        .filter(|s| !s.exited.load(Ordering::Relaxed))
+        .count();
+    Ok(SessionStats {
+        total: sessions.len(),
+        running,
+        exited: sessions.len() - running,
+    })
+}
+
+/// A terminal another app asked us to open, from the command line
+/// (`nanoprompt [DIR|FILE] [-e COMMAND...]`, a file meaning its directory)
+/// or a `nanoprompt://open?cwd=...&command=...` link.
+#[derive(Clone, Default, Serialize)]
+struct OpenRequest {
+    cwd: Option<String>,
+    /// To run once the shell is up
+    command: Option<String>,
+    /// `"cli"` or `"link"`. Any web page can fire a link, so confirm before
+    /// running a link's command.
+    source: &'static str,
+}
+
+/// Open requests the frontend hasn't picked up yet, see `take_open_requests`.
+#[derive(Default)]
+struct PendingOpens(Mutex<Vec<OpenRequest>>);
+
+/// Read a launch's arguments (program name excluded). Relative paths resolve
+/// against `cwd`, the launching process's directory.
+fn open_request_from_args(args: &[String], cwd: &std::path::Path) -> Option<OpenRequest> {
+    let mut request = OpenRequest {
+        source: "cli",
+        ..Default::default()
+    };
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-e" | "--command" => {
+                let command: Vec<&str> = args.by_ref().map(String::as_str).collect();
+                request.command = (!command.is_empty()).then(|| command.join(" "));
+            }
+            // Windows and Linux deliver links as arguments
+            link if link.starts_with("nanoprompt://") => {
+                return tauri::Url::parse(link).ok().as_ref().and_then(open_request_from_url);
+            }
+            // Not ours (e.g. macOS's -psn_ from older launchers)
+            flag if flag.starts_with('-') => {}
+            path => {
+                let path = cwd.join(expand_home(path));
+                let dir = if path.is_dir() { Some(path.as_path()) } else { path.parent() };
+                request.cwd = dir.map(|d| d.to_string_lossy().into_owned());
+            }
+        }
+    }
+    (request.cwd.is_some() || request.command.is_some()).then_some(request)
+}
+
+fn open_request_from_url(url: &tauri::Url) -> Option<OpenRequest> {
+    if url.scheme() != "nanoprompt" || url.host_str() != Some("open") {
+        return None;
+    }
+    let mut request = OpenRequest {
+        source: "link",
+        ..Default::default()
+    };
+    for (key, value) in url.query_pairs() {
+        match &*key {
+            "cwd" => request.cwd = Some(value.into_owned()),
+            "command" => request.command = Some(value.into_owned()),
+            _ => {}
+        }
+    }
+    Some(request)
+}
+
+/// Queue `request` and emit `open-session` so a listening frontend collects
+/// it; a frontend that's still loading collects it on startup instead.
+fn request_open(app: &AppHandle, request: OpenRequest) {
+    log::info!("open requested: cwd {:?}, from {}", request.cwd, request.source);
+    if let Ok(mut pending) = app.state::<PendingOpens>().0.lock() {
+        pending.push(request);
+    }
+    if let Some(window) = app.get_webview_window("main") {
+        let _ = window.show();
+        let _ = window.set_focus();
+    }
+    let _ = app.emit("open-session", ());
+}
+
+/// Directories sessions have been in (reported via OSC 7), most recent
+/// first, without duplicates or ones that no longer exist.
+#[tauri::command]
+fn recent_directories(recent: State<'_, recent::RecentDirs>, limit: usize) -> Vec<String> {
+    recent.list(limit)
+}
+
+/// Open requests received so far (see `OpenRequest`), oldest first. Call on
+/// startup and on every `open-session` event.
+#[tauri::command]
+fn take_open_requests(pending: State<'_, PendingOpens>) -> Result<Vec<OpenRequest>, String> {
+    let mut pending = pending.0.lock().map_err(|e| e.to_string())?;
+    Ok(std::mem::take(&mut *pending))
+}
+
+fn has_running_sessions(app: &AppHandle) -> bool {
+    let state = app.state::<PtyState>();
+    let sessions = state.sessions.lock().unwrap();
+    sessions.values().any(|s| !s.exited.load(Ordering::Relaxed))
+}
+
+#[cfg_attr(mobile, tauri::mobile_entry_point)]
+pub fn run() {
+    let app = tauri::Builder::default()
+        // Must come first: a second launch hands its arguments over and exits
+        .plugin(tauri_plugin_single_instance::init(|app, argv, cwd| {
+            let args = argv.get(1..).unwrap_or_default();
+            match open_request_from_args(args, std::path::Path::new(&cwd)) {
+                Some(request) => request_open(app, request),
+                None => {
+                    if let Some(window) = app.get_webview_window("main") {
+                        let _ = window.set_focus();
+                    }
+                }
+            }
+        }))
+        .plugin(tauri_plugin_deep_link::init())
         .plugin(tauri_plugin_opener::init())
         .manage(PtyState {
             sessions: Mutex::new(HashMap::new()),
             next_id: AtomicU32::new(1),
+            reserved: AtomicUsize::new(0),
         })
+        .manage(WindowTitles::default())
+        .manage(PendingOpens::default())
         .setup(|app| {
             let handle = app.handle();
 
+            #[cfg(feature = "logging")]
+            if let Err(e) = logging::init(handle) {
+                eprintln!("[nanoprompt] logging disabled: {}", e);
+            }
+            app.manage(recent::RecentDirs::load(handle));
+            app.state::<PtyState>()
+                .next_id
+                .store(ids::load(handle), Ordering::Relaxed);
+
+            // Installers register the scheme; this covers dev builds and AppImages
+            #[cfg(any(target_os = "linux", windows))]
+            if let Err(e) = handle.deep_link().register_all() {
+                log::warn!("could not register nanoprompt:// links: {}", e);
+            }
+            let link_handle = handle.clone();
+            handle.deep_link().on_open_url(move |event| {
+                for url in event.urls() {
+                    if let Some(request) = open_request_from_url(&url) {
+                        request_open(&link_handle, request);
+                    }
+                }
+            });
+            let args: Vec<String> = std::env::args().skip(1).collect();
+            let cwd = std::env::current_dir().unwrap_or_default();
+            if let Some(request) = open_request_from_args(&args, &cwd) {
+                request_open(handle, request);
+            }
+
             let app_menu = SubmenuBuilder::new(handle, "nanoprompt")
                 .item(&PredefinedMenuItem::about(handle, None, None)?)
                 .separator()
                 .item(&MenuItemBuilder::new("Settings...")
                     .id("settings")
-                    .accelerator("CmdOrCtrl+,")
+                    .accelerator(accelerator::for_item("settings"))
                     .build(handle)?)
                 .separator()
                 .item(&PredefinedMenuItem::hide(handle, None)?)
@@ -300,23 +4327,23 @@ pub fn run() {
                 .separator()
                 .item(&MenuItemBuilder::new("Quit nanoprompt")
                     .id("quit")
-                    .accelerator("CmdOrCtrl+Q")
+                    .accelerator(accelerator::for_item("quit"))
                     .build(handle)?)
                 .build()?;
 
             let file_menu = SubmenuBuilder::new(handle, "File")
                 .item(&MenuItemBuilder::new("New Tab")
                     .id("new_tab")
-                    .accelerator("CmdOrCtrl+T")
+                    .accelerator(accelerator::for_item("new_tab"))
                     .build(handle)?)
                 .separator()
                 .item(&MenuItemBuilder::new("Close Tab")
                     .id("close_tab")
-                    .accelerator("CmdOrCtrl+W")
+                    .accelerator(accelerator::for_item("close_tab"))
                     .build(handle)?)
                 .item(&MenuItemBuilder::new("Close Window")
                     .id("close_window")
-                    .accelerator("CmdOrCtrl+Shift+W")
+                    .accelerator(accelerator::for_item("close_window"))
                     .build(handle)?)
                 .build()?;
 
@@ -343,17 +4370,115 @@ pub fn run() {
                 .build()?;
 
             app.set_menu(menu)?;
+
+            if !cfg!(target_os = "macos") && !config::load(handle).menu_visible {
+                let _ = apply_menu_visibility(handle, false);
+            }
             Ok(())
         })
         .invoke_handler(tauri::generate_handler![
             create_pty,
+            create_pty_async,
+            create_pty_from_profile,
+            create_tmux_pty,
+            duplicate_pty,
+            new_tab_in_cwd,
             write_pty,
+            flush_pty,
+            query_pty,
+            ping_pty,
+            set_pty_input_enabled,
+            paste_pty,
+            write_file_to_pty,
+            cancel_write,
+            write_pty_line,
+            soft_reset_pty,
+            tap_pty,
+            untap_pty,
+            rotate_pty_log,
+            mirror_pty,
+            unmirror_pty,
+            start_recording,
+            stop_recording,
+            start_recording_all,
+            stop_recording_all,
             resize_pty,
+            get_pty_size,
+            resize_pty_repaint,
+            resize_group,
+            resize_window_sessions,
             close_pty,
+            close_ptys,
+            dismiss_pty,
+            session_stats,
+            list_ptys,
+            set_pty_metadata,
+            get_pty_metadata,
+            reset_id_counter,
+            last_activity,
+            get_pty_counters,
+            get_init_output,
+            get_key_encoding,
+            get_scrollback,
+            trim_scrollback,
+            export_scrollback_html,
+            get_command_blocks,
+            clear_command_blocks,
+            restart_all_sessions,
+            restart_sessions_matching,
+            get_pty_name,
+            get_pty_env,
+            tab_has_foreground_process,
+            interrupt_pty,
+            get_pty_resources,
+            get_process_tree,
+            find_orphans,
+            kill_orphans,
+            list_profiles,
+            validate_config,
+            get_global_env,
+            set_global_env,
+            save_profile,
+            delete_profile,
+            list_themes,
+            get_theme,
+            save_theme,
+            import_theme,
+            delete_theme,
+            set_active_theme,
+            set_window_profile,
             load_font,
+            fallback_font,
+            load_default_monospace,
+            load_font_family,
+            list_monospace_fonts,
+            decode_base64,
+            backend_info,
+            diagnostics,
+            git_status,
+            default_shell,
+            run_command,
+            run_in_pty,
+            check_terminfo,
             open_config,
+            reveal_config_file,
+            export_settings,
+            import_settings,
+            shell_integration_snippet,
+            install_shell_integration,
+            is_default_terminal,
+            take_open_requests,
+            recent_directories,
+            set_as_default_terminal,
+            set_menu_visible,
+            accelerator_conflicts,
+            set_window_title,
+            clear_window_title,
             close_window,
+            confirm_close,
+            cancel_close,
             force_quit,
+            relaunch_app,
         ])
         .on_menu_event(|app, event| {
             match event.id().as_ref() {
@@ -363,7 +4488,8 @@ pub fn run() {
                 "close_window" => { close_window(app.clone()); }
                 "quit" => {
                     if has_running_sessions(app) {
-                        let _ = app.emit("confirm-quit", ());
+                        // No label: the whole app, answered with force_quit
+                        let _ = app.emit("confirm-quit", serde_json::json!({ "label": null }));
                     } else {
                         app.exit(0);
                     }
@@ -372,11 +4498,32 @@ pub fn run() {
             }
         })
         .on_window_event(|window, event| {
-            if window.label() == "main" {
-                if let tauri::WindowEvent::CloseRequested { api, .. } = event {
+            match event {
+                // Ask first when tabs are still running; the frontend answers
+                // with confirm_close or cancel_close
+                tauri::WindowEvent::CloseRequested { api, .. }
+                    if window_has_running_sessions(window.app_handle(), window.label()) =>
+                {
+                    api.prevent_close();
+                    // Only that window asks; `emit` would reach every window
+                    let _ = window.app_handle().emit_to(
+                        window.label(),
+                        "confirm-quit",
+                        serde_json::json!({ "label": window.label() }),
+                    );
+                }
+                tauri::WindowEvent::CloseRequested { api, .. } if window.label() == "main" => {
                     api.prevent_close();
                     let _ = window.hide();
                 }
+                tauri::WindowEvent::Destroyed => remove_mirror_target(window.app_handle(), window.label()),
+                tauri::WindowEvent::Focused(focused) => {
+                    let _ = window.emit("window-focus", serde_json::json!({
+                        "label": window.label(),
+                        "focused": focused
+                    }));
+                }
+                _ => {}
             }
         })
         .build(tauri::generate_context!())
@@ -391,12 +4538,1251 @@ pub fn run() {
                     let _ = window.set_focus();
                 }
             }
-            tauri::RunEvent::ExitRequested { api, .. } => {
-                if has_running_sessions(app_handle) {
-                    api.prevent_exit();
+            tauri::RunEvent::ExitRequested { api, .. } if has_running_sessions(app_handle) => {
+                api.prevent_exit();
+            }
+            tauri::RunEvent::Exit => {
+                if let Some(recent) = app_handle.try_state::<recent::RecentDirs>() {
+                    recent.flush();
                 }
             }
             _ => {}
         }
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A session running `cat` on a real PTY, without the reader thread and
+    /// the rest of `open_session`'s plumbing. `cat` exits once the master
+    /// is dropped.
+    #[cfg(unix)]
+    fn test_session(options: PtyOptions) -> PtySession {
+        let options = PtyOptions {
+            program: options.program.clone().or(Some("cat".into())),
+            ..options
+        };
+        let pair = native_pty_system().openpty(DEFAULT_SIZE.into()).unwrap();
+        let child = pair.slave.spawn_command(build_command(&options, DEFAULT_SIZE)).unwrap();
+        let writer = pair.master.take_writer().unwrap();
+        PtySession {
+            master: pair.master,
+            writer,
+            child,
+            exited: Default::default(),
+            buffer_size: DEFAULT_BUFFER_SIZE,
+            bytes_in: Default::default(),
+            bytes_out: Default::default(),
+            last_activity: Default::default(),
+            tty_name: None,
+            options,
+            superseded: Default::default(),
+            cpu_sample: None,
+            modes: Default::default(),
+            tap: Default::default(),
+            recording: Default::default(),
+            scrollback: None,
+            command_blocks: Default::default(),
+            mirrors: Default::default(),
+            pty_log: None,
+            output_filter: None,
+            init_output: None,
+            capture: Default::default(),
+            input_enabled: true,
+            drop_locked_input: false,
+            write_cancelled: false,
+            metadata: None,
+        }
+    }
+
+    #[cfg(unix)]
+    fn in_window(label: &str) -> PtyOptions {
+        PtyOptions {
+            window: Some(label.into()),
+            ..Default::default()
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn closing_a_window_takes_only_its_sessions() {
+        let mut sessions = HashMap::from([
+            (1, test_session(in_window("main"))),
+            (2, test_session(in_window("window-2"))),
+            (3, test_session(in_window("main"))),
+        ]);
+        let mut taken: Vec<u32> = take_window_sessions(&mut sessions, "main")
+            .into_iter()
+            .map(|(id, _)| id)
+            .collect();
+        taken.sort();
+        assert_eq!(taken, [1, 3]);
+        assert_eq!(sessions.keys().copied().collect::<Vec<_>>(), [2]);
+    }
+
+    #[test]
+    fn paste_payload_brackets_multiline_text() {
+        let payload = paste_payload(b"echo one\necho two\n".to_vec(), true);
+        assert_eq!(payload, b"\x1b[200~echo one\necho two\n\x1b[201~");
+    }
+
+    #[test]
+    fn paste_payload_strips_embedded_end_markers() {
+        let payload = paste_payload(b"a\x1b[201~rm -rf ~\n".to_vec(), true);
+        assert_eq!(payload, b"\x1b[200~arm -rf ~\n\x1b[201~");
+        // Removing one marker mustn't splice another together
+        let payload = paste_payload(b"\x1b[20\x1b[201~1~".to_vec(), false);
+        assert_eq!(payload, b"");
+    }
+
+    #[test]
+    fn base64_round_trips() {
+        for len in 0..=8 {
+            let data: Vec<u8> = (0..len).map(|i| (i * 37 + 200) as u8).collect();
+            assert_eq!(base64_decode(&base64_encode(&data)).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn base64_decode_rejects_unpadded_length() {
+        assert!(base64_decode("QUJDRA").is_err());
+        assert!(base64_decode("QUJ").is_err());
+    }
+
+    #[test]
+    fn sanitize_input_strips_escape_sequences() {
+        assert_eq!(sanitize_input("ls\x1b]0;x\x07 -l\n".into()), ("ls -l\n".into(), true));
+        assert_eq!(sanitize_input("a\x1b[31mb\x1b]8;;u\x1b\\c\x03".into()), ("abc".into(), true));
+        assert_eq!(sanitize_input("tab\tand\r\n".into()), ("tab\tand\r\n".into(), false));
+    }
+
+    #[test]
+    fn sanitize_input_strips_del_and_c1_controls() {
+        assert_eq!(sanitize_input("rm\x7f\x7f\x7fls".into()), ("rmls".into(), true));
+        assert_eq!(sanitize_input("a\u{9b}31mb\u{85}c\u{80}".into()), ("abc".into(), true));
+        assert_eq!(sanitize_input("x\u{9d}0;t\u{9c}y".into()), ("xy".into(), true));
+        assert_eq!(sanitize_input("caf\u{e9} \u{a0}ok".into()), ("caf\u{e9} \u{a0}ok".into(), false));
+    }
+
+    #[test]
+    fn normalize_line_endings_handles_mixed_input() {
+        let text = "a\r\nb\nc\rd";
+        assert_eq!(normalize_line_endings(text, "\r"), "a\rb\rc\rd");
+        assert_eq!(normalize_line_endings(text, "\n"), "a\nb\nc\nd");
+        assert_eq!(normalize_line_endings(text, "\r\n"), "a\r\nb\r\nc\r\nd");
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn credentials_wrap_the_command_in_setpriv() {
+        let options = PtyOptions {
+            program: Some("/bin/echo".into()),
+            args: vec!["hi".into()],
+            uid: Some(1000),
+            ..Default::default()
+        };
+        let cmd = build_command(&options, DEFAULT_SIZE);
+        let argv: Vec<_> = cmd.get_argv().iter().map(|a| a.to_string_lossy().into_owned()).collect();
+        assert_eq!(argv, ["setpriv", "--reuid=1000", "--clear-groups", "--", "/bin/echo", "hi"]);
+    }
+
+    /// Run `options` on a PTY to completion and return what it printed.
+    #[cfg(unix)]
+    fn pty_output(options: &PtyOptions) -> String {
+        sized_pty_output(options, DEFAULT_SIZE)
+    }
+
+    #[cfg(unix)]
+    fn sized_pty_output(options: &PtyOptions, size: PtyDimensions) -> String {
+        let pair = native_pty_system().openpty(size.into()).unwrap();
+        let mut child = pair.slave.spawn_command(build_command(options, size)).unwrap();
+        drop(pair.slave);
+        let mut reader = pair.master.try_clone_reader().unwrap();
+        let mut output = Vec::new();
+        let mut buf = [0u8; 4096];
+        // Linux reports EIO rather than EOF once the child side is gone
+        while let Ok(n @ 1..) = reader.read(&mut buf) {
+            output.extend_from_slice(&buf[..n]);
+        }
+        child.wait().unwrap();
+        String::from_utf8_lossy(&output).into_owned()
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn arg0_sets_dollar_zero() {
+        let options = PtyOptions {
+            program: Some("/bin/sh".into()),
+            args: vec!["-c".into(), "echo \"[$0]\"".into()],
+            arg0: Some("renamed-shell".into()),
+            ..Default::default()
+        };
+        assert!(pty_output(&options).contains("[renamed-shell]"));
+    }
+
+    #[test]
+    fn restart_matches_program_substring() {
+        let program = |p: &str| PtyOptions {
+            program: Some(p.into()),
+            ..Default::default()
+        };
+        assert!(runs_program(&program("/usr/local/bin/zsh"), "zsh"));
+        assert!(!runs_program(&program("ssh"), "zsh"));
+        assert!(runs_program(&PtyOptions::default(), &default_shell()));
+    }
+
+    /// Whether `pid` is still around, zombie or not.
+    #[cfg(unix)]
+    fn process_exists(pid: u32) -> bool {
+        // SAFETY: signal 0 only checks that the pid exists
+        unsafe { libc::kill(pid as libc::pid_t, 0) == 0 }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn killed_children_are_reaped() {
+        let session = test_session(PtyOptions::default());
+        let pid = session.child.process_id().unwrap();
+        end_child(session, "kill");
+        assert!(!process_exists(pid));
+    }
+
+    /// Stand-in for the reader thread: collects `session`'s output, counts
+    /// it in `bytes_in` and copies it to whoever is waiting in
+    /// `query_pty`/`ping_pty`.
+    #[cfg(unix)]
+    fn start_reader(session: &PtySession) -> Arc<Mutex<Vec<u8>>> {
+        let mut reader = session.master.try_clone_reader().unwrap();
+        let (capture, bytes_in) = (session.capture.clone(), session.bytes_in.clone());
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let collected = output.clone();
+        std::thread::spawn(move || {
+            let mut buf = [0u8; 4096];
+            while let Ok(n @ 1..) = reader.read(&mut buf) {
+                collected.lock().unwrap().extend_from_slice(&buf[..n]);
+                bytes_in.fetch_add(n as u64, Ordering::Relaxed);
+                if let Some(tx) = capture.lock().ok().and_then(|c| c.clone()) {
+                    let _ = tx.send(buf[..n].to_vec());
+                }
+            }
+        });
+        output
+    }
+
+    #[cfg(unix)]
+    fn test_state(sessions: impl IntoIterator<Item = (u32, PtySession)>) -> PtyState {
+        PtyState {
+            sessions: Mutex::new(sessions.into_iter().collect()),
+            next_id: AtomicU32::new(1),
+            reserved: AtomicUsize::new(0),
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn ping_times_the_echo() {
+        let session = test_session(PtyOptions::default());
+        start_reader(&session);
+        let state = test_state([(1, session)]);
+        let timeout = Duration::from_secs(5);
+        let started = Instant::now();
+        let latency = ping(&state, 1, timeout, |_, _, e| e.to_string()).unwrap();
+        assert!(latency as u128 <= started.elapsed().as_millis());
+        assert!(ping(&state, 2, timeout, |_, _, e| e.to_string()).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn query_returns_output_up_to_the_terminator() {
+        let session = test_session(PtyOptions::default());
+        start_reader(&session);
+        let state = test_state([(1, session)]);
+        // cat echoes the line back (with the terminal's echo in front)
+        let reply = query(&state, 1, b"ping!\n", b'!', Duration::from_secs(5), |_, _, e| e.to_string()).unwrap();
+        assert_eq!(reply, b"ping!");
+        let err = query(&state, 1, b"", b'#', Duration::from_millis(50), |_, _, e| e.to_string()).unwrap_err();
+        assert!(err.contains("Timed out"), "{}", err);
+    }
+
+    #[test]
+    fn trimming_keeps_the_last_lines() {
+        let text: String = (1..=100).map(|i| format!("line {}\n", i)).collect();
+        let mut buffer: VecDeque<u8> = text.bytes().collect();
+        trim_lines(&mut buffer, 10);
+        let expected: String = (91..=100).map(|i| format!("line {}\n", i)).collect();
+        assert_eq!(buffer, expected.as_bytes());
+
+        buffer.extend(b"$ ");
+        trim_lines(&mut buffer, 1);
+        assert_eq!(buffer, b"line 100\n$ ");
+    }
+
+    #[test]
+    fn trimming_to_more_lines_than_there_are_is_a_no_op() {
+        let mut buffer: VecDeque<u8> = b"one\ntwo\nthree\n".iter().copied().collect();
+        trim_lines(&mut buffer, 10);
+        assert_eq!(buffer, b"one\ntwo\nthree\n");
+        trim_lines(&mut buffer, 3);
+        assert_eq!(buffer, b"one\ntwo\nthree\n");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn font_scan_survives_symlink_cycles() {
+        let dir = tempfile::tempdir().unwrap();
+        let (a, b) = (dir.path().join("a"), dir.path().join("b"));
+        std::fs::create_dir(&a).unwrap();
+        std::fs::create_dir(&b).unwrap();
+        std::os::unix::fs::symlink(&b, a.join("to-b")).unwrap();
+        std::os::unix::fs::symlink(&a, b.join("to-a")).unwrap();
+        std::fs::write(a.join("Hack-Regular.ttf"), b"").unwrap();
+        let mut found = Vec::new();
+        collect_fonts(&a, "hack", &mut found);
+        assert_eq!(found, [a.join("Hack-Regular.ttf")]);
+    }
+
+    #[test]
+    fn font_family_picks_one_file_per_variant() {
+        let dir = tempfile::tempdir().unwrap();
+        let names = [
+            "JetBrainsMono-Bold.ttf",
+            "JetBrainsMono-BoldItalic.ttf",
+            "JetBrainsMono-ExtraBold.ttf",
+            "JetBrainsMono-Italic.ttf",
+            "JetBrainsMono-Light.ttf",
+            "JetBrainsMono-Regular.ttf",
+            "JetBrainsMono-SemiBoldItalic.ttf",
+            "FiraCode-Regular.ttf",
+        ];
+        for name in names {
+            std::fs::write(dir.path().join(name), b"").unwrap();
+        }
+        let variants = font_variants(find_fonts(&[dir.path()], "JetBrains Mono"));
+        let names = variants.map(|p| p.and_then(|p| p.file_name()?.to_str().map(String::from)));
+        assert_eq!(
+            names,
+            [
+                Some("JetBrainsMono-Regular.ttf".to_string()),
+                Some("JetBrainsMono-Bold.ttf".to_string()),
+                Some("JetBrainsMono-Italic.ttf".to_string()),
+                Some("JetBrainsMono-BoldItalic.ttf".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn normalize_size_clamps_zero_dimensions() {
+        let size = normalize_size(0, 80, false);
+        assert_eq!((size.rows, size.cols), (1, 80));
+        let size = normalize_size(24, 0, false);
+        assert_eq!((size.rows, size.cols), (24, 1));
+        let size = normalize_size(30, 100, false);
+        assert_eq!((size.rows, size.cols), (30, 100));
+        // A spawn with no size at all gets the default instead
+        let size = normalize_size(0, 0, true);
+        assert_eq!((size.rows, size.cols), (DEFAULT_SIZE.rows, DEFAULT_SIZE.cols));
+        let size = normalize_size(0, 0, false);
+        assert_eq!((size.rows, size.cols), (1, 1));
+    }
+
+    /// A writer that keeps what it was given, for checking what a command
+    /// sends to the PTY.
+    #[derive(Clone, Default)]
+    struct Sink(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Sink {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn soft_reset_writes_decstr() {
+        let mut session = test_session(PtyOptions::default());
+        let sink = Sink::default();
+        session.writer = Box::new(sink.clone());
+        soft_reset(&mut session).unwrap();
+        assert_eq!(*sink.0.lock().unwrap(), b"\x1b[!p\x1b[0m\x1b(B\x0f\x1b[?25h");
+        assert_eq!(session.bytes_out.load(Ordering::Relaxed), SOFT_RESET.len() as u64);
+    }
+
+    #[test]
+    fn throttle_caps_events_under_a_flood() {
+        const MAX_PER_SEC: u32 = 50;
+        let (tx, rx) = mpsc::channel();
+        let producer = std::thread::spawn(move || {
+            let started = Instant::now();
+            let mut sent = 0;
+            while started.elapsed() < Duration::from_millis(1200) {
+                tx.send(vec![b'x'; 16]).unwrap();
+                sent += 16;
+            }
+            // Go quiet without closing, then close
+            std::thread::sleep(Duration::from_millis(2500));
+            drop(tx);
+            (sent, Instant::now())
+        });
+        let (mut events, mut bytes, mut throttled, mut cleared_at) = (0, 0, Vec::new(), None);
+        let started = Instant::now();
+        throttle(
+            rx,
+            MAX_PER_SEC,
+            |data| {
+                events += 1;
+                bytes += data.len();
+            },
+            |on| {
+                throttled.push(on);
+                cleared_at = (!on).then(Instant::now);
+            },
+        );
+        let elapsed = started.elapsed().as_secs_f64();
+
+        let (sent, closed_at) = producer.join().unwrap();
+        assert_eq!(bytes, sent);
+        // One event may go out immediately, the rest at most one per interval
+        assert!(events as f64 <= 1.0 + elapsed * MAX_PER_SEC as f64, "{} events in {}s", events, elapsed);
+        // Cleared once the flood ends, without waiting for more output
+        assert_eq!(throttled, [true, false]);
+        assert!(cleared_at.unwrap() < closed_at);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn closing_right_after_a_write_drains_its_output() {
+        let mut session = test_session(PtyOptions::default());
+        let output = start_reader(&session);
+        session.writer.write_all(b"last words\n").unwrap();
+        session.writer.flush().unwrap();
+        // The first step of `shut_down`; the kill after it has a grace
+        // period of its own, so check the drain on its own
+        drain_output(&session);
+        let drained = String::from_utf8_lossy(&output.lock().unwrap()).into_owned();
+        assert!(drained.contains("last words"), "{:?}", drained);
+        shut_down(session, "kill");
+    }
+
+    #[test]
+    fn missing_program_is_reported_before_spawning() {
+        let options = PtyOptions {
+            program: Some("nanoprompt-no-such-program".into()),
+            ..Default::default()
+        };
+        match check_program(&options) {
+            Err(PtyError::ProgramNotFound { program }) => assert_eq!(program, "nanoprompt-no-such-program"),
+            other => panic!("expected ProgramNotFound, got {:?}", other),
+        }
+        let options = PtyOptions {
+            program: Some("/bin/sh".into()),
+            ..Default::default()
+        };
+        assert!(check_program(&options).is_ok());
+    }
+
+    /// A shell that writes `marker` if it gets to exit cleanly: when its
+    /// input ends or on SIGTERM, but not on a kill.
+    #[cfg(unix)]
+    fn marker_session(marker: &std::path::Path) -> PtySession {
+        test_session(PtyOptions {
+            program: Some("/bin/sh".into()),
+            args: vec![
+                "-c".into(),
+                r#"trap 'echo clean > "$0"; exit' TERM; read line; echo clean > "$0""#.into(),
+                marker.to_string_lossy().into_owned(),
+            ],
+            ..Default::default()
+        })
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn shut_down_modes_let_the_child_exit_cleanly_or_not() {
+        let dir = tempfile::tempdir().unwrap();
+        for (mode, clean) in [("eof", true), ("term", true), ("kill", false)] {
+            let marker = dir.path().join(mode);
+            let session = marker_session(&marker);
+            let pid = session.child.process_id().unwrap();
+            // Let the shell set its trap before it's asked to leave
+            std::thread::sleep(Duration::from_millis(200));
+            shut_down(session, mode);
+            assert!(!process_exists(pid), "{} left the child running", mode);
+            assert_eq!(marker.exists(), clean, "{}", mode);
+        }
+    }
+
+    #[test]
+    fn inherit_env_keeps_only_the_allowlist() {
+        let options = PtyOptions {
+            program: Some("/bin/sh".into()),
+            inherit_env: Some(vec!["PATH".into(), "NANOPROMPT_UNSET_VAR".into()]),
+            env: HashMap::from([("EXTRA".into(), "1".into())]),
+            ..Default::default()
+        };
+        let cmd = build_command(&options, DEFAULT_SIZE);
+        let mut keys: Vec<_> = cmd.iter_full_env_as_str().map(|(key, _)| key).collect();
+        keys.sort();
+        assert_eq!(keys, ["COLORTERM", "COLUMNS", "EXTRA", "LINES", SESSION_MARKER, "PATH", "TERM"]);
+        assert_eq!(cmd.get_env("PATH"), std::env::var_os("PATH").as_deref());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn default_shell_prefers_a_usable_dollar_shell() {
+        let some = |s: &str| Some(s.to_string());
+        assert_eq!(pick_shell(some("/bin/sh"), some("/bin/nope")), "/bin/sh");
+        // Unusable $SHELL values fall through to the login shell
+        for shell in [None, some("/nonexistent/zsh"), some("sh"), some("/etc/passwd")] {
+            assert_eq!(pick_shell(shell, some("/bin/cat")), "/bin/cat");
+        }
+        assert_eq!(pick_shell(some("/nonexistent/zsh"), some("/nonexistent/fish")), "/bin/sh");
+        assert_eq!(pick_shell(None, None), "/bin/sh");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn max_sessions_is_enforced_until_one_closes() {
+        let state = test_state([
+            (1, test_session(PtyOptions::default())),
+            (2, test_session(PtyOptions::default())),
+        ]);
+        assert!(reserve_slot(&state, 0).is_ok());
+        assert!(reserve_slot(&state, 3).is_ok());
+        assert!(matches!(reserve_slot(&state, 2), Err(PtyError::LimitReached { limit: 2 })));
+
+        let closed = state.sessions.lock().unwrap().remove(&1).unwrap();
+        shut_down(closed, "kill");
+        assert!(reserve_slot(&state, 2).is_ok());
+        // Held sessions whose child is gone leave their slot free too
+        state.sessions.lock().unwrap()[&2].exited.store(true, Ordering::Relaxed);
+        assert!(reserve_slot(&state, 1).is_ok());
+        assert_eq!(state.reserved.load(Ordering::Relaxed), 0);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn sessions_still_spawning_count_against_max_sessions() {
+        let state = test_state([(1, test_session(PtyOptions::default()))]);
+        let spawning = reserve_slot(&state, 3).unwrap();
+        let _second = reserve_slot(&state, 3).unwrap();
+        assert!(matches!(reserve_slot(&state, 3), Err(PtyError::LimitReached { limit: 3 })));
+        // A failed spawn drops its reservation and frees the slot
+        drop(spawning);
+        assert!(reserve_slot(&state, 3).is_ok());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn recording_all_starts_and_stops_every_session() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = test_state([
+            (1, test_session(PtyOptions::default())),
+            (2, test_session(PtyOptions::default())),
+            (3, test_session(PtyOptions::default())),
+        ]);
+        // A recording started on its own is left alone by both
+        let own = dir.path().join("own.cast");
+        begin_recording(&state.sessions.lock().unwrap()[&3], &own, false).unwrap();
+
+        let mut batch = record_all(&state, dir.path()).unwrap();
+        batch.files.sort();
+        let cast = |id: u32| dir.path().join(format!("{}.cast", id));
+        assert_eq!(batch.files, [cast(1).to_string_lossy(), cast(2).to_string_lossy()]);
+        assert!(batch.errors.is_empty());
+        for session in state.sessions.lock().unwrap().values() {
+            let mut recording = session.recording.lock().unwrap();
+            recording.as_mut().unwrap().output(b"hi").unwrap();
+        }
+
+        let mut stopped = stop_batch(&state).unwrap();
+        stopped.sort();
+        assert_eq!(stopped, batch.files);
+        for id in [1, 2] {
+            let text = std::fs::read_to_string(cast(id)).unwrap();
+            let lines: Vec<_> = text.lines().collect();
+            assert_eq!(lines.len(), 2);
+            assert!(lines[1].ends_with(r#""o","hi"]"#));
+        }
+        let sessions = state.sessions.lock().unwrap();
+        assert!(sessions[&1].recording.lock().unwrap().is_none());
+        assert!(sessions[&3].recording.lock().unwrap().is_some());
+    }
+
+    #[test]
+    fn tmux_sessions_attach_or_create() {
+        let argv = |name: Option<&str>| {
+            let cmd = build_command(&tmux_options(name.map(String::from)).unwrap(), DEFAULT_SIZE);
+            assert_eq!(cmd.get_env("TMUX").unwrap(), "");
+            cmd.get_argv().iter().map(|a| a.to_string_lossy().into_owned()).collect::<Vec<_>>()
+        };
+        assert_eq!(argv(None), ["tmux", "new-session", "-A", "-s", DEFAULT_TMUX_SESSION]);
+        assert_eq!(argv(Some("work")), ["tmux", "new-session", "-A", "-s", "work"]);
+        for name in ["", "a:b", "a.b"] {
+            assert!(tmux_options(Some(name.into())).is_err());
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn metadata_round_trips_and_is_listed() {
+        let state = test_state([(1, test_session(PtyOptions::default()))]);
+        assert_eq!(get_metadata(&state, 1).unwrap(), None);
+        let json = r##"{"color":"#ff8800","tags":["build"]}"##;
+        set_metadata(&state, 1, json.into()).unwrap();
+        assert_eq!(get_metadata(&state, 1).unwrap().as_deref(), Some(json));
+        assert_eq!(list_sessions(&state).unwrap()[0].metadata.as_deref(), Some(json));
+
+        assert!(set_metadata(&state, 1, "{".into()).is_err());
+        assert!(set_metadata(&state, 2, "{}".into()).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn metadata_is_capped() {
+        let state = test_state([(1, test_session(PtyOptions::default()))]);
+        let blob = |len: usize| format!("\"{}\"", "x".repeat(len - 2));
+        set_metadata(&state, 1, blob(MAX_METADATA_BYTES)).unwrap();
+        assert!(set_metadata(&state, 1, blob(MAX_METADATA_BYTES + 1)).is_err());
+        // The rejected blob leaves the last one in place
+        assert_eq!(get_metadata(&state, 1).unwrap().map(|m| m.len()), Some(MAX_METADATA_BYTES));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn input_files_reach_the_child() {
+        let dir = tempfile::tempdir().unwrap();
+        let (script, received) = (dir.path().join("script.sh"), dir.path().join("received"));
+        std::fs::write(&script, "echo one\necho two\n").unwrap();
+        let mut session = test_session(PtyOptions {
+            program: Some("/bin/sh".into()),
+            args: vec!["-c".into(), r#"cat > "$0""#.into(), received.to_string_lossy().into_owned()],
+            ..Default::default()
+        });
+        session.writer.write_all(&read_input_file(&script).unwrap()).unwrap();
+        session.writer.flush().unwrap();
+        shut_down(session, "eof");
+        // Closing the writer sends a newline of its own before the EOF
+        let received = std::fs::read_to_string(&received).unwrap();
+        assert!(received.starts_with("echo one\necho two\n"), "{:?}", received);
+    }
+
+    #[test]
+    fn input_files_must_exist_and_fit() {
+        let dir = tempfile::tempdir().unwrap();
+        let err = read_input_file(&dir.path().join("missing")).unwrap_err();
+        assert!(err.contains("missing"), "{}", err);
+        let big = dir.path().join("big");
+        std::fs::File::create(&big).unwrap().set_len(MAX_INPUT_FILE_SIZE + 1).unwrap();
+        assert!(read_input_file(&big).unwrap_err().contains("limit"));
+    }
+
+    /// The directory `pwd` prints when `options` is run with its program
+    /// swapped for a shell.
+    #[cfg(unix)]
+    fn pwd_for(options: PtyOptions) -> String {
+        let options = PtyOptions {
+            program: Some("/bin/sh".into()),
+            args: vec!["-c".into(), "pwd".into()],
+            ..options
+        };
+        pty_output(&options).trim().to_string()
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn duplicates_inherit_the_sessions_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir_path = dir.path().canonicalize().unwrap().to_string_lossy().into_owned();
+        let source = test_session(PtyOptions {
+            cwd: Some(dir_path.clone()),
+            env: HashMap::from([("FOO".into(), "bar".into())]),
+            ..Default::default()
+        });
+        let options = duplicate_options(&source);
+        assert_eq!(options.env["FOO"], "bar");
+        assert_eq!(pwd_for(options), dir_path);
+
+        // A directory the shell reported wins over the process's
+        let reported = std::env::temp_dir().canonicalize().unwrap().to_string_lossy().into_owned();
+        *source.modes.cwd.lock().unwrap() = Some(reported.clone());
+        assert_eq!(duplicate_options(&source).cwd, Some(reported));
+    }
+
+    #[test]
+    fn missing_default_cwd_falls_back_to_home() {
+        let dir = tempfile::tempdir().unwrap();
+        let existing = dir.path().to_string_lossy().into_owned();
+        assert_eq!(existing_cwd(Some(existing.clone())), Some(existing));
+        assert_eq!(existing_cwd(Some("~".into())), Some("~".into()));
+        let gone = dir.path().join("gone").to_string_lossy().into_owned();
+        assert_eq!(existing_cwd(Some(gone)), None);
+        assert_eq!(existing_cwd(None), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn group_resize_updates_every_pane() {
+        let pane = |group_id| {
+            test_session(PtyOptions {
+                group_id,
+                ..Default::default()
+            })
+        };
+        let state = test_state([(1, pane(Some(7))), (2, pane(Some(7))), (3, pane(None))]);
+        let sizes = HashMap::from([(1, (30, 100)), (2, (30, 60)), (3, (30, 60)), (4, (1, 1))]);
+        let errors = resize_members(&state, 7, sizes).unwrap();
+        let mut failed: Vec<_> = errors.keys().copied().collect();
+        failed.sort();
+        assert_eq!(failed, [3, 4]);
+
+        let sessions = state.sessions.lock().unwrap();
+        let size = |id| {
+            let size = sessions[&id].master.get_size().unwrap();
+            (size.rows, size.cols)
+        };
+        assert_eq!(size(1), (30, 100));
+        assert_eq!(size(2), (30, 60));
+        assert_eq!(size(3), (DEFAULT_SIZE.rows, DEFAULT_SIZE.cols));
+    }
+
+    #[test]
+    fn namespaced_sessions_emit_their_own_events() {
+        let names = |ns: Option<&str>| {
+            ["pty-output", "pty-exit"].map(|event| session_event(ns, event).into_owned())
+        };
+        assert_eq!(names(Some("left")), ["left:pty-output", "left:pty-exit"]);
+        assert_eq!(names(Some("right/2")), ["right/2:pty-output", "right/2:pty-exit"]);
+        assert_eq!(names(None), ["pty-output", "pty-exit"]);
+        assert!(check_namespace("right/2").is_ok());
+        for ns in ["", "a:b", "a b"] {
+            assert!(check_namespace(ns).is_err(), "{:?}", ns);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn write_errors_from_a_gone_child_mark_it_exited() {
+        use std::io::{Error, ErrorKind};
+        // Linux buffers writes to a PTY nobody holds open rather than
+        // failing them, so the errors are made up here
+        let session = test_session(PtyOptions::default());
+        assert!(!mark_gone(&session, &Error::from(ErrorKind::WouldBlock)));
+        assert!(!session.exited.load(Ordering::Relaxed));
+        assert!(mark_gone(&session, &Error::from_raw_os_error(libc::EIO)));
+        assert!(session.exited.load(Ordering::Relaxed));
+        // Only the first failure reports the exit
+        assert!(!mark_gone(&session, &Error::from(ErrorKind::BrokenPipe)));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn new_tabs_open_where_the_source_shell_is() {
+        let dir = tempfile::tempdir().unwrap();
+        let (start, moved) = (dir.path().canonicalize().unwrap(), dir.path().join("moved"));
+        std::fs::create_dir(&moved).unwrap();
+        let moved = moved.canonicalize().unwrap().to_string_lossy().into_owned();
+        let mut source = test_session(PtyOptions {
+            program: Some("/bin/sh".into()),
+            cwd: Some(start.to_string_lossy().into_owned()),
+            ..Default::default()
+        });
+        // No OSC 7 from a plain sh, so this relies on the process's cwd
+        writeln!(source.writer, "cd '{}'", moved).unwrap();
+        source.writer.flush().unwrap();
+        let pid = source.child.process_id().unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while procinfo::cwd(pid).as_deref() != Some(moved.as_str()) && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        let state = test_state([(1, source)]);
+
+        let cwd = session_cwd(&state, 1).unwrap();
+        assert_eq!(cwd.as_deref(), Some(moved.as_str()));
+        assert_eq!(pwd_for(PtyOptions { cwd, ..Default::default() }), moved);
+        assert!(session_cwd(&state, 2).is_err());
+    }
+
+    /// Wait up to 5s for `output` to contain `text`.
+    #[cfg(unix)]
+    fn wait_for_output(output: &Mutex<Vec<u8>>, text: &str) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !String::from_utf8_lossy(&output.lock().unwrap()).contains(text) {
+            assert!(Instant::now() < deadline, "no {:?} in the output", text);
+            std::thread::sleep(Duration::from_millis(20));
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn interrupt_signals_the_foreground_group_without_isig() {
+        let mut session = test_session(PtyOptions {
+            program: Some("/bin/sh".into()),
+            args: vec!["-c".into(), "stty -isig; echo ready; (sleep 30)".into()],
+            ..Default::default()
+        });
+        let output = start_reader(&session);
+        wait_for_output(&output, "ready");
+        // With ISIG off, Ctrl-C is just a byte
+        session.writer.write_all(b"\x03").unwrap();
+        session.writer.flush().unwrap();
+        std::thread::sleep(Duration::from_millis(200));
+        assert!(session.child.try_wait().unwrap().is_none());
+
+        interrupt_foreground(&session).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while session.child.try_wait().unwrap().is_none() {
+            assert!(Instant::now() < deadline, "still running after SIGINT");
+            std::thread::sleep(Duration::from_millis(20));
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn input_is_counted_in_bytes_out() {
+        let mut session = test_session(PtyOptions::default());
+        session.send(b"hello\n").unwrap();
+        session.send(&[b'x'; 1000]).unwrap();
+        assert_eq!(session.bytes_out.load(Ordering::Relaxed), 1006);
+        assert!(session.last_activity().is_some());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn locked_sessions_refuse_input_until_unlocked() {
+        let mut session = test_session(PtyOptions::default());
+        let output = start_reader(&session);
+        session.input_enabled = false;
+        assert_eq!(session.accepts_input(), Err("input-locked".into()));
+        session.drop_locked_input = true;
+        assert_eq!(session.accepts_input(), Ok(false));
+
+        session.input_enabled = true;
+        assert_eq!(session.accepts_input(), Ok(true));
+        session.send(b"unlocked\n").unwrap();
+        wait_for_output(&output, "unlocked");
+    }
+
+    /// A bare TrueType file with just the `name` (family) and `post`
+    /// (`isFixedPitch`) tables `fontinfo` reads.
+    fn tiny_font(family: &str, fixed_pitch: bool) -> Vec<u8> {
+        let name: Vec<u8> = family.encode_utf16().flat_map(u16::to_be_bytes).collect();
+        let (name_at, post_at) = (12 + 2 * 16, 12 + 2 * 16 + 18 + name.len());
+        let mut font = vec![0, 1, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0];
+        for (tag, at) in [(b"name", name_at), (b"post", post_at)] {
+            font.extend_from_slice(tag);
+            font.extend_from_slice(&[0; 4]);
+            font.extend_from_slice(&(at as u32).to_be_bytes());
+            font.extend_from_slice(&[0; 4]);
+        }
+        // One Windows/Unicode record for name id 1, strings right after it
+        for field in [0, 1, 18, 3, 1, 0x409, 1, name.len() as u16, 0] {
+            font.extend_from_slice(&u16::to_be_bytes(field));
+        }
+        font.extend_from_slice(&name);
+        font.extend_from_slice(&[0; 12]);
+        font.extend_from_slice(&u32::to_be_bytes(fixed_pitch.into()));
+        font
+    }
+
+    #[test]
+    fn only_fixed_pitch_fonts_are_monospace() {
+        let dir = tempfile::tempdir().unwrap();
+        let files: Vec<_> = [
+            ("Serif.ttf", tiny_font("Some Serif", false)),
+            ("Mono.ttf", tiny_font("Some Mono", true)),
+            // Unreadable files are judged by name
+            ("Fira_Code-Bold.ttf", b"not a font".to_vec()),
+            ("Lobster.ttf", b"not a font".to_vec()),
+        ]
+        .into_iter()
+        .map(|(file, data)| {
+            let path = dir.path().join(file);
+            std::fs::write(&path, data).unwrap();
+            path
+        })
+        .collect();
+        assert_eq!(monospace_families(files), ["Fira Code", "Some Mono"]);
+        let serif = fontinfo::faces(&tiny_font("Some Serif", false)).unwrap();
+        assert_eq!((serif[0].family.as_str(), serif[0].fixed_pitch), ("Some Serif", false));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn window_resize_covers_its_running_sessions() {
+        let state = test_state([
+            (1, test_session(in_window("main"))),
+            (2, test_session(in_window("main"))),
+            (3, test_session(in_window("main"))),
+            (4, test_session(in_window("window-2"))),
+        ]);
+        state.sessions.lock().unwrap()[&3].exited.store(true, Ordering::Relaxed);
+        assert!(resize_window(&state, "main", 40, 120).unwrap().is_empty());
+
+        let sessions = state.sessions.lock().unwrap();
+        let cols = |id| sessions[&id].master.get_size().unwrap().cols;
+        assert_eq!((cols(1), cols(2)), (120, 120));
+        assert_eq!((cols(3), cols(4)), (DEFAULT_SIZE.cols, DEFAULT_SIZE.cols));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn run_command_reports_output_and_exit_code() {
+        let script = |s: &str| vec!["-c".to_string(), s.to_string()];
+        let output = run_command("/bin/sh".into(), script("echo out; echo err >&2; exit 3"), None, 5000, None);
+        let output = output.unwrap();
+        assert_eq!((output.stdout.as_str(), output.stderr.as_str()), ("out\n", "err\n"));
+        assert_eq!(output.code, Some(3));
+
+        let output = run_command("/bin/sh".into(), script("echo out; exit 4"), None, 5000, Some(true)).unwrap();
+        assert_eq!((output.stdout.trim(), output.code), ("out", Some(4)));
+
+        let err = run_command("/bin/sh".into(), script("sleep 5"), None, 100, None).err().unwrap();
+        assert!(err.contains("timed out"), "{}", err);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn idle_sessions_are_closed_after_the_timeout() {
+        let session = test_session(PtyOptions::default());
+        let (last_activity, exited) = (session.last_activity.clone(), session.exited.clone());
+        let pid = session.child.process_id().unwrap();
+        let state = test_state([(1, session)]);
+        let timeout = Duration::from_millis(300);
+
+        // Activity keeps pushing the close back
+        let busy_until = Instant::now() + Duration::from_millis(800);
+        let activity = last_activity.clone();
+        std::thread::spawn(move || {
+            while Instant::now() < busy_until {
+                activity.store(epoch_millis(), Ordering::Relaxed);
+                std::thread::sleep(Duration::from_millis(50));
+            }
+        });
+        let session = wait_until_idle(&state, 1, timeout, &last_activity, &exited).unwrap();
+        assert!(Instant::now() >= busy_until);
+        assert!(state.sessions.lock().unwrap().is_empty());
+        assert!(exited.load(Ordering::Relaxed));
+        end_child(session, "eof");
+        assert!(!process_exists(pid));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn quiet_bash_skips_its_rc_files() {
+        if !program_exists("bash", None) {
+            return;
+        }
+        let home = tempfile::tempdir().unwrap();
+        std::fs::write(home.path().join(".bashrc"), "echo RC-BANNER\n").unwrap();
+        let startup_output = |quiet| {
+            let mut session = test_session(PtyOptions {
+                program: Some("bash".into()),
+                args: vec!["-i".into()],
+                env: HashMap::from([("HOME".into(), home.path().to_string_lossy().into_owned())]),
+                quiet,
+                ..Default::default()
+            });
+            let output = start_reader(&session);
+            // Quoted so the echo of the typed line doesn't match
+            session.send(b"echo RE''ADY\n").unwrap();
+            wait_for_output(&output, "READY");
+            shut_down(session, "kill");
+            String::from_utf8_lossy(&output.lock().unwrap()).into_owned()
+        };
+        assert!(startup_output(false).contains("RC-BANNER"));
+        assert!(!startup_output(true).contains("RC-BANNER"));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn process_tree_includes_background_jobs() {
+        let mut session = test_session(PtyOptions {
+            program: Some("/bin/sh".into()),
+            args: vec!["-c".into(), "sleep 30 & echo started; wait".into()],
+            ..Default::default()
+        });
+        let output = start_reader(&session);
+        wait_for_output(&output, "started");
+        let shell = session.child.process_id().unwrap();
+        let tree = process_tree(shell);
+        assert_eq!(tree[0].pid, shell);
+        let sleep = tree.iter().find(|p| p.command.as_deref() == Some("sleep"));
+        assert_eq!(sleep.map(|p| p.ppid), Some(shell));
+        // SAFETY: plain syscall; the shell hasn't reaped the sleep yet
+        unsafe { libc::kill(sleep.unwrap().pid as libc::pid_t, libc::SIGKILL) };
+        session.child.kill().unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn global_env_sits_beneath_the_sessions_own() {
+        let mut options = PtyOptions {
+            program: Some("/bin/sh".into()),
+            args: vec!["-c".into(), "echo \"[$EDITOR] [$PAGER]\"".into()],
+            env: HashMap::from([("PAGER".into(), "less".into())]),
+            ..Default::default()
+        };
+        let global = [("EDITOR", "vim"), ("PAGER", "more")];
+        add_global_env(&mut options, global.map(|(k, v)| (k.into(), v.into())).into());
+        assert!(pty_output(&options).contains("[vim] [less]"));
+    }
+
+    #[test]
+    fn missing_fonts_can_fall_back_to_the_bundled_one() {
+        let family = "Nanoprompt No Such Family";
+        assert_eq!(load_font(family.into(), None).unwrap(), None);
+        let font = load_font(family.into(), Some(true)).unwrap().unwrap();
+        assert_eq!(font, fallback_font());
+        let data = font.strip_prefix("data:font/woff2;base64,").unwrap();
+        assert_eq!(base64_decode(data).unwrap(), FALLBACK_FONT);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn diagnostics_cover_every_session_without_secrets() {
+        let state = test_state([
+            (2, test_session(PtyOptions::default())),
+            (1, test_session(PtyOptions::default())),
+        ]);
+        let mut config = config::Config::default();
+        config.global_env.insert("API_TOKEN".into(), "hunter2".into());
+        let report = diagnostics_report(&state, config).unwrap();
+
+        let ids: Vec<u32> = report.sessions.iter().map(|s| s.id).collect();
+        assert_eq!(ids, [1, 2]);
+        assert!(report.sessions.iter().all(|s| s.pid.is_some() && !s.exited));
+        let json = serde_json::to_string(&report).unwrap();
+        assert!(json.contains("API_TOKEN") && !json.contains("hunter2"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn columns_and_lines_match_the_spawn_size() {
+        let mut options = PtyOptions {
+            program: Some("/bin/sh".into()),
+            args: vec!["-c".into(), "echo \"[$COLUMNS] [$LINES]\"; stty size".into()],
+            ..Default::default()
+        };
+        let size = PtyDimensions { rows: 40, cols: 132, ..DEFAULT_SIZE };
+        let output = sized_pty_output(&options, size);
+        assert!(output.contains("[132] [40]"), "{:?}", output);
+        assert!(output.contains("40 132"), "{:?}", output);
+        // The session's own env still wins
+        options.env.insert("COLUMNS".into(), "100".into());
+        assert!(sized_pty_output(&options, size).contains("[100] [40]"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn mirrors_get_output_until_their_window_closes() {
+        let state = test_state([(1, test_session(PtyOptions::default()))]);
+        {
+            let sessions = state.sessions.lock().unwrap();
+            for label in ["viewer", "viewer", "projector"] {
+                add_mirror(&sessions[&1], label.into()).unwrap();
+            }
+        }
+        let mirrors = state.sessions.lock().unwrap()[&1].mirrors.clone();
+        let sent = |data: &[u8]| {
+            let mut sent = Vec::new();
+            send_to_mirrors(&mirrors, 1, data, |label, payload| {
+                let data = base64_decode(payload["data"].as_str().unwrap()).unwrap();
+                sent.push((label.to_string(), payload["id"].clone(), data));
+            });
+            sent
+        };
+        assert_eq!(sent(b"hi"), [
+            ("viewer".into(), 1.into(), b"hi".to_vec()),
+            ("projector".into(), 1.into(), b"hi".to_vec()),
+        ]);
+
+        forget_mirror_target(&state, "viewer");
+        let labels: Vec<String> = sent(b"more").into_iter().map(|(label, ..)| label).collect();
+        assert_eq!(labels, ["projector"]);
+    }
+
+    #[test]
+    fn git_status_reports_uncommitted_changes() {
+        if !program_exists("git", None) {
+            return;
+        }
+        let dir = tempfile::tempdir().unwrap();
+        let cwd = dir.path().to_str().unwrap();
+        assert!(read_git_status(cwd).is_none());
+        let git = |args: &[&str]| {
+            let status = std::process::Command::new("git")
+                .args(["-c", "user.name=test", "-c", "user.email=test@example.com"])
+                .args(args)
+                .current_dir(cwd)
+                .output()
+                .unwrap()
+                .status;
+            assert!(status.success(), "git {:?}", args);
+        };
+        git(&["init", "-q"]);
+        git(&["symbolic-ref", "HEAD", "refs/heads/work"]);
+        git(&["commit", "-q", "--allow-empty", "-m", "first"]);
+        let info = read_git_status(cwd).unwrap();
+        assert_eq!((info.branch.as_deref(), info.dirty), (Some("work"), false));
+
+        std::fs::write(dir.path().join("new.txt"), "change").unwrap();
+        assert!(read_git_status(cwd).unwrap().dirty);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn output_filters_receive_the_output() {
+        let dir = tempfile::tempdir().unwrap();
+        let received = dir.path().join("received");
+        let argv = ["/bin/sh", "-c", r#"cat > "$0""#, received.to_str().unwrap()].map(String::from);
+        let filter = spawn_output_filter(&argv).unwrap();
+        let stdin = filter.stdin.clone();
+        for chunk in [&b"\x1b[1mbold\x1b[0m "[..], b"and plain\r\n"] {
+            assert!(write_tap(stdin.lock().unwrap().as_mut().unwrap().as_mut(), chunk));
+        }
+        // Closing the session closes the filter's input and lets it finish
+        drop(filter);
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while std::fs::read(&received).unwrap_or_default() != b"\x1b[1mbold\x1b[0m and plain\r\n" {
+            assert!(Instant::now() < deadline, "filter never wrote the output");
+            std::thread::sleep(Duration::from_millis(20));
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn a_dead_output_filter_is_noticed() {
+        let mut filter = spawn_output_filter(&["true".to_string()]).unwrap();
+        filter.child.as_mut().unwrap().wait().unwrap();
+        let mut stdin = filter.stdin.lock().unwrap();
+        assert!(!write_tap(stdin.as_mut().unwrap().as_mut(), b"anyone?"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn redraw_requests_reach_the_child_only_when_sent() {
+        let mut session = test_session(PtyOptions {
+            program: Some("/bin/sh".into()),
+            args: vec!["-c".into(), "stty raw -echo; echo ready; head -c 1 | od -An -tx1".into()],
+            ..Default::default()
+        });
+        let output = start_reader(&session);
+        wait_for_output(&output, "ready");
+        resize_session(&session, 30, 100).unwrap();
+        assert_eq!(session.bytes_out.load(Ordering::Relaxed), 0);
+
+        send_redraw(&mut session).unwrap();
+        wait_for_output(&output, "0c");
+        assert_eq!(session.bytes_out.load(Ordering::Relaxed), 1);
+    }
+
+    /// A directory with a subdirectory in it, which `ls --color` colors.
+    #[cfg(target_os = "linux")]
+    fn dir_to_list() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("subdir")).unwrap();
+        dir
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn run_in_pty_captures_colored_output() {
+        let dir = dir_to_list();
+        let cwd = Some(dir.path().to_string_lossy().into_owned());
+        let args = vec!["--color=auto".to_string()];
+        let run = run_in_pty("ls".into(), args, cwd, None, 5000).unwrap();
+        assert_eq!((run.code, run.timed_out), (Some(0), false));
+        let output = String::from_utf8(base64_decode(&run.output_base64).unwrap()).unwrap();
+        assert!(output.contains("subdir") && output.contains("\x1b["), "{:?}", output);
+
+        let run = run_in_pty("sleep".into(), vec!["5".into()], None, None, 100).unwrap();
+        assert_eq!((run.code, run.timed_out), (None, true));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn pixel_sizes_are_reported_back() {
+        let session = test_session(PtyOptions::default());
+        let dims = |size: PtyDimensions| (size.rows, size.cols, size.pixel_width, size.pixel_height);
+        resize_session_pixels(&session, 30, 100, (1000, 600)).unwrap();
+        assert_eq!(dims(session_size(&session).unwrap()), (30, 100, 1000, 600));
+        // Resizing without them keeps the 10x20 cell
+        resize_session(&session, 15, 50).unwrap();
+        assert_eq!(dims(session_size(&session).unwrap()), (15, 50, 500, 300));
+    }
+
+    #[test]
+    fn default_monospace_takes_the_first_installed_family() {
+        let dir = tempfile::tempdir().unwrap();
+        let families = ["DejaVu Sans Mono", "Liberation Mono", "Noto Sans Mono"];
+        assert_eq!(first_font(&[dir.path()], &families).unwrap(), fallback_font());
+
+        std::fs::write(dir.path().join("NotoSansMono-Regular.ttf"), b"noto").unwrap();
+        std::fs::write(dir.path().join("LiberationMono-Regular.ttf"), b"liberation").unwrap();
+        let url = first_font(&[dir.path()], &families).unwrap();
+        assert_eq!(url, format!("data:font/truetype;base64,{}", base64_encode(b"liberation")));
+        assert!(!load_default_monospace().unwrap().is_empty());
+    }
+
+    #[test]
+    fn write_progress_adds_up_to_the_file_size() {
+        let payload = vec![b'x'; PASTE_CHUNK_SIZE * 3 + 10];
+        let (mut received, mut events) = (Vec::new(), Vec::new());
+        let written = stream_chunks(
+            7,
+            &payload,
+            |chunk| {
+                received.extend_from_slice(chunk);
+                Ok(true)
+            },
+            |progress| events.push(progress),
+        )
+        .unwrap();
+        assert_eq!((written, received.len()), (payload.len(), payload.len()));
+        assert_eq!(events.len(), 4);
+        let last = events.last().unwrap();
+        assert_eq!((last["id"].as_u64(), last["written"].as_u64()), (Some(7), Some(payload.len() as u64)));
+        assert!(events.iter().all(|e| e["total"] == payload.len()));
+    }
+
+    #[test]
+    fn write_progress_stops_on_cancel_and_reports_errors() {
+        let payload = vec![b'x'; PASTE_CHUNK_SIZE * 3];
+        let mut chunks = 0;
+        let mut events = Vec::new();
+        let cancel_second = |_: &[u8]| {
+            chunks += 1;
+            Ok(chunks < 2)
+        };
+        let cancelled = stream_chunks(1, &payload, cancel_second, |e| events.push(e));
+        assert_eq!(cancelled.unwrap(), PASTE_CHUNK_SIZE);
+        assert_eq!(events.len(), 1);
+
+        let mut events = Vec::new();
+        let failed = stream_chunks(1, &payload, |_| Err("Input/output error".into()), |e| events.push(e));
+        assert!(failed.is_err());
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0]["written"], 0);
+        assert_eq!(events[0]["error"], "Input/output error");
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn force_pty_makes_ls_color_its_output() {
+        let dir = dir_to_list();
+        let ls = |force_pty| {
+            let cwd = Some(dir.path().to_string_lossy().into_owned());
+            run_command("ls".into(), vec!["--color=auto".into()], cwd, 5000, Some(force_pty)).unwrap().stdout
+        };
+        let (piped, in_pty) = (ls(false), ls(true));
+        assert!(piped.contains("subdir") && !piped.contains('\x1b'), "{:?}", piped);
+        assert!(in_pty.contains("subdir") && in_pty.contains("\x1b["), "{:?}", in_pty);
+    }
+}
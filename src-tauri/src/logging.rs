@@ -0,0 +1,54 @@
+//! File logger for the `logging` feature. Without the feature the `log`
+//! macros used around the crate go nowhere.
+
+use std::io::Write;
+use std::sync::Mutex;
+use tauri::{AppHandle, Manager};
+
+struct FileLogger {
+    file: Mutex<std::fs::File>,
+}
+
+impl log::Log for FileLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.target().starts_with("nanoprompt")
+    }
+
+    fn log(&self, record: &log::Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let millis = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_millis())
+            .unwrap_or(0);
+        if let Ok(mut file) = self.file.lock() {
+            let _ = writeln!(file, "{} {:<5} {}", millis, record.level(), record.args());
+        }
+    }
+
+    fn flush(&self) {
+        if let Ok(mut file) = self.file.lock() {
+            let _ = file.flush();
+        }
+    }
+}
+
+/// Append to `nanoprompt.log` in the app log dir. `NANOPROMPT_LOG=debug`
+/// (or `trace`) raises the level from the default `info`.
+pub fn init(app: &AppHandle) -> Result<(), String> {
+    let dir = app.path().app_log_dir().map_err(|e| e.to_string())?;
+    std::fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+    let file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(dir.join("nanoprompt.log"))
+        .map_err(|e| e.to_string())?;
+    let level = std::env::var("NANOPROMPT_LOG")
+        .ok()
+        .and_then(|level| level.parse().ok())
+        .unwrap_or(log::LevelFilter::Info);
+    log::set_boxed_logger(Box::new(FileLogger { file: Mutex::new(file) })).map_err(|e| e.to_string())?;
+    log::set_max_level(level);
+    Ok(())
+}
@@ -0,0 +1,342 @@
+//! Incremental scanner for the escape sequences the backend tracks. Output
+//! still goes to the frontend untouched; this only watches the stream, and
+//! keeps its state between reads so sequences split across chunks are seen.
+
+use std::ops::Range;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+/// Longest sequence body we buffer. Anything longer is dropped unparsed.
+const MAX_SEQUENCE_LEN: usize = 4096;
+/// Depth of the kitty keyboard flags stack; a push beyond it drops the oldest
+const MAX_KEYBOARD_STACK: usize = 8;
+
+#[derive(Clone, Copy, Default, PartialEq)]
+enum State {
+    #[default]
+    Ground,
+    Escape,
+    Csi,
+    Osc,
+    OscEscape,
+}
+
+/// A complete control sequence.
+pub enum Sequence<'a> {
+    /// `ESC [` ... final byte. `private` is a leading `<`, `=`, `>` or `?`.
+    Csi {
+        private: Option<u8>,
+        params: &'a [u8],
+        intermediates: &'a [u8],
+        action: u8,
+    },
+    /// `ESC ]` payload, terminated by BEL or ST
+    Osc(&'a [u8]),
+}
+
+#[derive(Default)]
+pub struct Parser {
+    state: State,
+    buf: Vec<u8>,
+    overflow: bool,
+    /// Bytes fed so far
+    offset: u64,
+    /// Offset of the current sequence's ESC
+    start: u64,
+}
+
+impl Parser {
+    /// Scan `data`, reporting each complete sequence with its span in the
+    /// whole stream fed so far.
+    pub fn feed(&mut self, data: &[u8], mut on_sequence: impl FnMut(Sequence, Range<u64>)) {
+        for &b in data {
+            self.offset += 1;
+            let span = self.start..self.offset;
+            match self.state {
+                State::Ground => {
+                    if b == 0x1b {
+                        self.state = State::Escape;
+                    }
+                }
+                State::Escape => self.escape(b),
+                State::Csi => match b {
+                    0x20..=0x3f => self.push(b),
+                    0x40..=0x7e => {
+                        if !self.overflow {
+                            on_sequence(csi(&self.buf, b), span);
+                        }
+                        self.state = State::Ground;
+                    }
+                    0x1b => self.state = State::Escape,
+                    // CAN and SUB abort the sequence
+                    0x18 | 0x1a => self.state = State::Ground,
+                    // Other C0 controls execute mid-sequence without ending it
+                    _ => {}
+                },
+                State::Osc => match b {
+                    0x07 => {
+                        if !self.overflow {
+                            on_sequence(Sequence::Osc(&self.buf), span);
+                        }
+                        self.state = State::Ground;
+                    }
+                    0x1b => self.state = State::OscEscape,
+                    0x18 | 0x1a => self.state = State::Ground,
+                    _ => self.push(b),
+                },
+                State::OscEscape => {
+                    if b == b'\\' {
+                        if !self.overflow {
+                            on_sequence(Sequence::Osc(&self.buf), span);
+                        }
+                        self.state = State::Ground;
+                    } else {
+                        // A new sequence started before the OSC was terminated
+                        self.escape(b);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Bytes fed so far.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// `b` is the byte after an ESC.
+    fn escape(&mut self, b: u8) {
+        self.start = self.offset - 2;
+        self.state = match b {
+            b'[' => State::Csi,
+            b']' => State::Osc,
+            0x1b => State::Escape,
+            _ => State::Ground,
+        };
+        self.buf.clear();
+        self.overflow = false;
+    }
+
+    fn push(&mut self, b: u8) {
+        if self.buf.len() < MAX_SEQUENCE_LEN {
+            self.buf.push(b);
+        } else {
+            self.overflow = true;
+        }
+    }
+}
+
+fn csi(body: &[u8], action: u8) -> Sequence<'_> {
+    let (private, rest) = match body.first() {
+        Some(&p @ b'<'..=b'?') => (Some(p), &body[1..]),
+        _ => (None, body),
+    };
+    let split = rest
+        .iter()
+        .position(|b| (0x20..=0x2f).contains(b))
+        .unwrap_or(rest.len());
+    Sequence::Csi {
+        private,
+        params: &rest[..split],
+        intermediates: &rest[split..],
+        action,
+    }
+}
+
+/// Numeric parameters of a CSI sequence; empty or malformed ones are `None`.
+pub fn params(params: &[u8]) -> impl Iterator<Item = Option<u32>> + '_ {
+    params
+        .split(|&b| b == b';')
+        .map(|p| std::str::from_utf8(p).ok()?.parse().ok())
+}
+
+/// Local path from an OSC 7 payload (`7;file://host/path`), percent-decoded.
+/// The host is ignored; we only ever spawn local shells.
+fn osc7_path(payload: &[u8]) -> Option<String> {
+    let url = payload.strip_prefix(b"7;")?.strip_prefix(b"file://")?;
+    let path = &url[url.iter().position(|&b| b == b'/')?..];
+    let mut out = Vec::with_capacity(path.len());
+    let mut i = 0;
+    while i < path.len() {
+        let hex = path.get(i + 1..i + 3).and_then(|h| std::str::from_utf8(h).ok());
+        match (path[i], hex.and_then(|h| u8::from_str_radix(h, 16).ok())) {
+            (b'%', Some(byte)) => {
+                out.push(byte);
+                i += 3;
+            }
+            (b, _) => {
+                out.push(b);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8(out).ok()
+}
+
+/// A tracked mode that changed value.
+pub enum ModeChange {
+    AltScreen(bool),
+    /// DECCKM (`?1`): arrows should send `ESC O` instead of `ESC [`
+    AppCursorKeys(bool),
+    /// `?2004`: the child wants pastes wrapped in bracket markers
+    BracketedPaste(bool),
+    /// Working directory reported by the shell via OSC 7
+    Cwd(String),
+    /// Window title set via OSC 0 or 2
+    Title(String),
+    /// Kitty keyboard protocol flags now in effect (0 = legacy encoding)
+    KeyboardFlags(u32),
+    /// `?2026`: the child is between begin and end of a synchronized update
+    SyncUpdate(bool),
+}
+
+/// Terminal modes tracked from a session's output.
+#[derive(Default)]
+pub struct TermModes {
+    pub alt_screen: AtomicBool,
+    pub app_cursor_keys: AtomicBool,
+    pub bracketed_paste: AtomicBool,
+    /// Inside a synchronized update. A flag, not a depth: a repeated begin
+    /// is ignored and the first end closes it.
+    pub sync_update: AtomicBool,
+    /// Last directory reported via OSC 7, if the shell reports one
+    pub cwd: Mutex<Option<String>>,
+    /// Last title set via OSC 0/2
+    pub title: Mutex<Option<String>>,
+    /// Kitty keyboard protocol flags stack; the top is in effect, empty
+    /// means legacy encoding
+    pub keyboard_flags: Mutex<Vec<u32>>,
+}
+
+impl TermModes {
+    /// Update from `seq`, reporting each mode whose value actually changed.
+    pub fn apply(&self, seq: &Sequence, mut on_change: impl FnMut(ModeChange)) {
+        if let Sequence::Osc(payload) = *seq {
+            if let Some(path) = osc7_path(payload) {
+                let mut cwd = self.cwd.lock().unwrap();
+                if cwd.as_deref() != Some(path.as_str()) {
+                    *cwd = Some(path.clone());
+                    on_change(ModeChange::Cwd(path));
+                }
+            } else if let Some(raw) = payload.strip_prefix(b"0;").or_else(|| payload.strip_prefix(b"2;")) {
+                let title = String::from_utf8_lossy(raw).into_owned();
+                let mut current = self.title.lock().unwrap();
+                if current.as_deref() != Some(title.as_str()) {
+                    *current = Some(title.clone());
+                    on_change(ModeChange::Title(title));
+                }
+            }
+            return;
+        }
+        if let Sequence::Csi {
+            private: Some(op @ (b'>' | b'<' | b'=')),
+            params: raw,
+            intermediates: [],
+            action: b'u',
+        } = *seq
+        {
+            self.apply_keyboard(op, raw, on_change);
+            return;
+        }
+        let Sequence::Csi {
+            private: Some(b'?'),
+            params: raw,
+            intermediates: [],
+            action: action @ (b'h' | b'l'),
+        } = *seq
+        else {
+            return;
+        };
+        let set = action == b'h';
+        for mode in params(raw).flatten() {
+            let (flag, change): (&AtomicBool, fn(bool) -> ModeChange) = match mode {
+                1 => (&self.app_cursor_keys, ModeChange::AppCursorKeys),
+                47 | 1047 | 1049 => (&self.alt_screen, ModeChange::AltScreen),
+                2004 => (&self.bracketed_paste, ModeChange::BracketedPaste),
+                2026 => (&self.sync_update, ModeChange::SyncUpdate),
+                _ => continue,
+            };
+            if flag.swap(set, Ordering::Relaxed) != set {
+                on_change(change(set));
+            }
+        }
+    }
+
+    /// Kitty keyboard flags in effect (0 = legacy encoding).
+    pub fn keyboard_flags(&self) -> u32 {
+        self.keyboard_flags.lock().unwrap().last().copied().unwrap_or(0)
+    }
+
+    /// Kitty keyboard protocol: `CSI > flags u` pushes, `CSI < n u` pops n
+    /// entries, `CSI = flags ; mode u` sets (1), adds (2) or removes (3)
+    /// flags on the top entry.
+    fn apply_keyboard(&self, op: u8, raw: &[u8], mut on_change: impl FnMut(ModeChange)) {
+        let mut args = params(raw);
+        let first = args.next().flatten();
+        let mut stack = self.keyboard_flags.lock().unwrap();
+        let before = stack.last().copied().unwrap_or(0);
+        match op {
+            b'>' => {
+                if stack.len() == MAX_KEYBOARD_STACK {
+                    stack.remove(0);
+                }
+                stack.push(first.unwrap_or(0));
+            }
+            b'<' => {
+                let keep = stack.len().saturating_sub(first.unwrap_or(1).max(1) as usize);
+                stack.truncate(keep);
+            }
+            _ => {
+                let flags = first.unwrap_or(0);
+                let next = match args.next().flatten().unwrap_or(1) {
+                    2 => before | flags,
+                    3 => before & !flags,
+                    _ => flags,
+                };
+                match stack.last_mut() {
+                    Some(top) => *top = next,
+                    None => stack.push(next),
+                }
+            }
+        }
+        let after = stack.last().copied().unwrap_or(0);
+        drop(stack);
+        if after != before {
+            on_change(ModeChange::KeyboardFlags(after));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Feed `chunks` one after another, collecting every mode change.
+    fn sync_changes(chunks: &[&[u8]]) -> (Vec<bool>, bool) {
+        let (mut parser, modes) = (Parser::default(), TermModes::default());
+        let mut changes = Vec::new();
+        for chunk in chunks {
+            parser.feed(chunk, |seq, _| {
+                modes.apply(&seq, |change| {
+                    if let ModeChange::SyncUpdate(on) = change {
+                        changes.push(on);
+                    }
+                })
+            });
+        }
+        (changes, modes.sync_update.load(Ordering::Relaxed))
+    }
+
+    #[test]
+    fn sync_update_toggles() {
+        assert_eq!(sync_changes(&[b"\x1b[?2026h"]), (vec![true], true));
+        assert_eq!(sync_changes(&[b"\x1b[?2026hframe\x1b[?2026l"]), (vec![true, false], false));
+    }
+
+    #[test]
+    fn sync_update_split_across_reads() {
+        let (changes, on) = sync_changes(&[b"\x1b[?20", b"26h", b"frame\x1b", b"[?2026", b"l"]);
+        assert_eq!(changes, [true, false]);
+        assert!(!on);
+    }
+}
@@ -0,0 +1,83 @@
+//! Heuristic password prompt detection for `detect_password_prompts`: the
+//! line being written (output since the last newline or carriage return)
+//! is checked for any of the configured patterns, case-insensitively.
+//! Prompts don't end in a newline, so a line that matches once a chunk of
+//! output has been read is taken to be waiting for a password.
+
+/// Longest line kept for matching; prompts are short
+const MAX_LINE: usize = 512;
+
+/// Patterns `Config::password_prompts` starts with.
+pub fn default_patterns() -> Vec<String> {
+    ["password:", "passphrase", "[sudo] password", "password for"]
+        .map(String::from)
+        .to_vec()
+}
+
+pub struct PromptWatch {
+    patterns: Vec<String>,
+    line: Vec<u8>,
+    active: bool,
+}
+
+impl PromptWatch {
+    pub fn new(patterns: &[String]) -> Self {
+        PromptWatch {
+            patterns: patterns
+                .iter()
+                .filter(|p| !p.is_empty())
+                .map(|p| p.to_lowercase())
+                .collect(),
+            line: Vec::new(),
+            active: false,
+        }
+    }
+
+    /// Watch a chunk of output. Returns whether a prompt is showing if that
+    /// changed.
+    pub fn feed(&mut self, data: &[u8]) -> Option<bool> {
+        let was_active = self.active;
+        for &b in data {
+            match b {
+                b'\n' | b'\r' => {
+                    self.line.clear();
+                    self.active = false;
+                }
+                // Other controls (and so escape sequences' ESC) don't count
+                0..0x20 | 0x7f => {}
+                _ if self.line.len() < MAX_LINE => self.line.push(b),
+                _ => {}
+            }
+        }
+        if !self.line.is_empty() {
+            let line = String::from_utf8_lossy(&self.line).to_lowercase();
+            self.active = self.patterns.iter().any(|p| line.contains(p.as_str()));
+        }
+        (self.active != was_active).then_some(self.active)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_password_prompt() {
+        let mut watch = PromptWatch::new(&default_patterns());
+        assert_eq!(watch.feed(b"Password: "), Some(true));
+        assert_eq!(watch.feed(b"\r\n"), Some(false));
+    }
+
+    #[test]
+    fn ignores_ordinary_output() {
+        let mut watch = PromptWatch::new(&default_patterns());
+        assert_eq!(watch.feed(b"total 42\r\ndrwxr-xr-x  src\r\n$ "), None);
+    }
+
+    #[test]
+    fn detects_prompt_split_across_reads() {
+        let mut watch = PromptWatch::new(&default_patterns());
+        assert_eq!(watch.feed(b"[sudo] pass"), None);
+        assert_eq!(watch.feed(b"word for me: "), Some(true));
+    }
+}
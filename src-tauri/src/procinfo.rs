@@ -0,0 +1,449 @@
+//! Best-effort inspection of the processes running inside our PTYs.
+//! Anything a platform doesn't support comes back empty rather than as an error.
+
+use std::collections::HashMap;
+#[cfg(target_os = "linux")]
+use std::collections::HashSet;
+use std::time::Duration;
+
+/// Environment the process was launched with.
+#[cfg(target_os = "linux")]
+pub fn environ(pid: u32) -> HashMap<String, String> {
+    match std::fs::read(format!("/proc/{}/environ", pid)) {
+        Ok(raw) => parse_env_block(raw.split(|&b| b == 0)),
+        Err(_) => HashMap::new(),
+    }
+}
+
+/// Environment the process was launched with.
+#[cfg(target_os = "macos")]
+pub fn environ(pid: u32) -> HashMap<String, String> {
+    let Some(raw) = procargs(pid) else { return HashMap::new() };
+    if raw.len() < 4 {
+        return HashMap::new();
+    }
+    // KERN_PROCARGS2 layout: argc, exec path, NUL padding, argv[argc], envp
+    let argc = i32::from_ne_bytes([raw[0], raw[1], raw[2], raw[3]]).max(0) as usize;
+    let fields = raw[4..]
+        .split(|&b| b == 0)
+        .filter(|f| !f.is_empty())
+        .skip(1 + argc);
+    parse_env_block(fields)
+}
+
+/// Environment the process was launched with.
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub fn environ(_pid: u32) -> HashMap<String, String> {
+    HashMap::new()
+}
+
+/// Short command name of a process (e.g. `vim`).
+#[cfg(target_os = "linux")]
+pub fn name(pid: u32) -> Option<String> {
+    let comm = std::fs::read_to_string(format!("/proc/{}/comm", pid)).ok()?;
+    Some(comm.trim_end().to_string())
+}
+
+/// Short command name of a process (e.g. `vim`).
+#[cfg(target_os = "macos")]
+pub fn name(pid: u32) -> Option<String> {
+    let mut buf = [0u8; 256];
+    // SAFETY: proc_name writes at most `buf.len()` bytes and returns the length.
+    let len = unsafe {
+        libc::proc_name(pid as libc::c_int, buf.as_mut_ptr() as *mut libc::c_void, buf.len() as u32)
+    };
+    if len <= 0 {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&buf[..len as usize]).into_owned())
+}
+
+/// Short command name of a process (e.g. `vim`).
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub fn name(_pid: u32) -> Option<String> {
+    None
+}
+
+/// Current working directory of a process.
+#[cfg(target_os = "linux")]
+pub fn cwd(pid: u32) -> Option<String> {
+    let path = std::fs::read_link(format!("/proc/{}/cwd", pid)).ok()?;
+    Some(path.to_string_lossy().into_owned())
+}
+
+/// Current working directory of a process.
+#[cfg(target_os = "macos")]
+pub fn cwd(pid: u32) -> Option<String> {
+    let mut info: libc::proc_vnodepathinfo = unsafe { std::mem::zeroed() };
+    let size = std::mem::size_of::<libc::proc_vnodepathinfo>() as libc::c_int;
+    // SAFETY: `info` is a correctly sized, writable proc_vnodepathinfo
+    let written = unsafe {
+        libc::proc_pidinfo(
+            pid as libc::c_int,
+            libc::PROC_PIDVNODEPATHINFO,
+            0,
+            &mut info as *mut _ as *mut libc::c_void,
+            size,
+        )
+    };
+    if written != size {
+        return None;
+    }
+    // vip_path is a MAXPATHLEN char buffer, declared as nested arrays
+    let path = &info.pvi_cdir.vip_path;
+    // SAFETY: the nested arrays are one contiguous buffer of this many bytes
+    let bytes = unsafe { std::slice::from_raw_parts(path.as_ptr() as *const u8, std::mem::size_of_val(path)) };
+    let path = std::ffi::CStr::from_bytes_until_nul(bytes).ok()?;
+    Some(path.to_string_lossy().into_owned())
+}
+
+/// Current working directory of a process.
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub fn cwd(_pid: u32) -> Option<String> {
+    None
+}
+
+/// CPU time and resident memory summed over a process and its descendants.
+pub struct Usage {
+    pub cpu_time: Duration,
+    pub rss_bytes: u64,
+}
+
+/// Deepest descendant level we follow when walking a process tree.
+const MAX_TREE_DEPTH: usize = 16;
+
+#[cfg(target_os = "linux")]
+pub fn tree_usage(root: u32) -> Option<Usage> {
+    let procs = linux_snapshot();
+    if !procs.iter().any(|p| p.pid == root) {
+        return None;
+    }
+    let tree = linux_subtree(root, &procs);
+    let (ticks, pages) = procs
+        .iter()
+        .filter(|p| tree.contains(&p.pid))
+        .fold((0u64, 0u64), |(t, r), p| (t + p.cpu_ticks, r + p.rss_pages));
+    // SAFETY: sysconf has no preconditions
+    let (hz, page) = unsafe { (libc::sysconf(libc::_SC_CLK_TCK), libc::sysconf(libc::_SC_PAGESIZE)) };
+    Some(Usage {
+        cpu_time: Duration::from_secs_f64(ticks as f64 / hz.max(1) as f64),
+        rss_bytes: pages * page.max(0) as u64,
+    })
+}
+
+#[cfg(target_os = "macos")]
+pub fn tree_usage(root: u32) -> Option<Usage> {
+    let mut tree = vec![root];
+    macos_descendants(root, 0, &mut tree);
+    let timebase = macos_timebase();
+    let mut usage = Usage {
+        cpu_time: Duration::ZERO,
+        rss_bytes: 0,
+    };
+    let mut found = false;
+    for pid in tree {
+        let Some(info) = macos_task_info(pid) else { continue };
+        found = true;
+        // Task times are mach ticks, which are only nanoseconds on Intel
+        let ticks = info.pti_total_user + info.pti_total_system;
+        usage.cpu_time += Duration::from_nanos(ticks * timebase.0 as u64 / timebase.1.max(1) as u64);
+        usage.rss_bytes += info.pti_resident_size;
+    }
+    found.then_some(usage)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub fn tree_usage(_root: u32) -> Option<Usage> {
+    None
+}
+
+/// A running process and its parent.
+#[derive(Clone, Copy)]
+pub struct Process {
+    pub pid: u32,
+    pub ppid: u32,
+}
+
+/// Every process we can see.
+#[cfg(target_os = "linux")]
+pub fn list() -> Vec<Process> {
+    linux_snapshot()
+        .into_iter()
+        .map(|p| Process {
+            pid: p.pid,
+            ppid: p.ppid,
+        })
+        .collect()
+}
+
+/// Every process we can see.
+#[cfg(target_os = "macos")]
+pub fn list() -> Vec<Process> {
+    let mut pids = vec![0 as libc::c_int; 4096];
+    // SAFETY: the size is passed in bytes; the return value is a pid count
+    let count = unsafe {
+        libc::proc_listallpids(
+            pids.as_mut_ptr() as *mut libc::c_void,
+            (pids.len() * std::mem::size_of::<libc::c_int>()) as libc::c_int,
+        )
+    };
+    pids.truncate(count.max(0) as usize);
+    pids.into_iter()
+        .filter(|&pid| pid > 0)
+        .filter_map(|pid| {
+            Some(Process {
+                pid: pid as u32,
+                ppid: macos_bsd_info(pid as u32)?.pbi_ppid,
+            })
+        })
+        .collect()
+}
+
+/// Every process we can see.
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub fn list() -> Vec<Process> {
+    Vec::new()
+}
+
+/// `root` and its descendants, each parent before its children, at most
+/// `MAX_TREE_DEPTH` levels down. Empty if `root` isn't visible.
+pub fn tree(root: u32) -> Vec<Process> {
+    let procs = list();
+    let Some(&first) = procs.iter().find(|p| p.pid == root) else { return Vec::new() };
+    let mut tree = vec![first];
+    let mut frontier = vec![root];
+    for _ in 0..MAX_TREE_DEPTH {
+        let next: Vec<Process> = procs
+            .iter()
+            .filter(|p| frontier.contains(&p.ppid) && !tree.iter().any(|t| t.pid == p.pid))
+            .copied()
+            .collect();
+        if next.is_empty() {
+            break;
+        }
+        frontier = next.iter().map(|p| p.pid).collect();
+        tree.extend(next);
+    }
+    tree
+}
+
+#[cfg(unix)]
+pub fn kill(pid: u32) -> Result<(), String> {
+    send_signal(pid, libc::SIGKILL)
+}
+
+/// Ask `pid` to exit (SIGTERM), giving it a chance to clean up.
+#[cfg(unix)]
+pub fn terminate(pid: u32) -> Result<(), String> {
+    send_signal(pid, libc::SIGTERM)
+}
+
+/// SIGINT every process in group `pgid`, as Ctrl-C would with ISIG on.
+#[cfg(unix)]
+pub fn interrupt_group(pgid: u32) -> Result<(), String> {
+    // SAFETY: plain syscall; a stale group just yields ESRCH
+    if unsafe { libc::killpg(pgid as libc::pid_t, libc::SIGINT) } == 0 {
+        Ok(())
+    } else {
+        Err(std::io::Error::last_os_error().to_string())
+    }
+}
+
+#[cfg(unix)]
+fn send_signal(pid: u32, signal: libc::c_int) -> Result<(), String> {
+    // SAFETY: plain syscall; a stale pid just yields ESRCH
+    if unsafe { libc::kill(pid as libc::pid_t, signal) } == 0 {
+        Ok(())
+    } else {
+        Err(std::io::Error::last_os_error().to_string())
+    }
+}
+
+#[cfg(not(unix))]
+pub fn kill(_pid: u32) -> Result<(), String> {
+    Err("Not supported on this platform".into())
+}
+
+#[cfg(not(unix))]
+pub fn terminate(_pid: u32) -> Result<(), String> {
+    Err("Not supported on this platform".into())
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn parse_env_block<'a>(entries: impl Iterator<Item = &'a [u8]>) -> HashMap<String, String> {
+    entries
+        .filter_map(|entry| {
+            let entry = String::from_utf8_lossy(entry);
+            let (key, value) = entry.split_once('=')?;
+            if key.is_empty() {
+                return None;
+            }
+            Some((key.to_string(), value.to_string()))
+        })
+        .collect()
+}
+
+#[cfg(target_os = "macos")]
+fn procargs(pid: u32) -> Option<Vec<u8>> {
+    let mut mib = [libc::CTL_KERN, libc::KERN_PROCARGS2, pid as libc::c_int];
+    let mut size: libc::size_t = 0;
+    // SAFETY: the first call only queries the size, the second writes at most
+    // `size` bytes into a buffer of exactly that length.
+    unsafe {
+        if libc::sysctl(mib.as_mut_ptr(), 3, std::ptr::null_mut(), &mut size, std::ptr::null_mut(), 0) != 0 {
+            return None;
+        }
+        let mut buf = vec![0u8; size];
+        if libc::sysctl(
+            mib.as_mut_ptr(),
+            3,
+            buf.as_mut_ptr() as *mut libc::c_void,
+            &mut size,
+            std::ptr::null_mut(),
+            0,
+        ) != 0
+        {
+            return None;
+        }
+        buf.truncate(size);
+        Some(buf)
+    }
+}
+
+#[cfg(target_os = "linux")]
+struct LinuxProc {
+    pid: u32,
+    ppid: u32,
+    cpu_ticks: u64,
+    rss_pages: u64,
+}
+
+/// Every process in `/proc` we can read.
+#[cfg(target_os = "linux")]
+fn linux_snapshot() -> Vec<LinuxProc> {
+    let Ok(entries) = std::fs::read_dir("/proc") else { return Vec::new() };
+    entries
+        .flatten()
+        .filter_map(|entry| entry.file_name().to_str()?.parse::<u32>().ok())
+        .filter_map(linux_stat)
+        .collect()
+}
+
+#[cfg(target_os = "linux")]
+fn linux_stat(pid: u32) -> Option<LinuxProc> {
+    let raw = std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    // comm may contain spaces and parens, so split after its closing paren.
+    // Fields from there on start at `state` (field 3 in proc(5)).
+    let rest = &raw[raw.rfind(')')? + 1..];
+    let fields: Vec<&str> = rest.split_whitespace().collect();
+    let field = |n: usize| fields.get(n - 3)?.parse::<u64>().ok();
+    Some(LinuxProc {
+        pid,
+        ppid: field(4)? as u32,
+        cpu_ticks: field(14)? + field(15)?,
+        rss_pages: field(24)?,
+    })
+}
+
+/// `root` plus all of its descendants.
+#[cfg(target_os = "linux")]
+fn linux_subtree(root: u32, procs: &[LinuxProc]) -> HashSet<u32> {
+    let mut tree = HashSet::from([root]);
+    let mut frontier = vec![root];
+    for _ in 0..MAX_TREE_DEPTH {
+        let next: Vec<u32> = procs
+            .iter()
+            .filter(|p| frontier.contains(&p.ppid) && tree.insert(p.pid))
+            .map(|p| p.pid)
+            .collect();
+        if next.is_empty() {
+            break;
+        }
+        frontier = next;
+    }
+    tree
+}
+
+#[cfg(target_os = "macos")]
+fn macos_children(pid: u32) -> Vec<u32> {
+    let mut buf = vec![0 as libc::pid_t; 256];
+    // SAFETY: the buffer size is passed in bytes and the return value is the
+    // number of pids written
+    let count = unsafe {
+        libc::proc_listchildpids(
+            pid as libc::pid_t,
+            buf.as_mut_ptr() as *mut libc::c_void,
+            (buf.len() * std::mem::size_of::<libc::pid_t>()) as libc::c_int,
+        )
+    };
+    buf.truncate(count.max(0) as usize);
+    buf.into_iter().filter(|&p| p > 0).map(|p| p as u32).collect()
+}
+
+#[cfg(target_os = "macos")]
+fn macos_descendants(pid: u32, depth: usize, out: &mut Vec<u32>) {
+    if depth >= MAX_TREE_DEPTH {
+        return;
+    }
+    for child in macos_children(pid) {
+        if !out.contains(&child) {
+            out.push(child);
+            macos_descendants(child, depth + 1, out);
+        }
+    }
+}
+
+#[cfg(target_os = "macos")]
+fn macos_task_info(pid: u32) -> Option<libc::proc_taskinfo> {
+    let mut info: libc::proc_taskinfo = unsafe { std::mem::zeroed() };
+    let size = std::mem::size_of::<libc::proc_taskinfo>() as libc::c_int;
+    // SAFETY: `info` is a correctly sized, writable proc_taskinfo
+    let written = unsafe {
+        libc::proc_pidinfo(
+            pid as libc::c_int,
+            libc::PROC_PIDTASKINFO,
+            0,
+            &mut info as *mut _ as *mut libc::c_void,
+            size,
+        )
+    };
+    (written == size).then_some(info)
+}
+
+#[cfg(target_os = "macos")]
+fn macos_bsd_info(pid: u32) -> Option<libc::proc_bsdinfo> {
+    let mut info: libc::proc_bsdinfo = unsafe { std::mem::zeroed() };
+    let size = std::mem::size_of::<libc::proc_bsdinfo>() as libc::c_int;
+    // SAFETY: `info` is a correctly sized, writable proc_bsdinfo
+    let written = unsafe {
+        libc::proc_pidinfo(
+            pid as libc::c_int,
+            libc::PROC_PIDTBSDINFO,
+            0,
+            &mut info as *mut _ as *mut libc::c_void,
+            size,
+        )
+    };
+    (written == size).then_some(info)
+}
+
+#[cfg(target_os = "macos")]
+#[repr(C)]
+struct MachTimebaseInfo {
+    numer: u32,
+    denom: u32,
+}
+
+#[cfg(target_os = "macos")]
+unsafe extern "C" {
+    fn mach_timebase_info(info: *mut MachTimebaseInfo) -> libc::c_int;
+}
+
+/// (numer, denom) for converting mach ticks to nanoseconds.
+#[cfg(target_os = "macos")]
+fn macos_timebase() -> (u32, u32) {
+    let mut info = MachTimebaseInfo { numer: 1, denom: 1 };
+    // SAFETY: writes into the struct we pass
+    unsafe { mach_timebase_info(&mut info) };
+    (info.numer, info.denom)
+}
@@ -0,0 +1,125 @@
+//! Raw per-session output logs for debugging, enabled by pointing
+//! `NANOPROMPT_PTY_LOG` at a directory. Each session writes `pty-<id>.log`
+//! there; full or rotated logs are renamed with a timestamp and a fresh
+//! file started.
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+const ENV_VAR: &str = "NANOPROMPT_PTY_LOG";
+
+pub struct PtyLog {
+    dir: PathBuf,
+    id: u32,
+    file: BufWriter<File>,
+    written: u64,
+    /// Rotate once the current file would grow past this (0 = never)
+    max_bytes: u64,
+}
+
+impl PtyLog {
+    /// Start logging session `id`, if `NANOPROMPT_PTY_LOG` is set.
+    pub fn from_env(id: u32, max_bytes: impl FnOnce() -> u64) -> Option<Result<Self, String>> {
+        let dir = std::env::var_os(ENV_VAR).filter(|dir| !dir.is_empty())?;
+        Some(PtyLog::open(PathBuf::from(dir), id, max_bytes()))
+    }
+
+    fn open(dir: PathBuf, id: u32, max_bytes: u64) -> Result<Self, String> {
+        std::fs::create_dir_all(&dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+        let path = current_path(&dir, id);
+        let file = File::create(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Ok(PtyLog {
+            dir,
+            id,
+            file: BufWriter::new(file),
+            written: 0,
+            max_bytes,
+        })
+    }
+
+    pub fn write(&mut self, data: &[u8]) -> Result<(), String> {
+        if self.max_bytes > 0 && self.written > 0 && self.written + data.len() as u64 > self.max_bytes {
+            self.rotate()?;
+        }
+        self.file.write_all(data).map_err(|e| e.to_string())?;
+        self.written += data.len() as u64;
+        Ok(())
+    }
+
+    /// Flush, rename the current file to `pty-<id>-<ms since epoch>.log` and
+    /// start a new one. Returns the renamed file. A second rotation in the
+    /// same millisecond gets a `-1`, `-2`, ... suffix rather than replacing
+    /// the first.
+    pub fn rotate(&mut self) -> Result<PathBuf, String> {
+        self.file.flush().map_err(|e| e.to_string())?;
+        let millis = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_millis())
+            .unwrap_or(0);
+        let mut rotated = self.dir.join(format!("pty-{}-{}.log", self.id, millis));
+        let mut n = 0;
+        while rotated.exists() {
+            n += 1;
+            rotated = self.dir.join(format!("pty-{}-{}-{}.log", self.id, millis, n));
+        }
+        let current = current_path(&self.dir, self.id);
+        std::fs::rename(&current, &rotated).map_err(|e| format!("{}: {}", current.display(), e))?;
+        let file = File::create(&current).map_err(|e| format!("{}: {}", current.display(), e))?;
+        self.file = BufWriter::new(file);
+        self.written = 0;
+        Ok(rotated)
+    }
+
+    pub fn flush(&mut self) {
+        let _ = self.file.flush();
+    }
+}
+
+fn current_path(dir: &Path, id: u32) -> PathBuf {
+    dir.join(format!("pty-{}.log", id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rotate_keeps_the_old_log_and_starts_a_fresh_one() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut log = PtyLog::open(dir.path().to_path_buf(), 7, 0).unwrap();
+        log.write(b"before\r\n").unwrap();
+        let rotated = log.rotate().unwrap();
+        log.write(b"after").unwrap();
+        log.flush();
+
+        assert_eq!(std::fs::read(&rotated).unwrap(), b"before\r\n");
+        assert_eq!(std::fs::read(dir.path().join("pty-7.log")).unwrap(), b"after");
+    }
+
+    #[test]
+    fn full_logs_rotate_before_the_write_that_overflows() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut log = PtyLog::open(dir.path().to_path_buf(), 1, 8).unwrap();
+        log.write(b"12345").unwrap();
+        log.write(b"6789").unwrap();
+        log.flush();
+
+        assert_eq!(std::fs::read(dir.path().join("pty-1.log")).unwrap(), b"6789");
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 2);
+    }
+
+    #[test]
+    fn back_to_back_rotations_keep_both_old_logs() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut log = PtyLog::open(dir.path().to_path_buf(), 3, 0).unwrap();
+        log.write(b"first").unwrap();
+        let first = log.rotate().unwrap();
+        log.write(b"second").unwrap();
+        let second = log.rotate().unwrap();
+
+        assert_ne!(first, second);
+        assert_eq!(std::fs::read(&first).unwrap(), b"first");
+        assert_eq!(std::fs::read(&second).unwrap(), b"second");
+    }
+}
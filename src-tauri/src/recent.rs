@@ -0,0 +1,139 @@
+//! Directories sessions have reported via OSC 7, most recent first, shared
+//! across sessions and kept in `recent_dirs.json` in the app data dir.
+//! Visits come from the reader threads, so saving is deferred: changes are
+//! written `SAVE_DELAY` after the first unsaved one, and on exit.
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+use tauri::{AppHandle, Manager};
+
+const MAX_RECENT: usize = 50;
+const SAVE_DELAY: Duration = Duration::from_secs(5);
+
+#[derive(Default)]
+pub struct RecentDirs {
+    /// Where the list is saved; `None` keeps it in memory only
+    path: Option<PathBuf>,
+    dirs: Mutex<Vec<String>>,
+    /// Changes not yet saved
+    pending: AtomicBool,
+}
+
+fn path(app: &AppHandle) -> Result<PathBuf, String> {
+    app.path()
+        .app_data_dir()
+        .map(|dir| dir.join("recent_dirs.json"))
+        .map_err(|e| e.to_string())
+}
+
+impl RecentDirs {
+    /// The saved list; empty if there isn't one or it can't be read.
+    pub fn load(app: &AppHandle) -> Self {
+        match path(app) {
+            Ok(path) => Self::load_from(&path),
+            Err(_) => Self::default(),
+        }
+    }
+
+    /// The list saved at `path`, which later saves go to.
+    pub fn load_from(path: &Path) -> Self {
+        let dirs = std::fs::read_to_string(path)
+            .ok()
+            .and_then(|raw| serde_json::from_str(&raw).ok())
+            .unwrap_or_default();
+        RecentDirs {
+            path: Some(path.to_path_buf()),
+            dirs: Mutex::new(dirs),
+            pending: AtomicBool::new(false),
+        }
+    }
+
+    /// Move `dir` to the front, scheduling a save if none is pending.
+    pub fn visit(&self, app: &AppHandle, dir: &str) {
+        if !self.push(dir) || self.pending.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        let app = app.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(SAVE_DELAY);
+            app.state::<RecentDirs>().flush();
+        });
+    }
+
+    /// Move `dir` to the front. Returns whether the list changed.
+    fn push(&self, dir: &str) -> bool {
+        let Ok(mut dirs) = self.dirs.lock() else { return false };
+        if dirs.first().is_some_and(|first| first == dir) {
+            return false;
+        }
+        dirs.retain(|d| d != dir);
+        dirs.insert(0, dir.to_string());
+        dirs.truncate(MAX_RECENT);
+        true
+    }
+
+    /// Save now if anything changed since the last save.
+    pub fn flush(&self) {
+        if !self.pending.swap(false, Ordering::AcqRel) {
+            return;
+        }
+        if let Err(e) = self.save() {
+            log::warn!("could not save recent directories: {}", e);
+        }
+    }
+
+    fn save(&self) -> Result<(), String> {
+        let Some(path) = &self.path else { return Ok(()) };
+        let json = {
+            let dirs = self.dirs.lock().map_err(|e| e.to_string())?;
+            serde_json::to_string(&*dirs).map_err(|e| e.to_string())?
+        };
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+        }
+        std::fs::write(path, json).map_err(|e| e.to_string())
+    }
+
+    /// Up to `limit` directories that still exist, most recent first.
+    pub fn list(&self, limit: usize) -> Vec<String> {
+        let Ok(dirs) = self.dirs.lock() else { return Vec::new() };
+        dirs.iter()
+            .filter(|dir| Path::new(dir).is_dir())
+            .take(limit)
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lists_most_recent_first_and_persists() {
+        let dir = tempfile::tempdir().unwrap();
+        let visited: Vec<String> = ["a", "b", "c"]
+            .iter()
+            .map(|name| {
+                let path = dir.path().join(name);
+                std::fs::create_dir(&path).unwrap();
+                path.to_string_lossy().into_owned()
+            })
+            .collect();
+        let file = dir.path().join("recent_dirs.json");
+        let recent = RecentDirs::load_from(&file);
+        for path in &visited {
+            assert!(recent.push(path));
+        }
+        assert!(!recent.push(&visited[2]));
+        let expected: Vec<String> = visited.iter().rev().cloned().collect();
+        assert_eq!(recent.list(10), expected);
+        assert_eq!(recent.list(2), expected[..2]);
+
+        recent.pending.store(true, Ordering::Relaxed);
+        recent.flush();
+        assert_eq!(RecentDirs::load_from(&file).list(10), expected);
+    }
+}
@@ -0,0 +1,97 @@
+//! asciicast v2 recordings of session output.
+
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+pub struct Recorder {
+    pub path: PathBuf,
+    file: BufWriter<std::fs::File>,
+    started: Instant,
+    /// Trailing bytes of a UTF-8 character split across reads
+    pending: Vec<u8>,
+    /// Started by `start_recording_all`, so `stop_recording_all` owns it
+    pub batch: bool,
+}
+
+impl Recorder {
+    /// Create (or truncate) `path` and write the header.
+    pub fn create(path: &Path, cols: u16, rows: u16, batch: bool) -> Result<Self, String> {
+        let file = std::fs::File::create(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let mut file = BufWriter::new(file);
+        let timestamp = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        let header = serde_json::json!({
+            "version": 2,
+            "width": cols,
+            "height": rows,
+            "timestamp": timestamp
+        });
+        writeln!(file, "{}", header).map_err(|e| e.to_string())?;
+        Ok(Recorder {
+            path: path.to_path_buf(),
+            file,
+            started: Instant::now(),
+            pending: Vec::new(),
+            batch,
+        })
+    }
+
+    /// Append an output event.
+    pub fn output(&mut self, data: &[u8]) -> std::io::Result<()> {
+        self.pending.extend_from_slice(data);
+        let valid = match std::str::from_utf8(&self.pending) {
+            Ok(_) => self.pending.len(),
+            // Hold back a character cut off at the end; real garbage is replaced
+            Err(e) if e.error_len().is_none() => e.valid_up_to(),
+            Err(_) => self.pending.len(),
+        };
+        if valid == 0 {
+            return Ok(());
+        }
+        let chunk: Vec<u8> = self.pending.drain(..valid).collect();
+        self.event(&chunk)
+    }
+
+    /// Write anything held back and flush.
+    pub fn finish(mut self) -> std::io::Result<()> {
+        if !self.pending.is_empty() {
+            let rest = std::mem::take(&mut self.pending);
+            self.event(&rest)?;
+        }
+        self.file.flush()
+    }
+
+    fn event(&mut self, data: &[u8]) -> std::io::Result<()> {
+        let elapsed = self.started.elapsed().as_secs_f64();
+        let event = serde_json::json!([elapsed, "o", String::from_utf8_lossy(data)]);
+        writeln!(self.file, "{}", event)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn events(path: &Path) -> Vec<serde_json::Value> {
+        let text = std::fs::read_to_string(path).unwrap();
+        text.lines().skip(1).map(|line| serde_json::from_str(line).unwrap()).collect()
+    }
+
+    #[test]
+    fn characters_split_across_reads_stay_whole() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("split.cast");
+        let mut recorder = Recorder::create(&path, 80, 24, false).unwrap();
+        let text = "a→b".as_bytes();
+        recorder.output(&text[..2]).unwrap();
+        recorder.output(&text[2..]).unwrap();
+        recorder.finish().unwrap();
+
+        let events = events(&path);
+        let output: Vec<_> = events.iter().map(|e| e[2].as_str().unwrap()).collect();
+        assert_eq!(output, ["a", "→b"]);
+    }
+}
@@ -0,0 +1,297 @@
+//! Terminal color themes. A few built-ins ship with the app; user themes
+//! are stored in config.json next to profiles.
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Theme {
+    pub name: String,
+    pub foreground: String,
+    pub background: String,
+    pub cursor: String,
+    /// black, red, green, yellow, blue, magenta, cyan, white, then the
+    /// bright variants in the same order
+    pub ansi: [String; 16],
+}
+
+impl Theme {
+    /// Every color must be `#rrggbb`.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.name.trim().is_empty() {
+            return Err("Theme name is required".into());
+        }
+        let colors = [&self.foreground, &self.background, &self.cursor]
+            .into_iter()
+            .chain(self.ansi.iter());
+        for color in colors {
+            if !is_hex_color(color) {
+                return Err(format!("Invalid color {:?} (expected #rrggbb)", color));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn is_hex_color(color: &str) -> bool {
+    color.len() == 7
+        && color.starts_with('#')
+        && color[1..].chars().all(|c| c.is_ascii_hexdigit())
+}
+
+fn theme(name: &str, foreground: &str, background: &str, cursor: &str, ansi: [&str; 16]) -> Theme {
+    Theme {
+        name: name.into(),
+        foreground: foreground.into(),
+        background: background.into(),
+        cursor: cursor.into(),
+        ansi: ansi.map(String::from),
+    }
+}
+
+/// Themes available without any configuration, matching the frontend's set.
+pub fn builtin() -> Vec<Theme> {
+    vec![
+        theme("Default", "#ffffff", "#000000", "#ffffff", [
+            "#000000", "#cd0000", "#00cd00", "#cdcd00", "#0000ee", "#cd00cd", "#00cdcd", "#e5e5e5",
+            "#666666", "#ff0000", "#00ff00", "#ffff00", "#5c5cff", "#ff00ff", "#00ffff", "#ffffff",
+        ]),
+        theme("Espresso", "#ffffff", "#323232", "#d6d6d6", [
+            "#353535", "#d25252", "#a5c261", "#ffc66d", "#6c99bb", "#d197d9", "#bed6ff", "#eeeeec",
+            "#606060", "#f00c0c", "#c2e075", "#e1e48b", "#8ab7d9", "#efb5f7", "#dcf4ff", "#ffffff",
+        ]),
+        theme("Dracula", "#f8f8f2", "#282a36", "#f8f8f2", [
+            "#21222c", "#ff5555", "#50fa7b", "#f1fa8c", "#bd93f9", "#ff79c6", "#8be9fd", "#f8f8f2",
+            "#6272a4", "#ff6e6e", "#69ff94", "#ffffa5", "#d6acff", "#ff92df", "#a4ffff", "#ffffff",
+        ]),
+        theme("Monokai Classic", "#fdfff1", "#272822", "#c0c1b5", [
+            "#272822", "#f92672", "#a6e22e", "#e6db74", "#fd971f", "#ae81ff", "#66d9ef", "#fdfff1",
+            "#6e7066", "#f92672", "#a6e22e", "#e6db74", "#fd971f", "#ae81ff", "#66d9ef", "#fdfff1",
+        ]),
+    ]
+}
+
+/// Read a color scheme from another terminal: an iTerm2 `.itermcolors`
+/// plist or a Windows Terminal scheme (bare, or the first of a settings
+/// file's `schemes`). The theme is named after the file unless the scheme
+/// carries its own name.
+pub fn import(path: &std::path::Path) -> Result<Theme, String> {
+    let raw = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let stem = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or_default();
+    let trimmed = raw.trim_start();
+    let theme = if extension.eq_ignore_ascii_case("itermcolors") || trimmed.starts_with('<') {
+        parse_itermcolors(&raw, stem)
+    } else if extension.eq_ignore_ascii_case("json") || trimmed.starts_with('{') {
+        parse_windows_terminal(&raw, stem)
+    } else {
+        Err("Unrecognized theme format (expected .itermcolors or Windows Terminal JSON)".into())
+    };
+    let theme = theme.map_err(|e| format!("{}: {}", path.display(), e))?;
+    theme.validate()?;
+    Ok(theme)
+}
+
+const ANSI_NAMES: [&str; 8] = ["black", "red", "green", "yellow", "blue", "purple", "cyan", "white"];
+
+fn parse_windows_terminal(raw: &str, fallback_name: String) -> Result<Theme, String> {
+    let value: serde_json::Value = serde_json::from_str(raw).map_err(|e| e.to_string())?;
+    let scheme = match value.get("schemes") {
+        Some(schemes) => schemes.get(0).ok_or("No color schemes in file")?,
+        None => &value,
+    };
+    let color = |key: &str| -> Result<String, String> {
+        scheme
+            .get(key)
+            .and_then(|v| v.as_str())
+            .map(|c| c.to_ascii_lowercase())
+            .ok_or_else(|| format!("Missing color {:?}", key))
+    };
+    let mut ansi: [String; 16] = Default::default();
+    for (i, name) in ANSI_NAMES.iter().enumerate() {
+        ansi[i] = color(name)?;
+        let mut bright = format!("bright{}", name);
+        bright[6..7].make_ascii_uppercase();
+        ansi[i + 8] = color(&bright)?;
+    }
+    let foreground = color("foreground")?;
+    Ok(Theme {
+        name: scheme
+            .get("name")
+            .and_then(|v| v.as_str())
+            .map(String::from)
+            .unwrap_or(fallback_name),
+        cursor: color("cursorColor").unwrap_or_else(|_| foreground.clone()),
+        background: color("background")?,
+        foreground,
+        ansi,
+    })
+}
+
+fn parse_itermcolors(raw: &str, name: String) -> Result<Theme, String> {
+    let colors = plist_colors(raw)?;
+    let color = |key: &str| -> Result<String, String> {
+        colors
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, c)| c.clone())
+            .ok_or_else(|| format!("Missing color {:?}", key))
+    };
+    let mut ansi: [String; 16] = Default::default();
+    for (i, slot) in ansi.iter_mut().enumerate() {
+        *slot = color(&format!("Ansi {} Color", i))?;
+    }
+    let foreground = color("Foreground Color")?;
+    Ok(Theme {
+        name,
+        cursor: color("Cursor Color").unwrap_or_else(|_| foreground.clone()),
+        background: color("Background Color")?,
+        foreground,
+        ansi,
+    })
+}
+
+/// The `<key>Name</key><dict>..components..</dict>` entries of an
+/// .itermcolors plist, as `#rrggbb`. Just enough XML for this one shape.
+fn plist_colors(raw: &str) -> Result<Vec<(String, String)>, String> {
+    let body = raw
+        .split_once("<dict>")
+        .map(|(_, rest)| rest)
+        .ok_or("Not a plist dictionary")?;
+    let mut colors = Vec::new();
+    let mut rest = body;
+    while let Some((key, after)) = tag(rest, "key") {
+        let Some(inner) = after.trim_start().strip_prefix("<dict>") else {
+            rest = after;
+            continue;
+        };
+        let (dict, after) = inner.split_once("</dict>").ok_or("Unterminated <dict>")?;
+        let mut rgb = [None; 3];
+        let mut fields = dict;
+        while let Some((component, after)) = tag(fields, "key") {
+            let after = after.trim_start();
+            let Some((value, after)) = after.starts_with("<real>").then(|| tag(after, "real")).flatten() else {
+                // "Color Space" and other non-numeric entries
+                fields = after;
+                continue;
+            };
+            let value: f64 = value
+                .trim()
+                .parse()
+                .map_err(|_| format!("{}: invalid component {:?}", key, value))?;
+            let slot = match component {
+                "Red Component" => Some(0),
+                "Green Component" => Some(1),
+                "Blue Component" => Some(2),
+                _ => None,
+            };
+            if let Some(slot) = slot {
+                rgb[slot] = Some((value.clamp(0.0, 1.0) * 255.0).round() as u8);
+            }
+            fields = after;
+        }
+        match rgb {
+            [Some(r), Some(g), Some(b)] => {
+                colors.push((key.to_string(), format!("#{:02x}{:02x}{:02x}", r, g, b)));
+            }
+            _ => return Err(format!("{}: incomplete color", key)),
+        }
+        rest = after;
+    }
+    Ok(colors)
+}
+
+/// Text of the next `<name>..</name>` element in `s`, and what follows it.
+fn tag<'a>(s: &'a str, name: &str) -> Option<(&'a str, &'a str)> {
+    let open = format!("<{}>", name);
+    let close = format!("</{}>", name);
+    let start = s.find(&open)? + open.len();
+    let end = start + s[start..].find(&close)?;
+    Some((&s[start..end], &s[end + close.len()..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WINDOWS_TERMINAL: &str = r##"{
+        "name": "Campbell",
+        "foreground": "#CCCCCC", "background": "#0C0C0C", "cursorColor": "#FFFFFF",
+        "black": "#0C0C0C", "red": "#C50F1F", "green": "#13A10E", "yellow": "#C19C00",
+        "blue": "#0037DA", "purple": "#881798", "cyan": "#3A96DD", "white": "#CCCCCC",
+        "brightBlack": "#767676", "brightRed": "#E74856", "brightGreen": "#16C60C",
+        "brightYellow": "#F9F1A5", "brightBlue": "#3B78FF", "brightPurple": "#B4009E",
+        "brightCyan": "#61D6D6", "brightWhite": "#F2F2F2"
+    }"##;
+
+    /// An .itermcolors plist where Ansi n is gray level n/15.
+    fn itermcolors() -> String {
+        let entry = |key: &str, level: f64| {
+            format!(
+                "<key>{}</key>\n<dict>\n<key>Color Space</key>\n<string>sRGB</string>\n\
+                 <key>Blue Component</key>\n<real>{l}</real>\n\
+                 <key>Green Component</key>\n<real>{l}</real>\n\
+                 <key>Red Component</key>\n<real>{l}</real>\n</dict>\n",
+                key,
+                l = level
+            )
+        };
+        let mut plist = String::from("<?xml version=\"1.0\"?>\n<plist version=\"1.0\">\n<dict>\n");
+        for i in 0..16 {
+            plist += &entry(&format!("Ansi {} Color", i), i as f64 / 15.0);
+        }
+        plist += &entry("Background Color", 0.0);
+        plist += &entry("Foreground Color", 1.0);
+        plist + "</dict>\n</plist>\n"
+    }
+
+    #[test]
+    fn parses_windows_terminal_scheme() {
+        let theme = parse_windows_terminal(WINDOWS_TERMINAL, "fallback".into()).unwrap();
+        assert_eq!(theme.name, "Campbell");
+        assert_eq!(theme.foreground, "#cccccc");
+        assert_eq!(theme.cursor, "#ffffff");
+        assert_eq!(theme.ansi[1], "#c50f1f");
+        assert_eq!(theme.ansi[13], "#b4009e");
+        theme.validate().unwrap();
+    }
+
+    #[test]
+    fn parses_windows_terminal_settings_file() {
+        let settings = format!(r#"{{ "profiles": {{}}, "schemes": [{}] }}"#, WINDOWS_TERMINAL);
+        let theme = parse_windows_terminal(&settings, "fallback".into()).unwrap();
+        assert_eq!(theme.background, "#0c0c0c");
+    }
+
+    #[test]
+    fn parses_itermcolors() {
+        let theme = parse_itermcolors(&itermcolors(), "Grays".into()).unwrap();
+        assert_eq!(theme.name, "Grays");
+        assert_eq!(theme.background, "#000000");
+        assert_eq!(theme.foreground, "#ffffff");
+        // No cursor color: falls back to the foreground
+        assert_eq!(theme.cursor, "#ffffff");
+        assert_eq!(theme.ansi[3], "#333333");
+        assert_eq!(theme.ansi[15], "#ffffff");
+        theme.validate().unwrap();
+    }
+
+    #[test]
+    fn import_detects_format_and_names_after_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("Grays.itermcolors");
+        std::fs::write(&path, itermcolors()).unwrap();
+        assert_eq!(import(&path).unwrap().name, "Grays");
+
+        let path = dir.path().join("scheme.json");
+        std::fs::write(&path, WINDOWS_TERMINAL).unwrap();
+        assert_eq!(import(&path).unwrap().ansi[2], "#13a10e");
+
+        let path = dir.path().join("scheme.txt");
+        std::fs::write(&path, "red = #ff0000").unwrap();
+        assert!(import(&path).is_err());
+    }
+}
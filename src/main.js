@@ -311,6 +311,25 @@ async function createTab(initialCommand) {
   });
 
   term.onData((data) => invoke("write_pty", { id, data }));
+
+  // Send pastes through paste_pty so they get sanitized. Left to xterm they
+  // would arrive raw through onData. Line endings become CR the way xterm
+  // sends them.
+  wrapper.addEventListener(
+    "paste",
+    (e) => {
+      e.preventDefault();
+      e.stopPropagation();
+      const text = e.clipboardData?.getData("text/plain");
+      if (!text) return;
+      invoke("paste_pty", {
+        id,
+        text: text.replace(/\r?\n/g, "\r"),
+        bracketed: term.modes.bracketedPasteMode,
+      });
+    },
+    true,
+  );
   term.onResize(({ rows, cols }) => invoke("resize_pty", { id, rows, cols }));
 
   sessions.set(id, { term, fitAddon, wrapper, tabEl, tabNum, title: null, exited: false });
@@ -369,11 +388,21 @@ listen("pty-output", (event) => {
 });
 
 listen("pty-exit", (event) => {
-  const id = event.payload;
+  const { id, code } = event.payload;
   const session = sessions.get(id);
   if (session) {
     session.exited = true;
-    session.term.write("\r\n\x1b[90m[Process exited]\x1b[0m\r\n");
+    const status = code ? ` with code ${code}` : "";
+    session.term.write(`\r\n\x1b[90m[Process exited${status}]\x1b[0m\r\n`);
+  }
+});
+
+listen("pty-restarted", (event) => {
+  const { id } = event.payload;
+  const session = sessions.get(id);
+  if (session) {
+    session.exited = false;
+    session.term.write("\r\n\x1b[90m[Restarted]\x1b[0m\r\n");
   }
 });
 
@@ -407,9 +436,7 @@ document.addEventListener("keydown", (e) => {
   }
   if (e.key === "Escape") {
     if (!quitOverlay.classList.contains("hidden")) {
-      quitOverlay.classList.add("hidden");
-      const session = sessions.get(activeId);
-      if (session) session.term.focus();
+      dismissQuit();
     }
     starMenu.classList.add("hidden");
   }
@@ -424,29 +451,49 @@ listen("menu-close-tab", () => {
 
 // --- Quit confirmation ---
 
-listen("confirm-quit", () => {
+// Label of the window whose close is being confirmed; null when the whole
+// app is quitting (menu quit)
+let closingLabel = null;
+
+listen("confirm-quit", (event) => {
+  closingLabel = event.payload?.label ?? null;
   let running = 0;
   for (const [, session] of sessions) {
     if (!session.exited) running++;
   }
   if (running === 0) {
-    invoke("force_quit");
+    confirmQuit();
     return;
   }
-  quitMessage.textContent =
-    `You have ${running} active session${running === 1 ? "" : "s"}. Quit nanoprompt?`;
+  const count = `${running} active session${running === 1 ? "" : "s"}`;
+  quitMessage.textContent = closingLabel === null
+    ? `You have ${count}. Quit nanoprompt?`
+    : `This window has ${count}. Close this window?`;
+  quitConfirm.textContent = closingLabel === null ? "Quit" : "Close";
   quitOverlay.classList.remove("hidden");
 });
 
-quitCancel.addEventListener("click", () => {
+function confirmQuit() {
+  quitOverlay.classList.add("hidden");
+  if (closingLabel === null) {
+    invoke("force_quit");
+  } else {
+    invoke("confirm_close", { label: closingLabel });
+  }
+}
+
+function dismissQuit() {
   quitOverlay.classList.add("hidden");
+  if (closingLabel !== null) {
+    invoke("cancel_close", { label: closingLabel });
+  }
   const session = sessions.get(activeId);
   if (session) session.term.focus();
-});
+}
 
-quitConfirm.addEventListener("click", () => {
-  invoke("force_quit");
-});
+quitCancel.addEventListener("click", dismissQuit);
+
+quitConfirm.addEventListener("click", confirmQuit);
 
 // --- Disable default context menu outside terminal ---
 